@@ -0,0 +1,275 @@
+//! The arithmetic around a 1024 bit RSA accelerator that only does the modular exponentiation,
+//! so it can be checked on the host
+
+use alloc::vec::Vec;
+use crypto_bigint::{BoxedUint, RandomMod as _, modular::BoxedMontyForm};
+use rsa::{RsaPrivateKey, rand_core::CryptoRng, traits::PublicKeyParts as _};
+use subtle::{Choice, ConditionallySelectable as _, ConstantTimeEq as _};
+
+pub const KEY_BYTES: usize = 128;
+pub const KEY_WORDS: usize = KEY_BYTES / 4;
+// PKCS#1 v1.5 needs at least 8 bytes of padding after the 0x00 0x02 header
+const MIN_PADDING: usize = 8;
+
+/// A number in the little endian words the accelerator takes
+pub type Operand = [u32; KEY_WORDS];
+
+/// Big endian bytes to the little endian words the accelerator takes
+pub fn to_operand(bytes: &[u8]) -> Operand {
+    // Keys can be stored with more precision than they need, the extra bytes are always zero
+    let bytes = &bytes[bytes.len().saturating_sub(KEY_BYTES)..];
+    let mut padded = [0u8; KEY_BYTES];
+    padded[KEY_BYTES - bytes.len()..].copy_from_slice(bytes);
+
+    let mut operand = [0u32; KEY_WORDS];
+    for (index, word) in padded.rchunks_exact(4).enumerate() {
+        operand[index] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    operand
+}
+
+/// The accelerator's little endian words back to big endian bytes
+pub fn from_operand(operand: &Operand) -> [u8; KEY_BYTES] {
+    let mut bytes = [0u8; KEY_BYTES];
+    for (index, word) in operand.iter().rev().enumerate() {
+        bytes[index * 4..index * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    bytes
+}
+
+// Only ever compares against the public modulus, so it doesn't need to be constant time
+fn less_than(a: &Operand, b: &Operand) -> bool {
+    for (a, b) in a.iter().rev().zip(b.iter().rev()) {
+        if a != b {
+            return a < b;
+        }
+    }
+    false
+}
+
+/// -modulus^-1 mod 2^32, for the accelerator's Montgomery multiplication
+pub fn m_prime(low_word: u32) -> u32 {
+    // Newton's method, each round doubles the number of correct bits
+    let mut inverse = 1u32;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u32.wrapping_sub(low_word.wrapping_mul(inverse)));
+    }
+    inverse.wrapping_neg()
+}
+
+/// R^2 mod modulus where R = 2^1024, by doubling one 2048 times
+pub fn r_squared(modulus: &Operand) -> Operand {
+    let mut value = [0u32; KEY_WORDS];
+    value[0] = 1;
+    for _ in 0..KEY_BYTES * 8 * 2 {
+        let mut carry = 0;
+        for word in value.iter_mut() {
+            let shifted = (*word << 1) | carry;
+            carry = *word >> 31;
+            *word = shifted;
+        }
+        if carry == 1 || !less_than(&value, modulus) {
+            let mut borrow = false;
+            for (word, m) in value.iter_mut().zip(modulus) {
+                let (difference, underflow) = word.overflowing_sub(*m);
+                let (difference, underflow_borrow) = difference.overflowing_sub(borrow as u32);
+                *word = difference;
+                borrow = underflow || underflow_borrow;
+            }
+        }
+    }
+    value
+}
+
+/// A ciphertext multiplied by r^e, so the exponentiation with the private exponent never sees
+/// anything an attacker chose
+pub struct Blinded {
+    pub base: Operand,
+    unblinder: BoxedUint,
+}
+
+/// Blinds `ciphertext` with a fresh random r, the same way the `rsa` crate does before its own
+/// exponentiation
+pub fn blind<R: CryptoRng + ?Sized>(
+    rng: &mut R,
+    key: &RsaPrivateKey,
+    ciphertext: &[u8],
+) -> Result<Blinded, rsa::Error> {
+    let bits = key.n_bits_precision();
+    let ciphertext =
+        BoxedUint::from_be_slice(ciphertext, bits).map_err(|_| rsa::Error::Decryption)?;
+    if &ciphertext >= key.n().as_ref() {
+        return Err(rsa::Error::Decryption);
+    }
+
+    let (r, unblinder) = loop {
+        let r = BoxedUint::random_mod(rng, key.n());
+        if let Some(inverse) = Option::<BoxedUint>::from(r.invert_mod(key.n())) {
+            break (r, inverse);
+        }
+    };
+
+    let params = key.n_params();
+    let r_pow_e = BoxedMontyForm::new(r, params.clone()).pow(key.e());
+    let base = (BoxedMontyForm::new(ciphertext, params.clone()) * r_pow_e).retrieve();
+    Ok(Blinded {
+        base: to_operand(&base.to_be_bytes()),
+        unblinder,
+    })
+}
+
+impl Blinded {
+    /// Takes r back out of the accelerator's result, leaving the padded message
+    pub fn unblind(&self, key: &RsaPrivateKey, result: &Operand) -> [u8; KEY_BYTES] {
+        let result = BoxedUint::from_be_slice(&from_operand(result), key.n_bits_precision())
+            .expect("the key is at least as wide as the accelerator");
+        let params = key.n_params();
+        let message = (BoxedMontyForm::new(result, params.clone())
+            * BoxedMontyForm::new(self.unblinder.clone(), params.clone()))
+        .retrieve();
+        from_operand(&to_operand(&message.to_be_bytes()))
+    }
+}
+
+/// Strips `0x00 0x02 <nonzero padding> 0x00` off a decrypted block. Like the `rsa` crate, it
+/// looks at every byte whether or not the padding is valid, so the timing doesn't tell a
+/// padding oracle where it went wrong
+pub fn unpad(block: &[u8; KEY_BYTES]) -> Result<Vec<u8>, rsa::Error> {
+    let first_byte_is_zero = block[0].ct_eq(&0);
+    let second_byte_is_two = block[1].ct_eq(&2);
+
+    let mut looking_for_separator = Choice::from(1);
+    let mut separator = 0u32;
+    for (index, byte) in block.iter().enumerate().skip(2) {
+        let is_zero = byte.ct_eq(&0);
+        separator.conditional_assign(&(index as u32), looking_for_separator & is_zero);
+        looking_for_separator &= !is_zero;
+    }
+
+    // The sign bit is set exactly when the separator comes after at least MIN_PADDING bytes
+    let enough_padding =
+        Choice::from((((2 + MIN_PADDING as i32 - separator as i32 - 1) >> 31) & 1) as u8);
+    let valid = first_byte_is_zero & second_byte_is_two & !looking_for_separator & enough_padding;
+    if !bool::from(valid) {
+        return Err(rsa::Error::Decryption);
+    }
+    Ok(block[separator as usize + 1..].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::{Pkcs1v15Encrypt, RsaPublicKey, traits::PrivateKeyParts as _};
+
+    use super::*;
+    use crate::encryption::software::tests::TestRng;
+
+    /// A padded block around `message`, with as much padding as fits
+    fn block(message: &[u8]) -> [u8; KEY_BYTES] {
+        let mut block = [0x5a; KEY_BYTES];
+        block[0] = 0x00;
+        block[1] = 0x02;
+        block[KEY_BYTES - message.len() - 1] = 0x00;
+        block[KEY_BYTES - message.len()..].copy_from_slice(message);
+        block
+    }
+
+    #[test]
+    fn test_operands_are_little_endian_words() {
+        let operand = to_operand(&[0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(operand[0], 0x02030405);
+        assert_eq!(operand[1], 0x01);
+        assert!(operand[2..].iter().all(|word| *word == 0));
+
+        let mut wide = [0u8; KEY_BYTES + 8];
+        wide[KEY_BYTES + 7] = 0x2a;
+        assert_eq!(to_operand(&wide)[0], 0x2a);
+
+        let bytes: Vec<u8> = (0..KEY_BYTES as u8).collect();
+        assert_eq!(from_operand(&to_operand(&bytes)), bytes.as_slice());
+    }
+
+    #[test]
+    fn test_m_prime() {
+        assert_eq!(m_prime(1), 0xffff_ffff);
+        // 3^-1 mod 2^32 is 0xaaaaaaab
+        assert_eq!(m_prime(3), 0x5555_5555);
+        for low_word in [0x8000_0001u32, 0xdead_beef, 0xffff_ffff, 0x1234_5677] {
+            assert_eq!(low_word.wrapping_mul(m_prime(low_word)), 0xffff_ffff);
+        }
+    }
+
+    #[test]
+    fn test_r_squared() {
+        // R = 1 mod 2^1024 - 1
+        let modulus = [u32::MAX; KEY_WORDS];
+        let mut one = [0u32; KEY_WORDS];
+        one[0] = 1;
+        assert_eq!(r_squared(&modulus), one);
+
+        // R = -2 mod 2^1023 + 1, so R^2 = 4
+        let mut modulus = [0u32; KEY_WORDS];
+        modulus[0] = 1;
+        modulus[KEY_WORDS - 1] = 0x8000_0000;
+        let mut four = [0u32; KEY_WORDS];
+        four[0] = 4;
+        assert_eq!(r_squared(&modulus), four);
+    }
+
+    #[test]
+    fn test_unpad() {
+        assert_eq!(unpad(&block(b"hello")).unwrap(), b"hello");
+        assert_eq!(unpad(&block(b"")).unwrap(), b"");
+        let longest = [7; KEY_BYTES - 3 - MIN_PADDING];
+        assert_eq!(unpad(&block(&longest)).unwrap(), longest);
+
+        let mut wrong_first = block(b"hello");
+        wrong_first[0] = 0x01;
+        assert!(unpad(&wrong_first).is_err());
+
+        let mut wrong_second = block(b"hello");
+        wrong_second[1] = 0x01;
+        assert!(unpad(&wrong_second).is_err());
+
+        let short_padding = block(&[7; KEY_BYTES - 3 - MIN_PADDING + 1]);
+        assert!(unpad(&short_padding).is_err());
+
+        let mut no_separator = [0x5a; KEY_BYTES];
+        no_separator[0] = 0x00;
+        no_separator[1] = 0x02;
+        assert!(unpad(&no_separator).is_err());
+    }
+
+    #[test]
+    fn test_blinded_decryption_round_trip() {
+        let mut rng = TestRng(0x2545_f491_4f6c_dd1d);
+        let key = RsaPrivateKey::new(&mut rng, KEY_BYTES * 8).unwrap();
+        let ciphertext = RsaPublicKey::from(&key)
+            .encrypt(&mut rng, Pkcs1v15Encrypt, b"shared secret!!!")
+            .unwrap();
+
+        // The Montgomery constants for a real modulus, against crypto-bigint's arithmetic
+        let modulus = to_operand(&key.n().to_be_bytes());
+        assert_eq!(modulus[0].wrapping_mul(m_prime(modulus[0])), 0xffff_ffff);
+        let two = BoxedUint::from_be_slice(&[2], key.n_bits_precision()).unwrap();
+        let exponent =
+            BoxedUint::from_be_slice(&(KEY_BYTES as u32 * 16).to_be_bytes(), 32).unwrap();
+        let expected = BoxedMontyForm::new(two, key.n_params().clone())
+            .pow(&exponent)
+            .retrieve();
+        assert_eq!(r_squared(&modulus), to_operand(&expected.to_be_bytes()));
+
+        let blinded = blind(&mut rng, &key, &ciphertext).unwrap();
+        assert_ne!(from_operand(&blinded.base), ciphertext.as_slice());
+
+        // What the accelerator computes, base^d mod n
+        let base =
+            BoxedUint::from_be_slice(&from_operand(&blinded.base), key.n_bits_precision()).unwrap();
+        let result = BoxedMontyForm::new(base, key.n_params().clone())
+            .pow(key.d())
+            .retrieve();
+        let message = blinded.unblind(&key, &to_operand(&result.to_be_bytes()));
+        assert_eq!(unpad(&message).unwrap(), b"shared secret!!!");
+
+        assert!(blind(&mut rng, &key, &key.n().to_be_bytes()).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! The connection's encryption, kept apart from the chip so it can be checked on the host
+
+use alloc::vec::Vec;
+use rsa::{
+    RsaPrivateKey,
+    rand_core::{CryptoRng, RngCore},
+};
+
+pub mod accelerator;
+pub mod software;
+pub mod stream;
+
+/// Everything the login and the encrypted connection need from the platform, so the protocol
+/// code doesn't depend on a particular chip
+// Only ever awaited on the server's single threaded executor, the futures don't need to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait CryptoBackend {
+    type Stream: CipherStream;
+
+    /// PKCS#1 v1.5 decryption with the server's private key
+    async fn rsa_decrypt(&self, key: &RsaPrivateKey, ciphertext: &[u8]) -> Result<Vec<u8>, rsa::Error>;
+
+    fn random_bytes(&self, out: &mut [u8]);
+
+    /// AES-128-CFB8 in both directions, keyed (and IV'd) with the shared secret like vanilla does
+    fn cfb8_stream(&self, shared_secret: &[u8; 16]) -> Self::Stream;
+}
+
+/// Both directions of an encrypted connection, encrypting and decrypting in place
+pub trait CipherStream {
+    fn encrypt(&mut self, data: &mut [u8]);
+    fn decrypt(&mut self, data: &mut [u8]);
+}
+
+/// Lets the `rsa` crate draw randomness from a backend
+pub struct BackendRng<'a, B>(pub &'a B);
+
+impl<'a, B: CryptoBackend> RngCore for BackendRng<'a, B> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.0.random_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.0.random_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dst: &mut [u8]) {
+        self.0.random_bytes(dst);
+    }
+}
+
+impl<'a, B: CryptoBackend> CryptoRng for BackendRng<'a, B> {}
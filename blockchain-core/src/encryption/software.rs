@@ -0,0 +1,124 @@
+use core::cell::RefCell;
+
+use alloc::vec::Vec;
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use rsa::{
+    Pkcs1v15Encrypt, RsaPrivateKey,
+    rand_core::{CryptoRng, RngCore},
+};
+
+use crate::encryption::{CryptoBackend, stream::Cfb8Stream};
+
+/// Pure software, works anywhere with a `RngCore + CryptoRng`, including a Linux host
+pub struct SoftwareBackend<R> {
+    rng: Mutex<NoopRawMutex, RefCell<R>>,
+}
+
+impl<R: RngCore + CryptoRng> SoftwareBackend<R> {
+    pub fn new(rng: R) -> Self {
+        Self {
+            rng: Mutex::new(RefCell::new(rng)),
+        }
+    }
+}
+
+impl<R: RngCore + CryptoRng> CryptoBackend for SoftwareBackend<R> {
+    type Stream = Cfb8Stream;
+
+    async fn rsa_decrypt(&self, key: &RsaPrivateKey, ciphertext: &[u8]) -> Result<Vec<u8>, rsa::Error> {
+        key.decrypt(Pkcs1v15Encrypt, ciphertext)
+    }
+
+    fn random_bytes(&self, out: &mut [u8]) {
+        self.rng.lock(|rng| rng.borrow_mut().fill_bytes(out));
+    }
+
+    fn cfb8_stream(&self, shared_secret: &[u8; 16]) -> Self::Stream {
+        Cfb8Stream::new(shared_secret)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use rsa::traits::PublicKeyParts as _;
+
+    use super::*;
+    use crate::encryption::{BackendRng, CipherStream as _};
+
+    /// Deterministic, so a failing key can be reproduced. Nowhere near good enough outside tests
+    pub struct TestRng(pub u64);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dst: &mut [u8]) {
+            for chunk in dst.chunks_mut(8) {
+                chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+            }
+        }
+    }
+
+    impl CryptoRng for TestRng {}
+
+    /// Nothing the backends await ever waits on anything
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_rsa_round_trip() {
+        let backend = SoftwareBackend::new(TestRng(0x9E37_79B9_7F4A_7C15));
+        let key = RsaPrivateKey::new(&mut BackendRng(&backend), 1024).unwrap();
+        let secret = [7u8; 16];
+
+        let ciphertext = key
+            .to_public_key()
+            .encrypt(&mut BackendRng(&backend), Pkcs1v15Encrypt, &secret)
+            .unwrap();
+        assert_eq!(ciphertext.len(), key.size());
+        assert_eq!(block_on(backend.rsa_decrypt(&key, &ciphertext)).unwrap(), secret);
+
+        let mut tampered = ciphertext.clone();
+        tampered[40] ^= 1;
+        assert!(block_on(backend.rsa_decrypt(&key, &tampered)).is_err());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let backend = SoftwareBackend::new(TestRng(1));
+        let secret = [7u8; 16];
+        let message = *b"a message that spans more than one aes block";
+
+        let mut data = message;
+        backend.cfb8_stream(&secret).encrypt(&mut data);
+        assert_ne!(data, message);
+
+        // Split unevenly, the stream has to carry its state between calls
+        let mut stream = backend.cfb8_stream(&secret);
+        let (head, tail) = data.split_at_mut(5);
+        stream.decrypt(head);
+        stream.decrypt(tail);
+        assert_eq!(data, message);
+    }
+}
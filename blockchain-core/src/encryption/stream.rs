@@ -0,0 +1,218 @@
+use aes::{
+    Aes128, Block,
+    cipher::{BlockEncrypt as _, KeyInit as _},
+};
+
+use crate::encryption::CipherStream;
+
+const BLOCK_SIZE: usize = 16;
+// Bytes shifted through the register before it has to be moved back to the start
+const WINDOW: usize = 256;
+// Decryption already knows every IV up front, so it runs this many blocks through AES at once.
+// The software AES works on several blocks in parallel and wastes the rest on a single one
+const PARALLEL_BLOCKS: usize = 8;
+
+/// CFB8 shifts one ciphertext byte into the IV per byte. Instead of shifting 16 bytes every time,
+/// ciphertext is appended to a longer buffer and the IV is the 16 bytes behind the end of it
+struct ShiftRegister {
+    buffer: [u8; BLOCK_SIZE + WINDOW],
+    position: usize,
+}
+
+impl ShiftRegister {
+    fn new(iv: &[u8; BLOCK_SIZE]) -> Self {
+        let mut buffer = [0u8; BLOCK_SIZE + WINDOW];
+        buffer[..BLOCK_SIZE].copy_from_slice(iv);
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn keystream(&self, cipher: &Aes128) -> u8 {
+        let mut block = Block::default();
+        cipher.encrypt_block_b2b(
+            Block::from_slice(&self.buffer[self.position..self.position + BLOCK_SIZE]),
+            &mut block,
+        );
+        block[0]
+    }
+
+    #[inline(always)]
+    fn push(&mut self, ciphertext: u8) {
+        if self.position == WINDOW {
+            self.compact();
+        }
+        self.buffer[self.position + BLOCK_SIZE] = ciphertext;
+        self.position += 1;
+    }
+
+    /// Pushes a run of ciphertext and fills `keystream` with the byte that decrypts each of them
+    fn push_all(&mut self, cipher: &Aes128, ciphertext: &[u8], keystream: &mut [u8]) {
+        let count = ciphertext.len();
+        debug_assert!(count <= PARALLEL_BLOCKS && keystream.len() == count);
+        if self.position + count > WINDOW {
+            self.compact();
+        }
+
+        let start = self.position + BLOCK_SIZE;
+        self.buffer[start..start + count].copy_from_slice(ciphertext);
+
+        let mut input = [Block::default(); PARALLEL_BLOCKS];
+        for (offset, block) in input[..count].iter_mut().enumerate() {
+            let iv = self.position + offset;
+            block.copy_from_slice(&self.buffer[iv..iv + BLOCK_SIZE]);
+        }
+        cipher.encrypt_blocks(&mut input[..count]);
+        for (byte, block) in keystream.iter_mut().zip(&input) {
+            *byte = block[0];
+        }
+        self.position += count;
+    }
+
+    fn compact(&mut self) {
+        self.buffer
+            .copy_within(self.position..self.position + BLOCK_SIZE, 0);
+        self.position = 0;
+    }
+}
+
+/// AES-128-CFB8 over whole slices, with one key schedule shared by both directions. Produces the
+/// same output as the `cfb8` crate
+pub struct Cfb8Stream {
+    cipher: Aes128,
+    encrypt: ShiftRegister,
+    decrypt: ShiftRegister,
+}
+
+impl Cfb8Stream {
+    /// Vanilla uses the shared secret as both the key and the IV
+    pub fn new(shared_secret: &[u8; BLOCK_SIZE]) -> Self {
+        Self {
+            cipher: Aes128::new(shared_secret.into()),
+            encrypt: ShiftRegister::new(shared_secret),
+            decrypt: ShiftRegister::new(shared_secret),
+        }
+    }
+}
+
+impl CipherStream for Cfb8Stream {
+    fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.encrypt.keystream(&self.cipher);
+            self.encrypt.push(*byte);
+        }
+    }
+
+    fn decrypt(&mut self, data: &mut [u8]) {
+        let mut keystream = [0u8; PARALLEL_BLOCKS];
+        for chunk in data.chunks_mut(PARALLEL_BLOCKS) {
+            let keystream = &mut keystream[..chunk.len()];
+            self.decrypt.push_all(&self.cipher, chunk, keystream);
+            for (byte, key) in chunk.iter_mut().zip(keystream.iter()) {
+                *byte ^= key;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use aes::cipher::{BlockDecryptMut as _, BlockEncryptMut as _, KeyIvInit as _};
+    use test::Bencher;
+
+    const SECRET: [u8; 16] = *b"0123456789abcdef";
+
+    /// What the connection used to do, one `cfb8` crate call per byte
+    struct ByteCfb8Stream {
+        encrypter: cfb8::Encryptor<Aes128>,
+        decrypter: cfb8::Decryptor<Aes128>,
+    }
+
+    impl ByteCfb8Stream {
+        fn new(secret: &[u8; 16]) -> Self {
+            Self {
+                encrypter: cfb8::Encryptor::new(secret.into(), secret.into()),
+                decrypter: cfb8::Decryptor::new(secret.into(), secret.into()),
+            }
+        }
+
+        fn encrypt(&mut self, data: &mut [u8]) {
+            for chunk in data.chunks_mut(1) {
+                self.encrypter.encrypt_block_mut(chunk.into());
+            }
+        }
+
+        fn decrypt(&mut self, data: &mut [u8]) {
+            for chunk in data.chunks_mut(1) {
+                self.decrypter.decrypt_block_mut(chunk.into());
+            }
+        }
+    }
+
+    fn sample(length: usize) -> alloc::vec::Vec<u8> {
+        (0..length).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_matches_byte_at_a_time() {
+        // Long enough to wrap the shift register a few times
+        let plaintext = sample(WINDOW * 3 + 5);
+
+        let mut expected = plaintext.clone();
+        ByteCfb8Stream::new(&SECRET).encrypt(&mut expected);
+
+        let mut fast = Cfb8Stream::new(&SECRET);
+        let mut actual = plaintext.clone();
+        for chunk in actual.chunks_mut(37) {
+            fast.encrypt(chunk);
+        }
+        assert_eq!(actual, expected);
+
+        let mut decrypted = expected.clone();
+        for chunk in decrypted.chunks_mut(101) {
+            fast.decrypt(chunk);
+        }
+        assert_eq!(decrypted, plaintext);
+
+        let mut reference = expected.clone();
+        ByteCfb8Stream::new(&SECRET).decrypt(&mut reference);
+        assert_eq!(reference, plaintext);
+    }
+
+    #[bench]
+    fn bench_encrypt_byte_at_a_time(b: &mut Bencher) {
+        let mut stream = ByteCfb8Stream::new(&SECRET);
+        let mut data = sample(4096);
+        b.bytes = data.len() as u64;
+        b.iter(|| stream.encrypt(&mut data));
+    }
+
+    #[bench]
+    fn bench_encrypt_stream(b: &mut Bencher) {
+        let mut stream = Cfb8Stream::new(&SECRET);
+        let mut data = sample(4096);
+        b.bytes = data.len() as u64;
+        b.iter(|| stream.encrypt(&mut data));
+    }
+
+    #[bench]
+    fn bench_decrypt_byte_at_a_time(b: &mut Bencher) {
+        let mut stream = ByteCfb8Stream::new(&SECRET);
+        let mut data = sample(4096);
+        b.bytes = data.len() as u64;
+        b.iter(|| stream.decrypt(&mut data));
+    }
+
+    #[bench]
+    fn bench_decrypt_stream(b: &mut Bencher) {
+        let mut stream = Cfb8Stream::new(&SECRET);
+        let mut data = sample(4096);
+        b.bytes = data.len() as u64;
+        b.iter(|| stream.decrypt(&mut data));
+    }
+}
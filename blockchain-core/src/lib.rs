@@ -0,0 +1,13 @@
+//! The world, storage formats and other logic from the server that doesn't touch the hardware.
+//! The server is built for the esp, this crate is also built for the host so its tests can run
+//! there: `cargo test` from outside the repo's `.cargo/config.toml`, see the readme
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(test, feature(test))]
+#![feature(int_roundings)]
+
+extern crate alloc;
+
+pub mod encryption;
+pub mod storage;
+pub mod transfer;
+pub mod world;
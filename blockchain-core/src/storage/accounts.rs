@@ -0,0 +1,336 @@
+use alloc::{string::String, vec::Vec};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use log::{info, warn};
+use mcproto_rs::hash::Sha1;
+
+use crate::storage::record::{RecordError, RecordStore};
+
+/// Bumped if the stored format ever changes, older records are treated like corrupt ones
+const ACCOUNTS_VERSION: u32 = 1;
+pub const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 20;
+/// PBKDF2 rounds, slow enough on the esp that a dumped flash is slow to guess through too
+const HASH_ITERATIONS: u32 = 2048;
+/// Wrong passwords allowed before a name is locked
+const MAX_FAILURES: u32 = 3;
+/// How long the first lock lasts, each further wrong password doubles it
+const LOCKOUT_SECS: u64 = 30;
+const MAX_LOCKOUT_SECS: u64 = 60 * 60;
+
+pub type Salt = [u8; SALT_LENGTH];
+pub type PasswordHash = [u8; HASH_LENGTH];
+
+struct Account {
+    username: String,
+    salt: Salt,
+    hash: PasswordHash,
+}
+
+/// Wrong passwords for a name since its last successful login, only kept in memory
+struct Failures {
+    username: String,
+    count: u32,
+    locked_until: u64,
+}
+
+#[derive(Debug)]
+pub enum AccountError<E> {
+    Record(RecordError<E>),
+    AlreadyRegistered,
+}
+
+impl<E> From<RecordError<E>> for AccountError<E> {
+    fn from(value: RecordError<E>) -> Self {
+        AccountError::Record(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginResult {
+    Success,
+    WrongPassword,
+    NotRegistered,
+    /// Too many wrong passwords, nothing is checked for this many more seconds
+    Locked(u64),
+}
+
+/// Password logins for offline mode, all accounts in one record
+pub struct AccountStore<F> {
+    store: RecordStore<F>,
+    accounts: Vec<Account>,
+    failures: Vec<Failures>,
+}
+
+/// PBKDF2-HMAC-SHA1 with a single output block. Slow on purpose, so it's done before taking
+/// the store rather than while holding it
+pub fn hash_password(password: &str, salt: &Salt) -> PasswordHash {
+    let mut key = [0u8; 64];
+    if password.len() > key.len() {
+        let mut sha = Sha1::new();
+        sha.update(password.as_bytes());
+        key[..HASH_LENGTH].copy_from_slice(&sha.finalize());
+    } else {
+        key[..password.len()].copy_from_slice(password.as_bytes());
+    }
+
+    // Every HMAC starts with the same padded key, so hash it once and clone the state
+    let mut inner = Sha1::new();
+    inner.update(&key.map(|byte| byte ^ 0x36));
+    let mut outer = Sha1::new();
+    outer.update(&key.map(|byte| byte ^ 0x5C));
+    let hmac = |message: &[&[u8]]| {
+        let mut sha = inner.clone();
+        for part in message {
+            sha.update(part);
+        }
+        let mut sha_outer = outer.clone();
+        sha_outer.update(&sha.finalize());
+        sha_outer.finalize()
+    };
+
+    let mut block = hmac(&[salt, &1u32.to_be_bytes()]);
+    let mut out = block;
+    for _ in 1..HASH_ITERATIONS {
+        block = hmac(&[&block]);
+        for (out, byte) in out.iter_mut().zip(block) {
+            *out ^= byte;
+        }
+    }
+    out
+}
+
+fn decode(data: &[u8]) -> Option<Vec<Account>> {
+    let mut accounts = Vec::new();
+    let mut rest = data;
+    while let Some((&length, tail)) = rest.split_first() {
+        let length = length as usize;
+        if tail.len() < length + SALT_LENGTH + HASH_LENGTH {
+            return None;
+        }
+        let (username, tail) = tail.split_at(length);
+        let (salt, tail) = tail.split_at(SALT_LENGTH);
+        let (hash, tail) = tail.split_at(HASH_LENGTH);
+        accounts.push(Account {
+            username: String::from_utf8(username.to_vec()).ok()?,
+            salt: salt.try_into().ok()?,
+            hash: hash.try_into().ok()?,
+        });
+        rest = tail;
+    }
+    Some(accounts)
+}
+
+fn encode(accounts: &[Account]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for account in accounts {
+        data.push(account.username.len() as u8);
+        data.extend_from_slice(account.username.as_bytes());
+        data.extend_from_slice(&account.salt);
+        data.extend_from_slice(&account.hash);
+    }
+    data
+}
+
+impl<F: NorFlash + ReadNorFlash> AccountStore<F> {
+    /// Loads the stored accounts, starting empty if there aren't any readable ones
+    pub fn load(mut store: RecordStore<F>) -> Self {
+        let accounts = match store.load() {
+            Ok((ACCOUNTS_VERSION, data)) => decode(&data).unwrap_or_else(|| {
+                warn!("stored accounts are invalid, starting without any");
+                Vec::new()
+            }),
+            Ok((version, _)) => {
+                warn!("unknown accounts version {}, starting without any", version);
+                Vec::new()
+            }
+            Err(RecordError::Missing) => Vec::new(),
+            Err(err) => {
+                warn!("failed to load accounts ({:?}), starting without any", err);
+                Vec::new()
+            }
+        };
+        info!("loaded {} accounts", accounts.len());
+
+        Self {
+            store,
+            accounts,
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn is_registered(&self, username: &str) -> bool {
+        self.accounts.iter().any(|account| account.username == username)
+    }
+
+    /// Stores a new account, `hash` being the password hashed with `salt`
+    pub fn register(&mut self, username: &str, salt: Salt, hash: PasswordHash) -> Result<(), AccountError<F::Error>> {
+        if self.is_registered(username) {
+            return Err(AccountError::AlreadyRegistered);
+        }
+
+        self.accounts.push(Account {
+            username: username.into(),
+            salt,
+            hash,
+        });
+        if let Err(err) = self.store.store(ACCOUNTS_VERSION, &encode(&self.accounts)) {
+            self.accounts.pop();
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// The salt to hash a login attempt for `username` with, or why there's no point hashing one
+    pub fn login_salt(&self, username: &str, now: u64) -> Result<Salt, LoginResult> {
+        let Some(account) = self.accounts.iter().find(|account| account.username == username) else {
+            return Err(LoginResult::NotRegistered);
+        };
+        match self.locked_for(username, now) {
+            Some(seconds) => Err(LoginResult::Locked(seconds)),
+            None => Ok(account.salt),
+        }
+    }
+
+    fn locked_for(&self, username: &str, now: u64) -> Option<u64> {
+        let failures = self.failures.iter().find(|failures| failures.username == username)?;
+        (failures.locked_until > now).then(|| failures.locked_until - now)
+    }
+
+    /// Checks a password hashed with the salt from `login_salt`, `now` being seconds from any
+    /// fixed point. Names with too many wrong passwords are locked for a while, and every wrong
+    /// password after that locks them longer
+    pub fn login(&mut self, username: &str, hash: &PasswordHash, now: u64) -> LoginResult {
+        let Some(account) = self.accounts.iter().find(|account| account.username == username) else {
+            return LoginResult::NotRegistered;
+        };
+        // Checked again, another attempt may have locked the name while this one was hashing
+        if let Some(seconds) = self.locked_for(username, now) {
+            return LoginResult::Locked(seconds);
+        }
+        let failures = self.failures.iter().position(|failures| failures.username == username);
+
+        // Compared without an early exit, so timing doesn't give away how much matched
+        let difference = hash.iter().zip(account.hash).fold(0, |acc, (a, b)| acc | (a ^ b));
+        if difference == 0 {
+            if let Some(index) = failures {
+                self.failures.swap_remove(index);
+            }
+            return LoginResult::Success;
+        }
+
+        let failures = match failures {
+            Some(index) => &mut self.failures[index],
+            None => {
+                self.failures.push(Failures {
+                    username: username.into(),
+                    count: 0,
+                    locked_until: 0,
+                });
+                self.failures.last_mut().unwrap()
+            }
+        };
+        failures.count += 1;
+        if failures.count >= MAX_FAILURES {
+            let doublings = (failures.count - MAX_FAILURES).min(16);
+            failures.locked_until = now + (LOCKOUT_SECS << doublings).min(MAX_LOCKOUT_SECS);
+        }
+        LoginResult::WrongPassword
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::record::tests::MockFlash;
+
+    const SECTOR: u32 = 4096;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| alloc::format!("{byte:02x}")).collect()
+    }
+
+    fn register<F: NorFlash + ReadNorFlash>(
+        accounts: &mut AccountStore<F>,
+        username: &str,
+        password: &str,
+        salt: Salt,
+    ) -> Result<(), AccountError<F::Error>> {
+        accounts.register(username, salt, hash_password(password, &salt))
+    }
+
+    fn login<F: NorFlash + ReadNorFlash>(
+        accounts: &mut AccountStore<F>,
+        username: &str,
+        password: &str,
+        now: u64,
+    ) -> LoginResult {
+        match accounts.login_salt(username, now) {
+            Ok(salt) => accounts.login(username, &hash_password(password, &salt), now),
+            Err(result) => result,
+        }
+    }
+
+    #[test]
+    fn test_hash_matches_pbkdf2() {
+        // python: hashlib.pbkdf2_hmac("sha1", b"hunter2", bytes(range(16)), 2048)
+        let salt: Salt = core::array::from_fn(|i| i as u8);
+        assert_eq!(hex(&hash_password("hunter2", &salt)), "f4bcb2547f30e112004af8f691e28e85eb04998d");
+        // Longer than a SHA-1 block, so the key is hashed first
+        let long = "x".repeat(70);
+        assert_eq!(hex(&hash_password(&long, &salt)), "ef470d76d510bfa05deb3056b05fdb4c6438b728");
+    }
+
+    #[test]
+    fn test_registered_accounts_survive_reload() {
+        let mut flash = MockFlash::new(2);
+        let mut accounts = AccountStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR));
+        register(&mut accounts, "alice", "hunter2", [1; SALT_LENGTH]).unwrap();
+        assert!(matches!(
+            register(&mut accounts, "alice", "other", [2; SALT_LENGTH]),
+            Err(AccountError::AlreadyRegistered)
+        ));
+        drop(accounts);
+
+        let mut accounts = AccountStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR));
+        assert!(accounts.is_registered("alice"));
+        assert!(!accounts.is_registered("bob"));
+        assert_eq!(login(&mut accounts, "alice", "hunter2", 0), LoginResult::Success);
+        assert_eq!(login(&mut accounts, "bob", "hunter2", 0), LoginResult::NotRegistered);
+    }
+
+    #[test]
+    fn test_wrong_passwords_lock_the_name() {
+        let mut flash = MockFlash::new(2);
+        let mut accounts = AccountStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR));
+        register(&mut accounts, "alice", "hunter2", [1; SALT_LENGTH]).unwrap();
+
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(login(&mut accounts, "alice", "guess", 100), LoginResult::WrongPassword);
+        }
+        // Even the right password is refused while locked
+        assert_eq!(login(&mut accounts, "alice", "hunter2", 100), LoginResult::Locked(LOCKOUT_SECS));
+
+        // Another wrong one once it's over locks it for twice as long
+        let unlocked = 100 + LOCKOUT_SECS;
+        assert_eq!(login(&mut accounts, "alice", "guess", unlocked), LoginResult::WrongPassword);
+        assert_eq!(login(&mut accounts, "alice", "hunter2", unlocked), LoginResult::Locked(LOCKOUT_SECS * 2));
+
+        assert_eq!(login(&mut accounts, "alice", "hunter2", unlocked + LOCKOUT_SECS * 2), LoginResult::Success);
+        assert_eq!(login(&mut accounts, "alice", "guess", unlocked + LOCKOUT_SECS * 2), LoginResult::WrongPassword);
+    }
+    #[test]
+    fn test_lock_is_checked_again_after_hashing() {
+        let mut flash = MockFlash::new(2);
+        let mut accounts = AccountStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR));
+        register(&mut accounts, "alice", "hunter2", [1; SALT_LENGTH]).unwrap();
+
+        // Wrong guesses from elsewhere lock the name while the right password is being hashed
+        let hash = hash_password("hunter2", &accounts.login_salt("alice", 0).unwrap());
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(login(&mut accounts, "alice", "guess", 0), LoginResult::WrongPassword);
+        }
+        assert_eq!(accounts.login("alice", &hash, 0), LoginResult::Locked(LOCKOUT_SECS));
+        assert_eq!(accounts.login_salt("alice", 0), Err(LoginResult::Locked(LOCKOUT_SECS)));
+        assert_eq!(accounts.login_salt("bob", 0), Err(LoginResult::NotRegistered));
+    }
+}
@@ -0,0 +1,444 @@
+use alloc::{string::String, vec::Vec};
+use core::net::SocketAddrV4;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use log::{info, warn};
+use mcproto_rs::auth::is_valid_username;
+
+use crate::storage::record::{RecordError, RecordStore};
+
+/// Bumped whenever the stored format changes, `migrate` has to keep reading every older one
+const CONFIG_VERSION: u32 = 2;
+/// Vanilla's default port
+pub const DEFAULT_PORT: u16 = 25565;
+const MAX_SSID_LENGTH: usize = 32;
+const MAX_MOTD_LENGTH: usize = 256;
+const MAX_DISCOVERY_LENGTH: usize = 64;
+pub const MAX_PLAYERS_LIMIT: u32 = 1000;
+/// Leaves room for everything else in the record's sector
+pub const MAX_FAVICON_LENGTH: usize = 3 * 1024;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const MAX_ADMINS: usize = 8;
+/// For the rcon password and the forwarding and transfer secrets
+const MAX_SECRET_LENGTH: usize = 64;
+
+/// Which proxy, if any, players have to join through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardingMode {
+    /// Modern forwarding, signed with `forwarding_secret`
+    Velocity,
+    /// Legacy forwarding through the handshake, only safe if nothing but the proxy can reach us
+    BungeeCord,
+}
+
+/// Everything that used to need a reflash to change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub ssid: String,
+    /// Empty for open networks
+    pub password: String,
+    pub motd: String,
+    pub max_players: u32,
+    pub port: u16,
+    /// A 64x64 PNG, `None` uses the one built in
+    pub favicon: Option<Vec<u8>>,
+    /// What LAN clients list the server as
+    pub discovery_motd: String,
+    /// Packets at least this long are compressed, negative turns compression off
+    pub compression_threshold: i32,
+    /// Usernames allowed to run admin commands
+    pub admins: Vec<String>,
+    /// Empty turns rcon off
+    pub rcon_password: String,
+    /// `ip:port` of a plain HTTP session server to verify logins with, empty for offline mode
+    pub session_server: String,
+    pub forwarding: Option<ForwardingMode>,
+    /// The secret from Velocity's `forwarding.secret` file
+    pub forwarding_secret: String,
+    /// Servers with the same secret take each other's word on who's logged in, empty turns it off
+    pub transfer_secret: String,
+    /// Every connection starts with a PROXY header from a load balancer
+    pub proxy_protocol: bool,
+    /// Wipes the world on the next boot, then turns itself off
+    pub reset_world: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ssid: String::new(),
+            password: String::new(),
+            motd: "blockchain - instead of bitcoin, a block game for your key chain!".into(),
+            max_players: 10,
+            port: DEFAULT_PORT,
+            favicon: None,
+            discovery_motd: "block-chain - a block game on your keychain!".into(),
+            // Smaller than this isn't worth the cpu time, same as vanilla
+            compression_threshold: 256,
+            admins: Vec::new(),
+            rcon_password: String::new(),
+            session_server: String::new(),
+            forwarding: None,
+            forwarding_secret: String::new(),
+            transfer_secret: String::new(),
+            proxy_protocol: false,
+            reset_world: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn is_admin(&self, username: &str) -> bool {
+        self.admins.iter().any(|admin| admin == username)
+    }
+
+    /// What's wrong with this config, if anything
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.ssid.len() > MAX_SSID_LENGTH {
+            return Err("ssid is longer than 32 bytes");
+        }
+        if !self.password.is_empty() && !(8..=63).contains(&self.password.len()) {
+            return Err("wifi passwords are 8 to 63 characters");
+        }
+        if self.motd.len() > MAX_MOTD_LENGTH {
+            return Err("motd is longer than 256 bytes");
+        }
+        if !(1..=MAX_PLAYERS_LIMIT).contains(&self.max_players) {
+            return Err("max players has to be 1 to 1000");
+        }
+        if self.port == 0 {
+            return Err("port can't be 0");
+        }
+        if let Some(favicon) = &self.favicon {
+            if favicon.len() > MAX_FAVICON_LENGTH {
+                return Err("favicon is larger than 3 KiB");
+            }
+            if !favicon.starts_with(PNG_SIGNATURE) {
+                return Err("favicon isn't a PNG");
+            }
+        }
+        if self.discovery_motd.len() > MAX_DISCOVERY_LENGTH {
+            return Err("discovery motd is longer than 64 bytes");
+        }
+        if self.admins.len() > MAX_ADMINS {
+            return Err("there can be at most 8 admins");
+        }
+        if !self.admins.iter().all(|admin| is_valid_username(admin)) {
+            return Err("admins have to be valid usernames");
+        }
+        if [&self.rcon_password, &self.forwarding_secret, &self.transfer_secret]
+            .iter()
+            .any(|secret| secret.len() > MAX_SECRET_LENGTH)
+        {
+            return Err("passwords and secrets are at most 64 bytes");
+        }
+        if !self.session_server.is_empty() && self.session_server.parse::<SocketAddrV4>().is_err() {
+            return Err("session server has to be an ip:port");
+        }
+        if self.forwarding == Some(ForwardingMode::Velocity) && self.forwarding_secret.is_empty() {
+            return Err("velocity forwarding needs a forwarding secret");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError<E> {
+    Record(RecordError<E>),
+    Invalid(&'static str),
+}
+
+impl<E> From<RecordError<E>> for ConfigError<E> {
+    fn from(value: RecordError<E>) -> Self {
+        ConfigError::Record(value)
+    }
+}
+
+fn put(data: &mut Vec<u8>, field: &[u8]) {
+    data.extend_from_slice(&(field.len() as u16).to_le_bytes());
+    data.extend_from_slice(field);
+}
+
+fn take<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (length, tail) = rest.split_first_chunk::<2>()?;
+    let length = u16::from_le_bytes(*length) as usize;
+    if tail.len() < length {
+        return None;
+    }
+    let (field, tail) = tail.split_at(length);
+    *rest = tail;
+    Some(field)
+}
+
+fn take_string(rest: &mut &[u8]) -> Option<String> {
+    String::from_utf8(take(rest)?.to_vec()).ok()
+}
+
+fn take_array<const N: usize>(rest: &mut &[u8]) -> Option<[u8; N]> {
+    take(rest)?.try_into().ok()
+}
+
+fn encode(config: &ServerConfig) -> Vec<u8> {
+    let mut data = Vec::new();
+    put(&mut data, config.ssid.as_bytes());
+    put(&mut data, config.password.as_bytes());
+    put(&mut data, config.motd.as_bytes());
+    put(&mut data, &config.max_players.to_le_bytes());
+    put(&mut data, &config.port.to_le_bytes());
+    put(&mut data, config.favicon.as_deref().unwrap_or_default());
+    put(&mut data, config.discovery_motd.as_bytes());
+    put(&mut data, &config.compression_threshold.to_le_bytes());
+    put(&mut data, config.admins.join(",").as_bytes());
+    put(&mut data, config.rcon_password.as_bytes());
+    put(&mut data, config.session_server.as_bytes());
+    let forwarding = match config.forwarding {
+        None => 0,
+        Some(ForwardingMode::Velocity) => 1,
+        Some(ForwardingMode::BungeeCord) => 2,
+    };
+    put(&mut data, &[forwarding]);
+    put(&mut data, config.forwarding_secret.as_bytes());
+    put(&mut data, config.transfer_secret.as_bytes());
+    put(&mut data, &[config.proxy_protocol as u8, config.reset_world as u8]);
+    data
+}
+
+fn decode_v1(rest: &mut &[u8], defaults: &ServerConfig) -> Option<ServerConfig> {
+    Some(ServerConfig {
+        ssid: take_string(rest)?,
+        password: take_string(rest)?,
+        motd: take_string(rest)?,
+        max_players: u32::from_le_bytes(take_array(rest)?),
+        port: u16::from_le_bytes(take_array(rest)?),
+        favicon: Some(take(rest)?.to_vec()).filter(|favicon| !favicon.is_empty()),
+        discovery_motd: take_string(rest)?,
+        compression_threshold: i32::from_le_bytes(take_array(rest)?),
+        ..defaults.clone()
+    })
+}
+
+/// v1 and everything that used to be set when building
+fn decode_v2(rest: &mut &[u8], defaults: &ServerConfig) -> Option<ServerConfig> {
+    let v1 = decode_v1(rest, defaults)?;
+    let admins = take_string(rest)?;
+    let rcon_password = take_string(rest)?;
+    let session_server = take_string(rest)?;
+    let forwarding = match take_array(rest)? {
+        [0] => None,
+        [1] => Some(ForwardingMode::Velocity),
+        [2] => Some(ForwardingMode::BungeeCord),
+        _ => return None,
+    };
+    let forwarding_secret = take_string(rest)?;
+    let transfer_secret = take_string(rest)?;
+    let [proxy_protocol, reset_world] = take_array(rest)?;
+    Some(ServerConfig {
+        admins: admins.split(',').filter(|admin| !admin.is_empty()).map(Into::into).collect(),
+        rcon_password,
+        session_server,
+        forwarding,
+        forwarding_secret,
+        transfer_secret,
+        proxy_protocol: proxy_protocol != 0,
+        reset_world: reset_world != 0,
+        ..v1
+    })
+}
+
+/// Reads a config stored by any version of the firmware. Newer formats are read by their own
+/// decoder, older ones by theirs with the fields they didn't have left at `defaults`, the
+/// build's settings
+fn migrate(version: u32, mut data: &[u8], defaults: &ServerConfig) -> Option<ServerConfig> {
+    let rest = &mut data;
+    let config = match version {
+        1 => decode_v1(rest, defaults),
+        2 => decode_v2(rest, defaults),
+        _ => None,
+    }?;
+    rest.is_empty().then_some(config)
+}
+
+/// The server's config, kept in its own record
+pub struct ConfigStore<F> {
+    store: RecordStore<F>,
+    config: ServerConfig,
+}
+
+impl<F: NorFlash + ReadNorFlash> ConfigStore<F> {
+    /// Loads the stored config. A device without one starts from `defaults` (the build's
+    /// settings) and keeps them, anything unreadable falls back to those without overwriting
+    /// what's there
+    pub fn load(mut store: RecordStore<F>, defaults: ServerConfig) -> Self {
+        let config = match store.load() {
+            Ok((version, data)) => match migrate(version, &data, &defaults) {
+                Some(config) if config.validate().is_ok() => {
+                    info!("loaded config version {}", version);
+                    Some(config)
+                }
+                Some(_) => {
+                    warn!("stored config is invalid, using the defaults");
+                    None
+                }
+                None => {
+                    warn!("can't read config version {}, using the defaults", version);
+                    None
+                }
+            },
+            Err(RecordError::Missing) => {
+                info!("no config stored, saving the defaults");
+                if let Err(err) = store.store(CONFIG_VERSION, &encode(&defaults)) {
+                    warn!("failed to store config: {:?}", err);
+                }
+                None
+            }
+            Err(err) => {
+                warn!("failed to load config ({:?}), using the defaults", err);
+                None
+            }
+        };
+
+        Self {
+            store,
+            config: config.unwrap_or(defaults),
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Changes the config, only keeping the change if it's valid and was stored
+    pub fn update(&mut self, change: impl FnOnce(&mut ServerConfig)) -> Result<(), ConfigError<F::Error>> {
+        let mut config = self.config.clone();
+        change(&mut config);
+        config.validate().map_err(ConfigError::Invalid)?;
+        self.store.store(CONFIG_VERSION, &encode(&config))?;
+        self.config = config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::record::tests::MockFlash;
+
+    const SECTOR: u32 = 4096;
+
+    #[test]
+    fn defaults_are_stored_on_first_boot() {
+        let mut flash = MockFlash::new(2);
+        let config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        assert_eq!(config.config(), &ServerConfig::default());
+        drop(config);
+
+        let (version, data) = RecordStore::new(&mut flash, [0, SECTOR], SECTOR).load().unwrap();
+        assert_eq!(migrate(version, &data, &ServerConfig::default()), Some(ServerConfig::default()));
+    }
+
+    #[test]
+    fn changes_survive_reload() {
+        let mut flash = MockFlash::new(2);
+        let mut config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        config
+            .update(|config| {
+                config.motd = "hello".into();
+                config.favicon = Some([PNG_SIGNATURE, &[0; MAX_FAVICON_LENGTH - 8]].concat());
+            })
+            .unwrap();
+        assert!(matches!(
+            config.update(|config| config.max_players = 0),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert_eq!(config.config().max_players, 10);
+        drop(config);
+
+        let config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        assert_eq!(config.config().motd, "hello");
+        assert_eq!(config.config().favicon.as_ref().map(Vec::len), Some(MAX_FAVICON_LENGTH));
+    }
+
+    #[test]
+    fn unreadable_configs_are_left_alone() {
+        let mut flash = MockFlash::new(2);
+        RecordStore::new(&mut flash, [0, SECTOR], SECTOR).store(CONFIG_VERSION + 1, b"from the future").unwrap();
+        let config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        assert_eq!(config.config(), &ServerConfig::default());
+        drop(config);
+
+        // A downgrade mustn't lose what the newer firmware stored
+        let (version, data) = RecordStore::new(&mut flash, [0, SECTOR], SECTOR).load().unwrap();
+        assert_eq!((version, data.as_slice()), (CONFIG_VERSION + 1, &b"from the future"[..]));
+    }
+
+    #[test]
+    fn v1_configs_take_the_build_settings() {
+        let mut flash = MockFlash::new(2);
+        let old = ServerConfig {
+            motd: "from v1".into(),
+            ..ServerConfig::default()
+        };
+        let mut v1 = Vec::new();
+        put(&mut v1, b"network");
+        put(&mut v1, b"");
+        put(&mut v1, old.motd.as_bytes());
+        put(&mut v1, &old.max_players.to_le_bytes());
+        put(&mut v1, &old.port.to_le_bytes());
+        put(&mut v1, b"");
+        put(&mut v1, old.discovery_motd.as_bytes());
+        put(&mut v1, &old.compression_threshold.to_le_bytes());
+        RecordStore::new(&mut flash, [0, SECTOR], SECTOR).store(1, &v1).unwrap();
+
+        let build = ServerConfig {
+            admins: alloc::vec!["alice".into()],
+            rcon_password: "hunter22".into(),
+            ..ServerConfig::default()
+        };
+        let mut config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), build.clone());
+        assert_eq!(config.config().ssid, "network");
+        assert_eq!(config.config().motd, "from v1");
+        assert!(config.config().is_admin("alice"));
+        assert_eq!(config.config().rcon_password, "hunter22");
+
+        // Stored as v2 from the next change on
+        config.update(|config| config.proxy_protocol = true).unwrap();
+        drop(config);
+        let (version, _) = RecordStore::new(&mut flash, [0, SECTOR], SECTOR).load().unwrap();
+        assert_eq!(version, CONFIG_VERSION);
+        let config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        assert!(config.config().proxy_protocol);
+        assert_eq!(config.config().admins, ["alice"]);
+    }
+
+    #[test]
+    fn runtime_settings_are_validated() {
+        let mut flash = MockFlash::new(2);
+        let mut config = ConfigStore::load(RecordStore::new(&mut flash, [0, SECTOR], SECTOR), ServerConfig::default());
+        let invalid: [fn(&mut ServerConfig); 5] = [
+            |config| config.admins = alloc::vec!["no spaces".into()],
+            |config| config.session_server = "example.com:80".into(),
+            |config| config.forwarding = Some(ForwardingMode::Velocity),
+            |config| config.transfer_secret = "x".repeat(MAX_SECRET_LENGTH + 1),
+            |config| config.admins = alloc::vec!["admin".into(); MAX_ADMINS + 1],
+        ];
+        for change in invalid {
+            assert!(matches!(config.update(change), Err(ConfigError::Invalid(_))));
+        }
+
+        // Everything at its longest still fits in the record
+        config
+            .update(|config| {
+                config.ssid = "s".repeat(MAX_SSID_LENGTH);
+                config.password = "p".repeat(63);
+                config.motd = "m".repeat(MAX_MOTD_LENGTH);
+                config.favicon = Some([PNG_SIGNATURE, &[0; MAX_FAVICON_LENGTH - 8]].concat());
+                config.discovery_motd = "d".repeat(MAX_DISCOVERY_LENGTH);
+                config.admins = alloc::vec!["a".repeat(16); MAX_ADMINS];
+                config.rcon_password = "r".repeat(MAX_SECRET_LENGTH);
+                config.session_server = "255.255.255.255:65535".into();
+                config.forwarding = Some(ForwardingMode::Velocity);
+                config.forwarding_secret = "f".repeat(MAX_SECRET_LENGTH);
+                config.transfer_secret = "t".repeat(MAX_SECRET_LENGTH);
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use log::{info, warn};
+
+use crate::storage::record::{RecordError, RecordStore};
+
+/// Bumped if the stored format ever changes, older records are treated like corrupt ones
+const SERVER_KEY_VERSION: u32 = 1;
+
+/// Saves an encoded key, the server stores its RSA key as PKCS#8 DER
+pub fn store_key<F: NorFlash + ReadNorFlash>(
+    store: &mut RecordStore<F>,
+    encoded: &[u8],
+) -> Result<(), RecordError<F::Error>> {
+    store.store(SERVER_KEY_VERSION, encoded)
+}
+
+/// Loads the stored key, or generates and stores a new one if there isn't a usable one
+pub fn load_key<F: NorFlash + ReadNorFlash, K, E: Debug>(
+    store: &mut RecordStore<F>,
+    decode: impl FnOnce(&[u8]) -> Result<K, E>,
+    encode: impl FnOnce(&K) -> Vec<u8>,
+    generate: impl FnOnce() -> K,
+) -> K {
+    match store.load() {
+        Ok((SERVER_KEY_VERSION, encoded)) => match decode(&encoded) {
+            Ok(key) => {
+                info!("loaded server key");
+                return key;
+            }
+            Err(err) => warn!("stored server key is invalid ({:?}), generating a new one", err),
+        },
+        Ok((version, _)) => warn!("unknown server key version {}, generating a new one", version),
+        Err(RecordError::Missing) => info!("no server key stored, generating one"),
+        Err(err) => warn!("failed to load server key ({:?}), generating a new one", err),
+    }
+
+    let key = generate();
+    if let Err(err) = store_key(store, &encode(&key)) {
+        // Still usable for this boot, we'll just get a different one next time
+        warn!("failed to store server key: {:?}", err);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::record::tests::MockFlash;
+
+    const SECTOR: u32 = 4096;
+
+    // Stands in for an RSA key, anything with an encoding that can be rejected
+    fn load(flash: &mut MockFlash, seed: u64) -> u64 {
+        load_key(
+            &mut RecordStore::new(flash, [0, SECTOR], SECTOR),
+            |encoded| encoded.try_into().map(u64::from_le_bytes),
+            |key| key.to_le_bytes().to_vec(),
+            || seed,
+        )
+    }
+
+    #[test]
+    fn test_key_survives_reboot() {
+        let mut flash = MockFlash::new(2);
+        let first = load(&mut flash, 1);
+        let second = load(&mut flash, 2);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_corrupt_key_is_replaced() {
+        let mut flash = MockFlash::new(2);
+        let first = load(&mut flash, 1);
+        flash.data[22] ^= 0xFF;
+
+        let second = load(&mut flash, 2);
+        assert_ne!(first, second);
+
+        // The replacement is stored, not regenerated every boot
+        let third = load(&mut flash, 3);
+        assert_eq!(second, third);
+    }
+
+    #[test]
+    fn test_rotated_key_is_loaded() {
+        let mut flash = MockFlash::new(2);
+        load(&mut flash, 1);
+
+        store_key(&mut RecordStore::new(&mut flash, [0, SECTOR], SECTOR), &2u64.to_le_bytes()).unwrap();
+
+        assert_eq!(load(&mut flash, 3), 2);
+    }
+}
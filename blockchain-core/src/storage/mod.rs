@@ -0,0 +1,4 @@
+pub mod accounts;
+pub mod config;
+pub mod key;
+pub mod record;
@@ -0,0 +1,361 @@
+use alloc::vec::Vec;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+// "BCR2", marks the start of a record. Erased flash reads as 0xFF so an empty slot never matches
+const RECORD_MAGIC: u32 = 0x4243_5232;
+// magic, sequence, version, length and crc, all u32
+const HEADER_SIZE: usize = 20;
+// "BCRD", records from before there were two slots: magic, version, length and crc of the data
+const LEGACY_MAGIC: u32 = 0x4243_5244;
+const LEGACY_HEADER_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum RecordError<E> {
+    Flash(E),
+    /// Nothing has been stored yet
+    Missing,
+    /// A record was started but its contents don't match the checksum
+    Corrupt,
+    TooLarge,
+}
+
+impl<E> From<E> for RecordError<E> {
+    fn from(value: E) -> Self {
+        RecordError::Flash(value)
+    }
+}
+
+/// A single versioned blob with a checksum, stored at a fixed place in a partition. Each write
+/// goes to whichever of the two slots doesn't hold the newest copy, so losing power part way
+/// through leaves the previous copy to load. Writing erases the slot first, so `capacity` has to
+/// be a multiple of the erase size
+pub struct RecordStore<F> {
+    flash: F,
+    slots: [u32; 2],
+    capacity: u32,
+}
+
+/// A copy found in one of the slots
+struct Slot {
+    sequence: u32,
+    version: u32,
+    data: Vec<u8>,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0xFFFF_FFFF, data) ^ 0xFFFF_FFFF
+}
+
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    crc
+}
+
+// Covers the header too, so a half written header can't pass for a record
+fn record_crc(sequence: u32, version: u32, data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for field in [sequence, version, data.len() as u32] {
+        crc = crc32_update(crc, &field.to_le_bytes());
+    }
+    crc32_update(crc, data) ^ 0xFFFF_FFFF
+}
+
+impl<F: NorFlash + ReadNorFlash> RecordStore<F> {
+    /// `slots` are the offsets of the two copies, each `capacity` long
+    pub fn new(flash: F, slots: [u32; 2], capacity: u32) -> Self {
+        assert!(
+            (capacity as usize).is_multiple_of(F::ERASE_SIZE)
+                && slots.iter().all(|offset| (*offset as usize).is_multiple_of(F::ERASE_SIZE)),
+            "records must cover whole erase sectors"
+        );
+        Self {
+            flash,
+            slots,
+            capacity,
+        }
+    }
+
+    fn read_data(&mut self, offset: u32, length: u32) -> Result<Vec<u8>, RecordError<F::Error>> {
+        let mut data = alloc::vec![0u8; (length as usize).next_multiple_of(F::READ_SIZE)];
+        self.flash.read(offset, &mut data)?;
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    /// The copy in a slot, `Missing` for an empty one and `Corrupt` for one that was never
+    /// finished
+    fn read_slot(&mut self, offset: u32) -> Result<Slot, RecordError<F::Error>> {
+        let mut header = [0u8; HEADER_SIZE];
+        self.flash.read(offset, &mut header)?;
+        let field = |index: usize| {
+            u32::from_le_bytes(header[index * 4..index * 4 + 4].try_into().unwrap())
+        };
+
+        match field(0) {
+            RECORD_MAGIC => {
+                let (sequence, version, length, crc) = (field(1), field(2), field(3), field(4));
+                if length as usize > self.capacity as usize - HEADER_SIZE {
+                    return Err(RecordError::Corrupt);
+                }
+                let data = self.read_data(offset + HEADER_SIZE as u32, length)?;
+                if record_crc(sequence, version, &data) != crc {
+                    return Err(RecordError::Corrupt);
+                }
+                Ok(Slot { sequence, version, data })
+            }
+            // Older than anything written since, so it loses to any copy in the other slot
+            LEGACY_MAGIC => {
+                let (version, length, crc) = (field(1), field(2), field(3));
+                if length as usize > self.capacity as usize - LEGACY_HEADER_SIZE {
+                    return Err(RecordError::Corrupt);
+                }
+                let data = self.read_data(offset + LEGACY_HEADER_SIZE as u32, length)?;
+                if crc32(&data) != crc {
+                    return Err(RecordError::Corrupt);
+                }
+                Ok(Slot { sequence: 0, version, data })
+            }
+            _ => Err(RecordError::Missing),
+        }
+    }
+
+    /// The index of the slot with the newest copy and the copy itself
+    fn newest(&mut self) -> Result<(usize, Slot), RecordError<F::Error>> {
+        let first = self.read_slot(self.slots[0]);
+        let second = self.read_slot(self.slots[1]);
+        match (first, second) {
+            (Ok(first), Ok(second)) if second.sequence > first.sequence => Ok((1, second)),
+            (Ok(first), _) => Ok((0, first)),
+            (_, Ok(second)) => Ok((1, second)),
+            (Err(RecordError::Flash(err)), _) | (_, Err(RecordError::Flash(err))) => Err(RecordError::Flash(err)),
+            (Err(RecordError::Corrupt), _) | (_, Err(RecordError::Corrupt)) => Err(RecordError::Corrupt),
+            (Err(err), _) => Err(err),
+        }
+    }
+
+    /// The stored version and data
+    pub fn load(&mut self) -> Result<(u32, Vec<u8>), RecordError<F::Error>> {
+        let (_, newest) = self.newest()?;
+        Ok((newest.version, newest.data))
+    }
+
+    pub fn store(&mut self, version: u32, data: &[u8]) -> Result<(), RecordError<F::Error>> {
+        if data.len() > self.capacity as usize - HEADER_SIZE {
+            return Err(RecordError::TooLarge);
+        }
+
+        // Never over the newest copy, it's what's left if this write doesn't finish
+        let (slot, sequence) = match self.newest() {
+            // Flash wears out long before the sequence could wrap
+            Ok((index, newest)) => (1 - index, newest.sequence.wrapping_add(1)),
+            Err(RecordError::Flash(err)) => return Err(RecordError::Flash(err)),
+            Err(_) => (0, 1),
+        };
+
+        let length = (HEADER_SIZE + data.len()).next_multiple_of(F::WRITE_SIZE);
+        let mut raw = Vec::with_capacity(length);
+        raw.extend_from_slice(&RECORD_MAGIC.to_le_bytes());
+        raw.extend_from_slice(&sequence.to_le_bytes());
+        raw.extend_from_slice(&version.to_le_bytes());
+        raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&record_crc(sequence, version, data).to_le_bytes());
+        raw.extend_from_slice(data);
+        raw.resize(length, 0xFF);
+
+        let offset = self.slots[slot];
+        self.flash.erase(offset, offset + self.capacity)?;
+        self.flash.write(offset, &raw)?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<(), RecordError<F::Error>> {
+        for offset in self.slots {
+            self.flash.erase(offset, offset + self.capacity)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+
+    const SECTOR: usize = 4096;
+
+    #[derive(Debug)]
+    pub struct MockFlashError(NorFlashErrorKind);
+
+    impl NorFlashError for MockFlashError {
+        fn kind(&self) -> NorFlashErrorKind {
+            self.0
+        }
+    }
+
+    /// Behaves like NOR flash: erasing sets bytes to 0xFF and writing can only clear bits
+    pub struct MockFlash {
+        pub data: Vec<u8>,
+        /// Bytes written before the power goes out, every write after that fails
+        pub power_cut_after: Option<usize>,
+    }
+
+    impl MockFlash {
+        pub fn new(sectors: usize) -> Self {
+            Self {
+                data: alloc::vec![0xFF; sectors * SECTOR],
+                power_cut_after: None,
+            }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = MockFlashError;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::READ_SIZE) || !bytes.len().is_multiple_of(Self::READ_SIZE) {
+                return Err(MockFlashError(NorFlashErrorKind::NotAligned));
+            }
+            let source = self
+                .data
+                .get(offset..offset + bytes.len())
+                .ok_or(MockFlashError(NorFlashErrorKind::OutOfBounds))?;
+            bytes.copy_from_slice(source);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(SECTOR) || !to.is_multiple_of(SECTOR) {
+                return Err(MockFlashError(NorFlashErrorKind::NotAligned));
+            }
+            self.data[from..to].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE) || !bytes.len().is_multiple_of(Self::WRITE_SIZE) {
+                return Err(MockFlashError(NorFlashErrorKind::NotAligned));
+            }
+            let written = bytes.len().min(self.power_cut_after.unwrap_or(usize::MAX));
+            for (target, byte) in self.data[offset..offset + written].iter_mut().zip(bytes) {
+                *target &= *byte;
+            }
+            if let Some(left) = &mut self.power_cut_after {
+                *left -= written;
+                if written < bytes.len() {
+                    return Err(MockFlashError(NorFlashErrorKind::Other));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn store(flash: &mut MockFlash) -> RecordStore<&mut MockFlash> {
+        RecordStore::new(flash, [0, SECTOR as u32], SECTOR as u32)
+    }
+
+    #[test]
+    fn test_empty_flash_is_missing() {
+        let mut store = RecordStore::new(MockFlash::new(2), [0, SECTOR as u32], SECTOR as u32);
+        assert!(matches!(store.load(), Err(RecordError::Missing)));
+    }
+
+    #[test]
+    fn test_store_then_load() {
+        let mut store = RecordStore::new(MockFlash::new(3), [2 * SECTOR as u32, SECTOR as u32], SECTOR as u32);
+        store.store(3, b"hello there").unwrap();
+        let (version, data) = store.load().unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(data, b"hello there");
+
+        // Overwriting has to erase first, NOR flash can't set bits back
+        for (version, data) in [(4, &b"bye"[..]), (5, b"again"), (6, b"and again")] {
+            store.store(version, data).unwrap();
+            assert_eq!(store.load().unwrap(), (version, data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_interrupted_writes_keep_the_last_copy() {
+        let mut flash = MockFlash::new(2);
+        store(&mut flash).store(1, b"first").unwrap();
+        store(&mut flash).store(2, b"second").unwrap();
+
+        // Cut before, inside and right at the end of the header, and part way through the data
+        for cut in [0, 4, 12, HEADER_SIZE, HEADER_SIZE + 4] {
+            flash.power_cut_after = Some(cut);
+            assert!(store(&mut flash).store(3, b"third, never finished").is_err());
+            flash.power_cut_after = None;
+            assert_eq!(store(&mut flash).load().unwrap(), (2, b"second".to_vec()), "cut after {cut}");
+        }
+
+        // Back on, the next write goes where the unfinished one was
+        store(&mut flash).store(3, b"third").unwrap();
+        assert_eq!(store(&mut flash).load().unwrap(), (3, b"third".to_vec()));
+        flash.power_cut_after = Some(8);
+        assert!(store(&mut flash).store(4, b"fourth").is_err());
+        flash.power_cut_after = None;
+        assert_eq!(store(&mut flash).load().unwrap(), (3, b"third".to_vec()));
+    }
+
+    #[test]
+    fn test_legacy_records_load() {
+        let mut flash = MockFlash::new(2);
+        let data = b"from before";
+        for (index, field) in [LEGACY_MAGIC, 7, data.len() as u32, crc32(data)].into_iter().enumerate() {
+            flash.data[index * 4..index * 4 + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        flash.data[LEGACY_HEADER_SIZE..LEGACY_HEADER_SIZE + data.len()].copy_from_slice(data);
+        assert_eq!(store(&mut flash).load().unwrap(), (7, data.to_vec()));
+
+        // Rewriting it leaves the old copy alone until the new one is done
+        store(&mut flash).store(8, b"now").unwrap();
+        assert_eq!(store(&mut flash).load().unwrap(), (8, b"now".to_vec()));
+        assert_eq!(u32::from_le_bytes(flash.data[..4].try_into().unwrap()), LEGACY_MAGIC);
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let mut flash = MockFlash::new(2);
+        store(&mut flash).store(1, b"some key material").unwrap();
+        flash.data[HEADER_SIZE + 2] ^= 0x10;
+        assert!(matches!(store(&mut flash).load(), Err(RecordError::Corrupt)));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let mut flash = MockFlash::new(2);
+        let data = alloc::vec![0u8; SECTOR];
+        assert!(matches!(store(&mut flash).store(1, &data), Err(RecordError::TooLarge)));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut flash = MockFlash::new(2);
+        store(&mut flash).store(1, b"data").unwrap();
+        store(&mut flash).store(2, b"more data").unwrap();
+        store(&mut flash).clear().unwrap();
+        assert!(matches!(store(&mut flash).load(), Err(RecordError::Missing)));
+    }
+}
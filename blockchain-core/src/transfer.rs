@@ -0,0 +1,149 @@
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use mcproto_rs::{cookie, uuid::UUID4};
+
+use crate::world::clock::WorldTimestamp;
+
+pub const TRANSFER_COOKIE: &str = "blockchain:transfer";
+/// Bumped if the payload ever changes, older cookies are then ignored
+const COOKIE_VERSION: u8 = 2;
+/// How long a player may be away and still bring their login back. Anyone away longer logs in
+/// again
+pub const TRANSFER_COOKIE_TTL: WorldTimestamp = 5 * 60;
+/// Cookies handed out and not yet brought back. Past this the oldest stops being accepted
+pub const MAX_OUTSTANDING_TRANSFERS: usize = 16;
+
+/// What a player carries to another server and back, in a cookie only we can sign
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferState {
+    pub uuid: UUID4,
+    pub logged_in: bool,
+    /// World time on the server that sent the player away
+    pub issued_at: WorldTimestamp,
+    /// Random, so each cookie is only ever accepted once
+    pub nonce: u64,
+}
+
+impl TransferState {
+    pub fn to_cookie(self, secret: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(30);
+        payload.push(COOKIE_VERSION);
+        payload.extend_from_slice(&self.uuid.to_u128().to_be_bytes());
+        payload.push(self.logged_in as u8);
+        payload.extend_from_slice(&self.issued_at.to_be_bytes());
+        payload.extend_from_slice(&self.nonce.to_be_bytes());
+        cookie::sign(TRANSFER_COOKIE, &payload, secret).expect("transfer state doesn't fit in a cookie")
+    }
+
+    /// `None` for cookies we didn't sign or can't read
+    pub fn from_cookie(cookie: &[u8], secret: &[u8]) -> Option<Self> {
+        let payload = cookie::verify(TRANSFER_COOKIE, cookie, secret)?;
+        let [COOKIE_VERSION, rest @ ..] = payload else {
+            return None;
+        };
+        let (uuid, rest) = rest.split_first_chunk::<16>()?;
+        let ([logged_in], rest) = rest.split_first_chunk::<1>()?;
+        let (issued_at, rest) = rest.split_first_chunk::<4>()?;
+        let (nonce, []) = rest.split_first_chunk::<8>()? else {
+            return None;
+        };
+        Some(Self {
+            uuid: UUID4::from(u128::from_be_bytes(*uuid)),
+            logged_in: *logged_in != 0,
+            issued_at: WorldTimestamp::from_be_bytes(*issued_at),
+            nonce: u64::from_be_bytes(*nonce),
+        })
+    }
+}
+
+/// The cookies this server handed out that haven't come back yet. A signed cookie alone could be
+/// copied and replayed forever, so only one listed here is accepted, once, and only while it's
+/// fresh. Kept in memory, cookies from before a reboot are no good
+pub struct TransferTickets {
+    outstanding: Mutex<NoopRawMutex, RefCell<Vec<TransferState>>>,
+}
+
+impl TransferTickets {
+    pub const fn new() -> Self {
+        Self {
+            outstanding: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Remembers a cookie that's about to be handed out
+    pub fn issue(&self, state: TransferState) {
+        self.outstanding.lock(|outstanding| {
+            let mut outstanding = outstanding.borrow_mut();
+            if outstanding.len() >= MAX_OUTSTANDING_TRANSFERS {
+                outstanding.remove(0);
+            }
+            outstanding.push(state);
+        });
+    }
+
+    /// Whether a cookie that came back is one we handed out and is still fresh. Either way it's
+    /// never accepted again
+    pub fn redeem(&self, state: &TransferState, now: WorldTimestamp) -> bool {
+        self.outstanding.lock(|outstanding| {
+            let mut outstanding = outstanding.borrow_mut();
+            let Some(index) = outstanding.iter().position(|issued| issued == state) else {
+                return false;
+            };
+            outstanding.remove(index);
+            now.saturating_sub(state.issued_at) <= TRANSFER_COOKIE_TTL
+        })
+    }
+}
+
+impl Default for TransferTickets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(nonce: u64) -> TransferState {
+        TransferState {
+            uuid: UUID4::offline_player("alice"),
+            logged_in: true,
+            issued_at: 1000,
+            nonce,
+        }
+    }
+
+    #[test]
+    fn state_survives_the_cookie() {
+        let state = state(7);
+        let cookie = state.to_cookie(b"secret");
+        assert_eq!(TransferState::from_cookie(&cookie, b"secret"), Some(state));
+        assert_eq!(TransferState::from_cookie(&cookie, b"other"), None);
+    }
+
+    #[test]
+    fn test_cookies_are_redeemed_once() {
+        let tickets = TransferTickets::new();
+        tickets.issue(state(1));
+        assert!(!tickets.redeem(&state(2), 1010), "never issued");
+        assert!(tickets.redeem(&state(1), 1010));
+        assert!(!tickets.redeem(&state(1), 1010), "replayed");
+    }
+
+    #[test]
+    fn test_stale_cookies_are_rejected() {
+        let tickets = TransferTickets::new();
+        tickets.issue(state(1));
+        assert!(!tickets.redeem(&state(1), 1000 + TRANSFER_COOKIE_TTL + 1));
+        // Not even once it would be fresh again, e.g. after the clock was wound back
+        assert!(!tickets.redeem(&state(1), 1000));
+
+        for nonce in 0..=MAX_OUTSTANDING_TRANSFERS as u64 {
+            tickets.issue(state(nonce));
+        }
+        assert!(!tickets.redeem(&state(0), 1000), "pushed out by newer ones");
+        assert!(tickets.redeem(&state(1), 1000));
+    }
+}
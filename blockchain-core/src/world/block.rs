@@ -0,0 +1,155 @@
+use crate::world::{WorldPositionType, clock::WorldTimestamp};
+
+#[repr(C, packed(1))]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PackedChunkPosition(u16);
+
+impl PackedChunkPosition {
+    pub fn x(&self) -> u8 {
+        // Little Endian
+        (self.0 & 0b0000000000001111) as u8
+    }
+    pub fn z(&self) -> u8 {
+        ((self.0 & 0b0000000011110000) >> 4) as u8
+    }
+    pub fn y(&self) -> u8 {
+        ((self.0 & 0b1111111100000000) >> 8) as u8
+    }
+
+    pub fn new(x: u8, y: u8, z: u8) -> Self {
+        let mut chunk: u16 = 0;
+        chunk |= x as u16;
+        chunk |= (z << 4) as u16;
+        let mut chunk_y: u16 = y.into();
+        chunk_y <<= 8;
+        chunk |= chunk_y;
+        PackedChunkPosition(chunk)
+    }
+}
+
+#[repr(u16)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockType {
+    NONE = 0,
+    AIR = 1,
+    STONE = 2,
+    DIRT = 3,
+
+}
+
+impl BlockType {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(BlockType::NONE),
+            1 => Some(BlockType::AIR),
+            2 => Some(BlockType::STONE),
+            3 => Some(BlockType::DIRT),
+            _ => None,
+        }
+    }
+
+    /// The block placed by the item with this id, if it's one the world can hold. Item ids
+    /// haven't moved for these across 1.21
+    pub fn from_item_id(id: i32) -> Option<Self> {
+        match id {
+            1 => Some(BlockType::STONE),
+            28 => Some(BlockType::DIRT),
+            _ => None,
+        }
+    }
+
+    /// The block state id the 1.21.8 client uses for this block
+    pub fn state_id(&self) -> i32 {
+        match self {
+            BlockType::NONE | BlockType::AIR => 0,
+            BlockType::STONE => 1,
+            BlockType::DIRT => 10,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockType::NONE => "nothing",
+            BlockType::AIR => "air",
+            BlockType::STONE => "stone",
+            BlockType::DIRT => "dirt",
+        }
+    }
+}
+
+#[repr(C, packed(1))]
+#[derive(Clone, Copy)]
+pub struct BlockUpdatePointer([u8; 3]);
+
+impl BlockUpdatePointer {
+    pub fn from_u32(value: u32) -> Self {
+        let [n1, n2, n3, _] = value.to_le_bytes();
+        Self([n1, n2, n3])
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_le_bytes([self.0[0], self.0[1], self.0[2], 0])
+    }
+
+    /// Chunk heads are never pointed to by `next`, so the first one doubles as the end of a chain
+    pub fn is_null(self) -> bool {
+        self.to_u32() == 0
+    }
+}
+
+/// Index into the world's player table, so an update doesn't have to carry a whole username
+#[repr(C, packed(1))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PlayerIndex(pub u8);
+
+impl PlayerIndex {
+    /// Updates made by the server itself (world generation, the console)
+    pub const SERVER: PlayerIndex = PlayerIndex(u8::MAX);
+    /// Handed out once the player table is full
+    pub const UNKNOWN: PlayerIndex = PlayerIndex(u8::MAX - 1);
+    /// Updates made by a rollback, which is all a restore undoes
+    pub const ROLLBACK: PlayerIndex = PlayerIndex(u8::MAX - 2);
+}
+
+#[repr(C, packed(1))]
+#[derive(Clone, Copy)]
+pub struct BlockUpdate {
+    pub pos: PackedChunkPosition,
+    pub block: BlockType,
+    pub next: BlockUpdatePointer,
+    pub chunk_x: WorldPositionType,
+    pub chunk_z: WorldPositionType,
+    pub player: PlayerIndex,
+    pub timestamp: WorldTimestamp,
+}
+
+impl BlockUpdate {
+    pub fn to_bytes(self) -> [u8; size_of::<BlockUpdate>()] {
+        unsafe { core::mem::transmute(self) }
+    }
+
+    /// Erased or half-written flash can hold any bit pattern, so the block type is checked rather
+    /// than transmuted
+    pub fn from_bytes(raw: [u8; size_of::<BlockUpdate>()]) -> Self {
+        let block = BlockType::from_u16(u16::from_le_bytes([raw[2], raw[3]])).unwrap_or(BlockType::NONE);
+        Self {
+            pos: PackedChunkPosition(u16::from_le_bytes([raw[0], raw[1]])),
+            block,
+            next: BlockUpdatePointer([raw[4], raw[5], raw[6]]),
+            chunk_x: raw[7],
+            chunk_z: raw[8],
+            player: PlayerIndex(raw[9]),
+            timestamp: u32::from_le_bytes([raw[10], raw[11], raw[12], raw[13]]),
+        }
+    }
+
+    /// Absolute block coordinates of this update
+    pub fn position(&self) -> (i32, i32, i32) {
+        let pos = self.pos;
+        (
+            self.chunk_x as i32 * 16 + pos.x() as i32,
+            pos.y() as i32,
+            self.chunk_z as i32 * 16 + pos.z() as i32,
+        )
+    }
+}
@@ -0,0 +1,23 @@
+use embassy_time::Instant;
+
+/// Seconds of world time. Keeps counting across reboots, unlike `Instant`
+pub type WorldTimestamp = u32;
+
+/// Ticks once a second off the embassy time driver, starting where the journal left off last boot
+pub struct WorldClock {
+    epoch: WorldTimestamp,
+}
+
+impl WorldClock {
+    /// `last` is the newest timestamp found in the journal
+    pub fn resume_from(last: WorldTimestamp) -> Self {
+        Self {
+            epoch: last.saturating_add(1),
+        }
+    }
+
+    pub fn now(&self) -> WorldTimestamp {
+        let uptime: WorldTimestamp = Instant::now().as_secs().try_into().unwrap_or(WorldTimestamp::MAX);
+        self.epoch.saturating_add(uptime)
+    }
+}
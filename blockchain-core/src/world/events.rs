@@ -0,0 +1,148 @@
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::{cell::RefCell, mem};
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use log::warn;
+
+use crate::world::{WorldPositionType, block::BlockType, locate};
+
+/// Changes queued one at a time before whole chunks are queued for a resend instead
+pub const MAX_PENDING_BLOCK_CHANGES: usize = 256;
+/// Chunks queued for a resend before the client is only told to rejoin
+pub const MAX_DIRTY_CHUNKS: usize = 64;
+
+/// A block that changed without the connected client causing it (a rollback, the console)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub block: BlockType,
+}
+
+/// What the connected client hasn't picked up yet
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PendingChanges {
+    pub changes: Vec<BlockChange>,
+    /// Chunks that changed too much to queue block by block, their newest blocks are read back
+    /// from the world when they're sent
+    pub chunks: BTreeSet<(WorldPositionType, WorldPositionType)>,
+    /// Even the chunks didn't fit, the client has to rejoin to see everything
+    pub overflowed: bool,
+}
+
+impl PendingChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.chunks.is_empty() && !self.overflowed
+    }
+
+    fn push(&mut self, change: BlockChange) {
+        let Some((chunk_x, chunk_z, _)) = locate(change.x, change.y, change.z) else {
+            return;
+        };
+        if self.overflowed || self.chunks.contains(&(chunk_x, chunk_z)) {
+            return;
+        }
+        if self.changes.len() < MAX_PENDING_BLOCK_CHANGES {
+            self.changes.push(change);
+        } else if self.chunks.len() < MAX_DIRTY_CHUNKS {
+            self.chunks.insert((chunk_x, chunk_z));
+        } else {
+            warn!("too many block changes for the client, it has to rejoin to see them");
+            self.overflowed = true;
+            self.changes = Vec::new();
+            self.chunks.clear();
+        }
+    }
+}
+
+/// Changes waiting for the connected client to pick them up. Nothing is kept while nobody is
+/// connected, a client reads every block from the world when it joins anyway
+pub struct BlockChanges {
+    // `None` while nobody is connected
+    pending: Mutex<NoopRawMutex, RefCell<Option<PendingChanges>>>,
+}
+
+impl BlockChanges {
+    pub const fn new() -> Self {
+        Self {
+            pending: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Starts keeping changes for a client that just connected
+    pub fn listen(&self) {
+        self.pending.lock(|pending| *pending.borrow_mut() = Some(PendingChanges::default()));
+    }
+
+    /// Drops whatever the client that just left didn't pick up
+    pub fn stop_listening(&self) {
+        self.pending.lock(|pending| *pending.borrow_mut() = None);
+    }
+
+    /// Queues changes for whoever is connected. Past `MAX_PENDING_BLOCK_CHANGES` only the chunks
+    /// they're in are kept, so a big rollback can't take the whole heap
+    pub fn publish(&self, changes: &[BlockChange]) {
+        self.pending.lock(|pending| {
+            if let Some(pending) = pending.borrow_mut().as_mut() {
+                for change in changes {
+                    pending.push(*change);
+                }
+            }
+        });
+    }
+
+    /// Everything queued since the last call
+    pub fn take(&self) -> PendingChanges {
+        self.pending
+            .lock(|pending| pending.borrow_mut().as_mut().map(mem::take).unwrap_or_default())
+    }
+}
+
+impl Default for BlockChanges {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(x: i32, z: i32) -> BlockChange {
+        BlockChange {
+            x,
+            y: 5,
+            z,
+            block: BlockType::STONE,
+        }
+    }
+
+    #[test]
+    fn test_big_changes_fall_back_to_chunks() {
+        let changes = BlockChanges::new();
+        changes.listen();
+        // Fills the queue and spills into two more chunks
+        let published: Vec<_> = (0..MAX_PENDING_BLOCK_CHANGES as i32 + 32).map(|x| change(x, 0)).collect();
+        changes.publish(&published);
+
+        let pending = changes.take();
+        assert_eq!(pending.changes.len(), MAX_PENDING_BLOCK_CHANGES);
+        let last_chunk = (MAX_PENDING_BLOCK_CHANGES as i32 + 31).div_floor(16) as u8;
+        assert_eq!(pending.chunks, BTreeSet::from([(last_chunk - 1, 0), (last_chunk, 0)]));
+        assert!(!pending.overflowed);
+        assert!(changes.take().is_empty());
+    }
+
+    #[test]
+    fn test_too_many_chunks_overflow() {
+        let changes = BlockChanges::new();
+        changes.listen();
+        changes.publish(&alloc::vec![change(0, 0); MAX_PENDING_BLOCK_CHANGES]);
+        let spread: Vec<_> = (0..=MAX_DIRTY_CHUNKS as i32).map(|z| change(0, z * 16)).collect();
+        changes.publish(&spread);
+
+        let pending = changes.take();
+        assert!(pending.overflowed);
+        assert!(pending.changes.is_empty() && pending.chunks.is_empty());
+    }
+}
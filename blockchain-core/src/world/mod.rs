@@ -0,0 +1,487 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use log::{info, warn};
+
+use crate::world::{
+    block::{BlockType, BlockUpdate, BlockUpdatePointer, PackedChunkPosition, PlayerIndex},
+    clock::{WorldClock, WorldTimestamp},
+    events::BlockChange,
+    players::{PLAYER_NAME_LENGTH, PLAYER_TABLE_SIZE, PlayerNameEntry},
+};
+
+pub mod block;
+pub mod clock;
+pub mod events;
+pub mod players;
+pub mod replay;
+pub mod rollback;
+
+const READ_ALIGNMENT: usize = 4;
+const CHUNKED_READ_ALIGNMENT: usize = READ_ALIGNMENT * 32;
+// Big enough for an unaligned block update or player table entry
+const IO_WINDOW: usize = READ_ALIGNMENT * 8;
+const BLOCK_UPDATE_SIZE: u32 = core::mem::size_of::<BlockUpdate>() as u32;
+
+type WorldPositionType = u8; // 256 * 16 blocks
+
+/*
+ * On the flash, we have a table of player names, then the first X bits that determine whether or
+ * not that position is filled. Then, a bunch of end-to-end blockupdates that report being filled
+ * to the 'fill map'
+ *
+ * The first 256 * 256 updates are reserved, one per chunk. A chunk's reserved update only holds a
+ * pointer to the newest update in that chunk, and every update points to the one before it, so
+ * walking a chunk's chain goes backwards through its history
+ */
+
+/// Splits absolute block coordinates into chunk coordinates and a position inside that chunk,
+/// or `None` if they fall outside the world
+pub fn locate(x: i32, y: i32, z: i32) -> Option<(WorldPositionType, WorldPositionType, PackedChunkPosition)> {
+    let chunk_x: WorldPositionType = x.div_floor(16).try_into().ok()?;
+    let chunk_z: WorldPositionType = z.div_floor(16).try_into().ok()?;
+    let y: u8 = y.try_into().ok()?;
+    Some((
+        chunk_x,
+        chunk_z,
+        PackedChunkPosition::new(x.rem_euclid(16) as u8, y, z.rem_euclid(16) as u8),
+    ))
+}
+
+pub struct World<F> {
+    flash: F,
+    clock: WorldClock,
+    max_update_count: u32,
+    reserved_update_count: u32,
+    start_of_fill_markers: u32,
+    start_of_block_data: u32,
+    // Updates are only ever allocated, so nothing before this fill marker byte can be free
+    free_search_from: u32,
+}
+
+pub struct ChunkUpdates<'a, F> {
+    world: &'a mut World<F>,
+    next: BlockUpdatePointer,
+    // A corrupt chain could loop forever, it can never be longer than the journal
+    remaining: u32,
+}
+
+impl<'a, F: NorFlash + ReadNorFlash> Iterator for ChunkUpdates<'a, F> {
+    type Item = (BlockUpdatePointer, BlockUpdate);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let pointer = self.next;
+        let update = self.world.read_block_update(pointer);
+        self.next = update.next;
+        Some((pointer, update))
+    }
+}
+
+impl<F: NorFlash + ReadNorFlash> World<F> {
+    /// Zeroes the whole partition, which is what an empty world looks like
+    pub fn clear(flash: &mut F, partition_size: u32) {
+        info!("clearing world...");
+        let zero = [0; READ_ALIGNMENT];
+        for offset in (0..partition_size).step_by(READ_ALIGNMENT) {
+            flash
+                .write(
+                    offset,
+                    &zero[0..READ_ALIGNMENT
+                        .min((partition_size - (offset + READ_ALIGNMENT as u32)) as usize)],
+                )
+                .expect("failed to write to flash");
+        }
+        info!("cleared world");
+    }
+
+    pub fn new(world: F, partition_size: u32) -> Self {
+        let journal_size = partition_size - PLAYER_TABLE_SIZE;
+
+        let block_updates = (journal_size * 8).div_floor(8 * BLOCK_UPDATE_SIZE + 1);
+        let fill_marker_length = block_updates.div_floor(8); // Floor it, drop the leftover space (even if we can fit stuff, I can't think of a way to calculate this reliably)
+        let block_updates = fill_marker_length * 8; // Round block updates to the nearest byte for the fill marker, makes things simpler
+
+        let total = PLAYER_TABLE_SIZE + block_updates * BLOCK_UPDATE_SIZE + fill_marker_length;
+
+        if total > partition_size {
+            panic!("something is wrong in the world size caculations");
+        }
+
+        // The number of reserved updates we need to store
+        let mut reserved: u32 = WorldPositionType::MAX.into();
+        reserved += 1;
+        reserved *= reserved;
+        info!("reserving {} for world chunk start", reserved);
+        let reserve_aligned_bytes = reserved.div_ceil(8);
+
+        if reserved >= block_updates {
+            panic!("requires more reserved block updates than available");
+        }
+
+        let start_of_fill_markers = PLAYER_TABLE_SIZE;
+        let start_of_block_data = start_of_fill_markers + fill_marker_length;
+        let data_offset = start_of_block_data + (reserved * BLOCK_UPDATE_SIZE);
+        info!(
+            "total world size: {total} of {partition_size} [{PLAYER_TABLE_SIZE}b player table, {fill_marker_length}b fill map then {}b data, {block_updates} updates of {}b]. losing {} bytes. unreserved fill markers start at {reserve_aligned_bytes}. unreserved block data starts at {data_offset}",
+            block_updates * BLOCK_UPDATE_SIZE,
+            BLOCK_UPDATE_SIZE,
+            partition_size - total
+        );
+
+        let mut world = Self {
+            flash: world,
+            clock: WorldClock::resume_from(0),
+            max_update_count: block_updates,
+            reserved_update_count: reserved,
+            start_of_fill_markers,
+            start_of_block_data,
+            free_search_from: reserve_aligned_bytes,
+        };
+
+        // Updates are allocated in order, so the one just before the first free space is the newest
+        let newest = match world.first_free_space() {
+            Some(pointer) => pointer.to_u32().checked_sub(1),
+            None => Some(world.max_update_count - 1),
+        }
+        .filter(|slot| *slot >= world.reserved_update_count);
+        if let Some(newest) = newest {
+            let newest = world.read_block_update(BlockUpdatePointer::from_u32(newest));
+            world.clock = WorldClock::resume_from(newest.timestamp);
+        }
+        info!("world clock resuming at {}", world.clock.now());
+
+        world
+    }
+
+    fn read_bytes(&mut self, offset: u32, out: &mut [u8]) {
+        let skip = offset as usize % READ_ALIGNMENT;
+        let length = (skip + out.len()).next_multiple_of(READ_ALIGNMENT);
+        assert!(length <= IO_WINDOW, "flash read too large for window");
+
+        let mut window = [0u8; IO_WINDOW];
+        self.flash
+            .read(offset - skip as u32, &mut window[..length])
+            .expect("failed to read from flash");
+        out.copy_from_slice(&window[skip..skip + out.len()]);
+    }
+
+    fn write_bytes(&mut self, offset: u32, data: &[u8]) {
+        let skip = offset as usize % READ_ALIGNMENT;
+        let length = (skip + data.len()).next_multiple_of(READ_ALIGNMENT);
+        assert!(length <= IO_WINDOW, "flash write too large for window");
+
+        let mut window = [0u8; IO_WINDOW];
+        self.flash
+            .read(offset - skip as u32, &mut window[..length])
+            .expect("failed to read from flash");
+        window[skip..skip + data.len()].copy_from_slice(data);
+        self.flash
+            .write(offset - skip as u32, &window[..length])
+            .expect("failed to write to flash");
+    }
+
+    fn first_free_space(&mut self) -> Option<BlockUpdatePointer> {
+        let mut bytes = [0u8; CHUNKED_READ_ALIGNMENT];
+        let fill_marker_length = self.start_of_block_data - self.start_of_fill_markers;
+
+        // From the start of the reserved fill markers to the end of the fill markers (start of data)
+        for offset in (self.free_search_from..fill_marker_length).step_by(CHUNKED_READ_ALIGNMENT) {
+            self.flash
+                .read(self.start_of_fill_markers + offset, &mut bytes)
+                .expect("failed to read");
+
+            // Make sure we don't read block data
+            let to_use = CHUNKED_READ_ALIGNMENT.min((fill_marker_length - offset) as usize);
+
+            // If one of them has 0s
+            for (byte_index, byte) in bytes[..to_use].iter().enumerate() {
+                if *byte != u8::MAX {
+                    let marker_byte = offset + byte_index as u32;
+                    for v in 0..8 {
+                        if byte & (0b1 << v) == 0 {
+                            self.free_search_from = marker_byte;
+                            return Some(BlockUpdatePointer::from_u32(marker_byte * 8 + v));
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn find_free_space(&mut self) -> BlockUpdatePointer {
+        match self.first_free_space() {
+            Some(pointer) => pointer,
+            None => panic!("no space left in the world"),
+        }
+    }
+
+    fn mark_space_filled(&mut self, pointer: BlockUpdatePointer) {
+        let pointer = pointer.to_u32();
+        let byte_offset = self.start_of_fill_markers + pointer.div_floor(8);
+        let bit_offset = pointer % 8;
+
+        let mut buf = [0u8; 1];
+        self.read_bytes(byte_offset, &mut buf);
+        buf[0] |= 0b1 << bit_offset;
+        self.write_bytes(byte_offset, &buf);
+    }
+
+    /// Current world time, for stamping updates
+    pub fn now(&self) -> WorldTimestamp {
+        self.clock.now()
+    }
+
+    fn chunk_head(chunk_x: WorldPositionType, chunk_z: WorldPositionType) -> BlockUpdatePointer {
+        BlockUpdatePointer::from_u32(chunk_x as u32 * (WorldPositionType::MAX as u32 + 1) + chunk_z as u32)
+    }
+
+    pub fn read_block_update(&mut self, pointer: BlockUpdatePointer) -> BlockUpdate {
+        let memory_offset = self.start_of_block_data + pointer.to_u32() * BLOCK_UPDATE_SIZE;
+        let mut raw_bytes = [0u8; BLOCK_UPDATE_SIZE as usize];
+        self.read_bytes(memory_offset, &mut raw_bytes);
+        BlockUpdate::from_bytes(raw_bytes)
+    }
+
+    pub fn write_block_update(&mut self, pointer: BlockUpdatePointer, value: BlockUpdate) {
+        let memory_offset = self.start_of_block_data + pointer.to_u32() * BLOCK_UPDATE_SIZE;
+        info!("writing to memory offset of {}", memory_offset);
+        self.write_bytes(memory_offset, &value.to_bytes());
+        self.mark_space_filled(pointer);
+    }
+
+    /// Journals a block change by `player` and links it in as the newest update of its chunk
+    pub fn append_block_update(
+        &mut self,
+        x: i32,
+        y: i32,
+        z: i32,
+        block: BlockType,
+        player: PlayerIndex,
+    ) -> Option<BlockUpdatePointer> {
+        let Some((chunk_x, chunk_z, pos)) = locate(x, y, z) else {
+            warn!("tried to update block outside of the world at {x} {y} {z}");
+            return None;
+        };
+
+        let head_pointer = Self::chunk_head(chunk_x, chunk_z);
+        let mut head = self.read_block_update(head_pointer);
+        let timestamp = self.now();
+
+        let pointer = self.find_free_space();
+        self.write_block_update(
+            pointer,
+            BlockUpdate {
+                pos,
+                block,
+                next: head.next,
+                chunk_x,
+                chunk_z,
+                player,
+                timestamp,
+            },
+        );
+
+        head.next = pointer;
+        head.chunk_x = chunk_x;
+        head.chunk_z = chunk_z;
+        head.timestamp = timestamp;
+        self.write_block_update(head_pointer, head);
+
+        Some(pointer)
+    }
+
+    /// Walks a chunk's updates, newest first
+    pub fn chunk_updates(&mut self, chunk_x: WorldPositionType, chunk_z: WorldPositionType) -> ChunkUpdates<'_, F> {
+        let head = self.read_block_update(Self::chunk_head(chunk_x, chunk_z));
+        let remaining = self.max_update_count - self.reserved_update_count;
+        ChunkUpdates {
+            world: self,
+            next: head.next,
+            remaining,
+        }
+    }
+
+    /// The newest block at every position a chunk's journal has touched
+    pub fn latest_blocks(&mut self, chunk_x: WorldPositionType, chunk_z: WorldPositionType) -> Vec<BlockChange> {
+        let mut latest = BTreeMap::new();
+        // Newest first, so the first update seen for a position is its current block
+        for (_, update) in self.chunk_updates(chunk_x, chunk_z) {
+            latest.entry(update.position()).or_insert(update.block);
+        }
+        latest
+            .into_iter()
+            .map(|((x, y, z), block)| BlockChange { x, y, z, block })
+            .collect()
+    }
+
+    /// Up to `limit` of the newest updates to a single block, newest first
+    pub fn block_history(&mut self, x: i32, y: i32, z: i32, limit: usize) -> Vec<BlockUpdate> {
+        let Some((chunk_x, chunk_z, pos)) = locate(x, y, z) else {
+            return Vec::new();
+        };
+
+        self.chunk_updates(chunk_x, chunk_z)
+            .map(|(_, update)| update)
+            .filter(|update| update.pos == pos)
+            .take(limit)
+            .collect()
+    }
+
+    /// Where `name` is in the player table, or the first free entry if it isn't there
+    fn search_players(&mut self, encoded: &PlayerNameEntry) -> Result<PlayerIndex, Option<PlayerIndex>> {
+        for index in 0..=u8::MAX {
+            let index = PlayerIndex(index);
+            if players::is_reserved(index) {
+                continue;
+            }
+            let mut entry: PlayerNameEntry = [0u8; PLAYER_NAME_LENGTH];
+            self.read_bytes(players::table_offset(index), &mut entry);
+            if entry == *encoded {
+                return Ok(index);
+            }
+            // Entries are only ever added, nobody is stored past the first empty one
+            if entry == [0u8; PLAYER_NAME_LENGTH] {
+                return Err(Some(index));
+            }
+        }
+        Err(None)
+    }
+
+    /// Finds `name` in the player table, adding it if this is the first time we've seen them
+    pub fn player_index(&mut self, name: &str) -> PlayerIndex {
+        let Some(encoded) = players::encode_name(name) else {
+            warn!("cannot store player name {name}");
+            return PlayerIndex::UNKNOWN;
+        };
+
+        match self.search_players(&encoded) {
+            Ok(index) => index,
+            Err(Some(index)) => {
+                self.write_bytes(players::table_offset(index), &encoded);
+                info!("added {name} to the player table at {}", index.0);
+                index
+            }
+            Err(None) => {
+                warn!("player table is full, {name} will be recorded as unknown");
+                PlayerIndex::UNKNOWN
+            }
+        }
+    }
+
+    /// Finds `name` in the player table without adding it, for looking players up
+    pub fn find_player(&mut self, name: &str) -> Option<PlayerIndex> {
+        let encoded = players::encode_name(name)?;
+        self.search_players(&encoded).ok()
+    }
+
+    pub fn player_name(&mut self, index: PlayerIndex) -> Option<String> {
+        if players::is_reserved(index) {
+            return None;
+        }
+        let mut entry: PlayerNameEntry = [0u8; PLAYER_NAME_LENGTH];
+        self.read_bytes(players::table_offset(index), &mut entry);
+        players::decode_name(&entry)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind};
+
+    /// Big enough for the reserved chunk heads plus some room for updates
+    pub const TEST_WORLD_SIZE: u32 = 0x110000;
+
+    /// Flash that can be written over without erasing, like the world expects
+    pub struct RamFlash {
+        pub data: Vec<u8>,
+    }
+
+    impl RamFlash {
+        pub fn new(size: u32) -> Self {
+            Self {
+                data: alloc::vec![0; size as usize],
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = READ_ALIGNMENT;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            let source = self
+                .data
+                .get(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(source);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = READ_ALIGNMENT;
+        const ERASE_SIZE: usize = 4096;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.data[from as usize..to as usize].fill(0);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    pub fn test_world() -> World<RamFlash> {
+        World::new(RamFlash::new(TEST_WORLD_SIZE), TEST_WORLD_SIZE)
+    }
+
+    #[test]
+    fn test_looking_players_up_doesnt_add_them() {
+        let mut world = test_world();
+        assert_eq!(world.find_player("alice"), None);
+        assert_eq!(world.find_player("a name far too long"), None);
+
+        let alice = world.player_index("alice");
+        assert_eq!(world.find_player("alice"), Some(alice));
+        assert_eq!(world.find_player("bob"), None);
+        // Bob still gets the entry after alice, the lookup didn't take it
+        assert_eq!(world.player_index("bob"), PlayerIndex(alice.0 + 1));
+    }
+
+    #[test]
+    fn test_latest_blocks_are_the_newest() {
+        let mut world = test_world();
+        let alice = world.player_index("alice");
+        world.append_block_update(1, 5, 1, BlockType::STONE, alice);
+        world.append_block_update(1, 5, 1, BlockType::DIRT, alice);
+        world.append_block_update(2, 5, 1, BlockType::STONE, alice);
+        // Another chunk
+        world.append_block_update(17, 5, 1, BlockType::STONE, alice);
+
+        let latest = world.latest_blocks(0, 0);
+        assert_eq!(
+            latest,
+            alloc::vec![
+                BlockChange { x: 1, y: 5, z: 1, block: BlockType::DIRT },
+                BlockChange { x: 2, y: 5, z: 1, block: BlockType::STONE },
+            ]
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use alloc::string::String;
+
+use crate::world::block::PlayerIndex;
+
+/// Vanilla usernames are at most 16 bytes, which is exactly one table entry
+pub const PLAYER_NAME_LENGTH: usize = 16;
+/// One entry per possible `PlayerIndex`, including the reserved ones
+pub const PLAYER_TABLE_SIZE: u32 = (u8::MAX as u32 + 1) * PLAYER_NAME_LENGTH as u32;
+
+pub type PlayerNameEntry = [u8; PLAYER_NAME_LENGTH];
+
+pub fn encode_name(name: &str) -> Option<PlayerNameEntry> {
+    let bytes = name.as_bytes();
+    if bytes.is_empty() || bytes.len() > PLAYER_NAME_LENGTH {
+        return None;
+    }
+    let mut entry = [0u8; PLAYER_NAME_LENGTH];
+    entry[..bytes.len()].copy_from_slice(bytes);
+    Some(entry)
+}
+
+pub fn decode_name(entry: &PlayerNameEntry) -> Option<String> {
+    let length = entry.iter().position(|b| *b == 0).unwrap_or(PLAYER_NAME_LENGTH);
+    if length == 0 {
+        return None;
+    }
+    String::from_utf8(entry[..length].to_vec()).ok()
+}
+
+pub fn is_reserved(index: PlayerIndex) -> bool {
+    index == PlayerIndex::SERVER || index == PlayerIndex::UNKNOWN || index == PlayerIndex::ROLLBACK
+}
+
+pub fn table_offset(index: PlayerIndex) -> u32 {
+    index.0 as u32 * PLAYER_NAME_LENGTH as u32
+}
@@ -0,0 +1,87 @@
+use embassy_time::Instant;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::world::{
+    World,
+    block::{BlockUpdate, BlockUpdatePointer},
+};
+
+/// Most updates sent to a client between two of its packets, so a fast replay can't starve it
+pub const REPLAY_BATCH: u32 = 64;
+pub const DEFAULT_REPLAY_SPEED: u32 = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplayPhase {
+    /// Every journaled position is set to air so the build starts from nothing
+    Clearing,
+    /// The journal is played back in order at the chosen speed
+    Building,
+}
+
+/// A time-lapse of the journal being streamed to one client
+pub struct Replay {
+    pub phase: ReplayPhase,
+    /// Next journal entry to send in the current phase
+    pub cursor: u32,
+    per_second: u32,
+    started: Instant,
+}
+
+impl Replay {
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            phase: ReplayPhase::Clearing,
+            cursor: 0,
+            per_second: per_second.max(1),
+            started: Instant::now(),
+        }
+    }
+
+    /// How many entries can be sent right now without getting ahead of the replay speed
+    pub fn budget(&self) -> u32 {
+        match self.phase {
+            ReplayPhase::Clearing => REPLAY_BATCH,
+            ReplayPhase::Building => {
+                let elapsed = self.started.elapsed().as_millis();
+                let due = (elapsed * self.per_second as u64 / 1000).min(u32::MAX as u64) as u32;
+                due.saturating_sub(self.cursor).min(REPLAY_BATCH)
+            }
+        }
+    }
+
+    /// Moves on once the journal runs out, returning whether the whole replay is done
+    pub fn finish_phase(&mut self) -> bool {
+        match self.phase {
+            ReplayPhase::Clearing => {
+                self.phase = ReplayPhase::Building;
+                self.cursor = 0;
+                self.started = Instant::now();
+                false
+            }
+            ReplayPhase::Building => true,
+        }
+    }
+
+    /// Sends whatever is left as fast as the batch size allows
+    pub fn fast_forward(&mut self) {
+        self.per_second = u32::MAX;
+    }
+}
+
+impl<F: NorFlash + ReadNorFlash> World<F> {
+    fn is_filled(&mut self, slot: u32) -> bool {
+        let mut buf = [0u8; 1];
+        self.read_bytes(self.start_of_fill_markers + slot.div_floor(8), &mut buf);
+        buf[0] & (0b1 << (slot % 8)) != 0
+    }
+
+    /// The `sequence`th update ever written. Updates are allocated in order, so this is journal
+    /// order, and the first unfilled slot is the end of the journal
+    pub fn journal_entry(&mut self, sequence: u32) -> Option<BlockUpdate> {
+        let slot = self.reserved_update_count.checked_add(sequence)?;
+        if slot >= self.max_update_count || !self.is_filled(slot) {
+            return None;
+        }
+        Some(self.read_block_update(BlockUpdatePointer::from_u32(slot)))
+    }
+}
@@ -0,0 +1,236 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use log::info;
+
+use crate::world::{
+    World, WorldPositionType,
+    block::{BlockType, PlayerIndex},
+    clock::WorldTimestamp,
+    events::BlockChange,
+};
+
+/// An inclusive box of absolute block coordinates
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub min: (i32, i32, i32),
+    pub max: (i32, i32, i32),
+}
+
+impl Region {
+    pub fn new(a: (i32, i32, i32), b: (i32, i32, i32)) -> Self {
+        Self {
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    pub fn contains(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+
+    /// Every chunk the region touches, clamped to the world
+    fn chunks(&self) -> impl Iterator<Item = (WorldPositionType, WorldPositionType)> + use<> {
+        let clamp = |v: i32| v.div_floor(16).clamp(0, WorldPositionType::MAX as i32) as WorldPositionType;
+        let (min_x, max_x) = (clamp(self.min.0), clamp(self.max.0));
+        let (min_z, max_z) = (clamp(self.min.2), clamp(self.max.2));
+        (min_x..=max_x).flat_map(move |x| (min_z..=max_z).map(move |z| (x, z)))
+    }
+}
+
+/// Which updates a rollback undoes. Unset fields match everything
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RollbackFilter {
+    pub player: Option<PlayerIndex>,
+    /// Oldest timestamp to undo, inclusive
+    pub since: Option<WorldTimestamp>,
+    /// Newest timestamp to undo, inclusive
+    pub until: Option<WorldTimestamp>,
+}
+
+impl RollbackFilter {
+    fn matches(&self, player: PlayerIndex, timestamp: WorldTimestamp) -> bool {
+        self.player.is_none_or(|p| p == player)
+            && self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp <= until)
+    }
+}
+
+struct PositionState {
+    newest: BlockType,
+    // Whether the newest update is one of the ones being undone
+    newest_matched: bool,
+    matched: bool,
+    // The value from just before the oldest matching update seen so far
+    previous: Option<BlockType>,
+}
+
+impl<F: NorFlash + ReadNorFlash> World<F> {
+    /// Puts every block in `region` back to how it was before the updates matching `filter`.
+    /// The result is journaled as `PlayerIndex::ROLLBACK`'s updates, which `restore` undoes
+    pub fn rollback(&mut self, region: &Region, filter: &RollbackFilter) -> Vec<BlockChange> {
+        let changes = self.undo(region, filter, false, PlayerIndex::ROLLBACK);
+        info!("rolled back {} blocks in {:?}", changes.len(), region);
+        changes
+    }
+
+    /// Undoes the rollbacks made in `region` between `since` and `until`. Blocks changed again
+    /// after the rollback are left alone, so a restore never overwrites newer edits
+    pub fn restore(
+        &mut self,
+        region: &Region,
+        since: Option<WorldTimestamp>,
+        until: Option<WorldTimestamp>,
+    ) -> Vec<BlockChange> {
+        let filter = RollbackFilter {
+            player: Some(PlayerIndex::ROLLBACK),
+            since,
+            until,
+        };
+        let changes = self.undo(region, &filter, true, PlayerIndex::SERVER);
+        info!("restored {} blocks in {:?}", changes.len(), region);
+        changes
+    }
+
+    /// Journals the value from before the oldest update matching `filter` at every position in
+    /// `region`, as `by`'s updates
+    fn undo(
+        &mut self,
+        region: &Region,
+        filter: &RollbackFilter,
+        newest_only: bool,
+        by: PlayerIndex,
+    ) -> Vec<BlockChange> {
+        let mut changes = Vec::new();
+
+        for (chunk_x, chunk_z) in region.chunks() {
+            let mut positions: BTreeMap<(i32, i32, i32), PositionState> = BTreeMap::new();
+
+            // Newest first, so the last matching update we see for a position is the oldest one
+            for (_, update) in self.chunk_updates(chunk_x, chunk_z) {
+                let position = update.position();
+                if !region.contains(position) {
+                    continue;
+                }
+                let matches = filter.matches(update.player, update.timestamp);
+                let state = positions.entry(position).or_insert(PositionState {
+                    newest: update.block,
+                    newest_matched: matches,
+                    matched: false,
+                    previous: None,
+                });
+                if matches {
+                    state.matched = true;
+                    state.previous = None;
+                } else if state.matched && state.previous.is_none() {
+                    state.previous = Some(update.block);
+                }
+            }
+
+            for ((x, y, z), state) in positions {
+                if !state.matched || (newest_only && !state.newest_matched) {
+                    continue;
+                }
+                // Nothing older than the update means it was never changed from the generated world
+                let block = state.previous.unwrap_or(BlockType::NONE);
+                if block == state.newest {
+                    continue;
+                }
+                self.append_block_update(x, y, z, block, by);
+                changes.push(BlockChange { x, y, z, block });
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{events::BlockChanges, tests::test_world};
+    use embassy_time::{Duration, MockDriver};
+
+    #[test]
+    fn test_large_rollbacks_reach_the_client() {
+        let mut world = test_world();
+        let player = world.player_index("alice");
+        // More than a chunk's width, and more than the old queue held
+        for x in 0..100 {
+            world.append_block_update(x, 5, 0, BlockType::STONE, player);
+        }
+
+        let changes = BlockChanges::new();
+        changes.listen();
+        let filter = RollbackFilter {
+            player: Some(player),
+            ..Default::default()
+        };
+        let rolled_back = world.rollback(&Region::new((0, 0, 0), (99, 10, 0)), &filter);
+        changes.publish(&rolled_back);
+
+        let sent = changes.take().changes;
+        assert_eq!(sent.len(), 100);
+        assert!(sent.iter().all(|change| change.block == BlockType::NONE));
+        assert!(changes.take().is_empty());
+        let latest = world.block_history(42, 5, 0, 1)[0];
+        assert_eq!({ latest.block }, BlockType::NONE);
+
+        // Nobody to send them to
+        changes.stop_listening();
+        changes.publish(&rolled_back);
+        assert!(changes.take().is_empty());
+    }
+
+    #[test]
+    fn test_only_the_window_is_rolled_back() {
+        let mut world = test_world();
+        let player = world.player_index("alice");
+        world.append_block_update(1, 5, 1, BlockType::STONE, player);
+        let early = world.block_history(1, 5, 1, 1)[0].timestamp;
+        MockDriver::get().advance(Duration::from_secs(10));
+        world.append_block_update(2, 5, 1, BlockType::STONE, player);
+
+        let filter = RollbackFilter {
+            player: Some(player),
+            since: Some(early),
+            until: Some(early),
+        };
+        let changes = world.rollback(&Region::new((0, 0, 0), (15, 10, 15)), &filter);
+        assert_eq!(changes, alloc::vec![BlockChange { x: 1, y: 5, z: 1, block: BlockType::NONE }]);
+        let later = world.block_history(2, 5, 1, 1)[0];
+        assert_eq!({ later.block }, BlockType::STONE);
+    }
+
+    #[test]
+    fn test_restore_only_undoes_rollbacks() {
+        let mut world = test_world();
+        let alice = world.player_index("alice");
+        let bob = world.player_index("bob");
+        let region = Region::new((0, 0, 0), (15, 10, 15));
+        world.append_block_update(1, 5, 1, BlockType::STONE, PlayerIndex::SERVER);
+        world.append_block_update(2, 5, 1, BlockType::DIRT, alice);
+        world.append_block_update(3, 5, 1, BlockType::DIRT, alice);
+
+        let filter = RollbackFilter {
+            player: Some(alice),
+            ..Default::default()
+        };
+        assert_eq!(world.rollback(&region, &filter).len(), 2);
+        // Bob builds over one of the rolled back blocks before the restore
+        world.append_block_update(3, 5, 1, BlockType::STONE, bob);
+        // The server's own updates after the rollback aren't the restore's business either
+        world.append_block_update(4, 5, 1, BlockType::STONE, PlayerIndex::SERVER);
+
+        let changes = world.restore(&region, None, None);
+        assert_eq!(changes, alloc::vec![BlockChange { x: 2, y: 5, z: 1, block: BlockType::DIRT }]);
+        let newest = |world: &mut World<_>, x| world.block_history(x, 5, 1, 1)[0].block;
+        assert_eq!(newest(&mut world, 1), BlockType::STONE);
+        assert_eq!(newest(&mut world, 3), BlockType::STONE);
+        assert_eq!(newest(&mut world, 4), BlockType::STONE);
+
+        // Restoring twice changes nothing, the restore isn't a rollback
+        assert!(world.restore(&region, None, None).is_empty());
+    }
+}
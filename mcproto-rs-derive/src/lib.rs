@@ -0,0 +1,417 @@
+//! Derives for the `Serialize` and `Deserialize` traits in mcproto-rs.
+//!
+//! Structs are written field by field in declaration order, exactly like `proto_struct!`. Enums
+//! are written as an id followed by the variant's fields, exactly like `proto_enum_with_type!`.
+//!
+//! Field attributes:
+//! * `#[mc(varint)]` / `#[mc(varlong)]` - an `i32` / `i64` written as a `VarInt` / `VarLong`
+//! * `#[mc(count = VarInt)]` - a `Vec<E>` prefixed by its length, like `CountedArray<E, VarInt>`
+//! * `#[mc(optional)]` - an `Option<T>` prefixed by a bool, the other attributes apply to `T`
+//! * `#[mc(max_length = 16)]` - reject longer strings and arrays while reading
+//!
+//! Enum attributes:
+//! * `#[mc(tag = u8)]` on the enum - the type of the id, `VarInt` if omitted
+//! * `#[mc(id = 0x01)]` on a variant, or an explicit discriminant - the id of the variant
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Expr, Field, Fields,
+    GenericArgument, Ident, LitInt, PathArguments, Result, Type,
+};
+
+#[proc_macro_derive(McSerialize, attributes(mc))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(McDeserialize, attributes(mc))]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_serialize(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::mcproto_rs::Serialize));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = FieldList::parse(&data.fields)?;
+            let writes = fields.serialize();
+            let pattern = fields.pattern(quote!(Self));
+            quote! {
+                let #pattern = self;
+                #writes
+                Ok(())
+            }
+        }
+        Data::Enum(data) => {
+            let tag = EnumOpts::parse(&input.attrs)?.tag;
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let id = variant_id(variant)?;
+                let fields = FieldList::parse(&variant.fields)?;
+                let ident = &variant.ident;
+                let pattern = fields.pattern(quote!(Self::#ident));
+                let writes = fields.serialize();
+                arms.push(quote! {
+                    #pattern => {
+                        let __id: #tag = (#id).into();
+                        __to.serialize_other(&__id)?;
+                        #writes
+                    }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+                Ok(())
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions cannot be serialized")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::mcproto_rs::Serialize for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn mc_serialize<__S: ::mcproto_rs::Serializer>(&self, __to: &mut __S) -> ::mcproto_rs::SerializeResult {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_deserialize(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::mcproto_rs::Deserialize));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = FieldList::parse(&data.fields)?;
+            let reads = fields.deserialize();
+            let pattern = fields.pattern(quote!(Self));
+            quote! {
+                let __rest = __data;
+                #reads
+                ::mcproto_rs::Deserialized::ok(#pattern, __rest)
+            }
+        }
+        Data::Enum(data) => {
+            let tag = EnumOpts::parse(&input.attrs)?.tag;
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let id = variant_id(variant)?;
+                let fields = FieldList::parse(&variant.fields)?;
+                let ident = &variant.ident;
+                let reads = fields.deserialize();
+                let pattern = fields.pattern(quote!(Self::#ident));
+                arms.push(quote! {
+                    #id => {
+                        #reads
+                        ::mcproto_rs::Deserialized::ok(#pattern, __rest)
+                    }
+                });
+            }
+            quote! {
+                let ::mcproto_rs::Deserialized { value: __id, data: __rest } =
+                    <#tag as ::mcproto_rs::Deserialize>::mc_deserialize(__data)?;
+                match __id.into() {
+                    #(#arms)*
+                    __other => Err(::mcproto_rs::DeserializeErr::CannotUnderstandValue(
+                        ::mcproto_rs::__private::format!("invalid {} {:?}", stringify!(#name), __other),
+                    )),
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new_spanned(input, "unions cannot be deserialized")),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::mcproto_rs::Deserialize for #name #ty_generics #where_clause {
+            fn mc_deserialize(__data: &[u8]) -> ::mcproto_rs::DeserializeResult<'_, Self> {
+                #body
+            }
+        }
+    })
+}
+
+struct EnumOpts {
+    tag: Type,
+}
+
+impl EnumOpts {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut tag = None;
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("mc")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    tag = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `tag`"))
+                }
+            })?;
+        }
+
+        Ok(Self {
+            tag: tag.unwrap_or_else(|| parse_quote!(::mcproto_rs::types::VarInt)),
+        })
+    }
+}
+
+fn variant_id(variant: &syn::Variant) -> Result<Expr> {
+    let mut id = None;
+    for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("mc")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `id`"))
+            }
+        })?;
+    }
+
+    id.or_else(|| variant.discriminant.as_ref().map(|(_, expr)| expr.clone()))
+        .ok_or_else(|| Error::new_spanned(variant, "variant needs an id, use #[mc(id = ...)] or a discriminant"))
+}
+
+#[derive(Clone, Default)]
+struct FieldOpts {
+    var: Option<Ident>,
+    count: Option<Type>,
+    optional: bool,
+    max_length: Option<LitInt>,
+}
+
+impl FieldOpts {
+    fn parse(field: &Field) -> Result<Self> {
+        let mut opts = Self::default();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("mc")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("varint") {
+                    opts.var = Some(format_ident!("VarInt"));
+                } else if meta.path.is_ident("varlong") {
+                    opts.var = Some(format_ident!("VarLong"));
+                } else if meta.path.is_ident("count") {
+                    opts.count = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("optional") {
+                    opts.optional = true;
+                } else if meta.path.is_ident("max_length") {
+                    opts.max_length = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `varint`, `varlong`, `count`, `optional` or `max_length`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(opts)
+    }
+}
+
+// how a single value is put on the wire
+enum Encoding {
+    Plain { ty: Box<Type>, max_length: Option<LitInt> },
+    Var { wrapper: Ident },
+    Counted { elem: Box<Type>, counter: Box<Type>, max_length: Option<LitInt> },
+    Optional(Box<Encoding>),
+}
+
+impl Encoding {
+    fn new(ty: &Type, opts: &FieldOpts) -> Result<Self> {
+        if opts.optional {
+            let inner = generic_arg(ty, "Option")
+                .ok_or_else(|| Error::new_spanned(ty, "#[mc(optional)] needs an Option<T> field"))?;
+            let inner_opts = FieldOpts { optional: false, ..opts.clone() };
+            return Ok(Encoding::Optional(Box::new(Self::new(inner, &inner_opts)?)));
+        }
+
+        match (&opts.var, &opts.count) {
+            (Some(_), Some(_)) => Err(Error::new_spanned(ty, "a field cannot be both a var int and counted")),
+            (Some(wrapper), None) => match &opts.max_length {
+                Some(max) => Err(Error::new_spanned(max, "max_length does not apply to var ints")),
+                None => Ok(Encoding::Var { wrapper: wrapper.clone() }),
+            },
+            (None, Some(counter)) => {
+                let elem = generic_arg(ty, "Vec")
+                    .ok_or_else(|| Error::new_spanned(ty, "#[mc(count = ...)] needs a Vec<E> field"))?;
+                Ok(Encoding::Counted {
+                    elem: Box::new(elem.clone()),
+                    counter: Box::new(counter.clone()),
+                    max_length: opts.max_length.clone(),
+                })
+            }
+            (None, None) => Ok(Encoding::Plain {
+                ty: Box::new(ty.clone()),
+                max_length: opts.max_length.clone(),
+            }),
+        }
+    }
+
+    // statements writing `value`, an expression of type &T, to `__to`
+    fn serialize(&self, value: TokenStream2) -> TokenStream2 {
+        match self {
+            Encoding::Plain { .. } => quote! {
+                __to.serialize_other(#value)?;
+            },
+            Encoding::Var { wrapper } => quote! {
+                __to.serialize_other(&::mcproto_rs::types::#wrapper(*#value))?;
+            },
+            Encoding::Counted { counter, .. } => quote! {
+                __to.serialize_other(&<#counter as ::mcproto_rs::types::ArrayCounter>::from_count(#value.len()))?;
+                for __elem in #value.iter() {
+                    __to.serialize_other(__elem)?;
+                }
+            },
+            Encoding::Optional(inner) => {
+                let inner = inner.serialize(quote!(__some));
+                quote! {
+                    match #value {
+                        ::core::option::Option::Some(__some) => {
+                            __to.serialize_other(&true)?;
+                            #inner
+                        }
+                        ::core::option::Option::None => {
+                            __to.serialize_other(&false)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // an expression reading the value from the slice `input`, giving a DeserializeResult
+    fn deserialize(&self, input: TokenStream2) -> TokenStream2 {
+        match self {
+            Encoding::Plain { ty, max_length: None } => quote! {
+                <#ty as ::mcproto_rs::Deserialize>::mc_deserialize(#input)
+            },
+            Encoding::Plain { ty, max_length: Some(max) } => quote! {
+                <#ty as ::mcproto_rs::DeserializeLimited>::mc_deserialize_limited(#input, #max)
+            },
+            Encoding::Var { wrapper } => quote! {
+                <::mcproto_rs::types::#wrapper as ::mcproto_rs::Deserialize>::mc_deserialize(#input)
+                    .map(|__d| __d.map(|__v| __v.0))
+            },
+            Encoding::Counted { elem, counter, max_length } => {
+                let array = quote!(::mcproto_rs::types::CountedArray<#elem, #counter>);
+                let read = match max_length {
+                    None => quote!(<#array as ::mcproto_rs::Deserialize>::mc_deserialize(#input)),
+                    Some(max) => quote!(<#array as ::mcproto_rs::DeserializeLimited>::mc_deserialize_limited(#input, #max)),
+                };
+                quote! {
+                    #read.map(|__d| __d.map(<::mcproto_rs::__private::Vec<#elem>>::from))
+                }
+            }
+            Encoding::Optional(inner) => {
+                let inner = inner.deserialize(quote!(__present));
+                quote! {
+                    match <bool as ::mcproto_rs::Deserialize>::mc_deserialize(#input) {
+                        Ok(::mcproto_rs::Deserialized { value: true, data: __present }) =>
+                            #inner.map(|__d| __d.map(::core::option::Option::Some)),
+                        Ok(::mcproto_rs::Deserialized { value: false, data: __absent }) =>
+                            ::mcproto_rs::Deserialized::ok(::core::option::Option::None, __absent),
+                        Err(__err) => Err(__err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn generic_arg<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last().filter(|segment| segment.ident == wrapper)?;
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => &args.args,
+        _ => return None,
+    };
+    match args.first() {
+        Some(GenericArgument::Type(ty)) => Some(ty),
+        _ => None,
+    }
+}
+
+struct FieldList {
+    named: bool,
+    fields: Vec<(Option<Ident>, String, Encoding)>,
+}
+
+impl FieldList {
+    fn parse(fields: &Fields) -> Result<Self> {
+        let named = matches!(fields, Fields::Named(_));
+        let fields = fields
+            .iter()
+            .enumerate()
+            .map(|(idx, field)| {
+                let label = match &field.ident {
+                    Some(ident) => ident.to_string(),
+                    None => idx.to_string(),
+                };
+                let encoding = Encoding::new(&field.ty, &FieldOpts::parse(field)?)?;
+                Ok((field.ident.clone(), label, encoding))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { named, fields })
+    }
+
+    fn binding(idx: usize) -> Ident {
+        format_ident!("__field{}", idx)
+    }
+
+    // `path { a: __field0, .. }`, `path(__field0, ..)` or `path`, binding every field in order
+    fn pattern(&self, path: TokenStream2) -> TokenStream2 {
+        let bindings = (0..self.fields.len()).map(Self::binding);
+        if self.named {
+            let names = self.fields.iter().map(|(ident, _, _)| ident);
+            quote!(#path { #(#names: #bindings),* })
+        } else if self.fields.is_empty() {
+            path
+        } else {
+            quote!(#path(#(#bindings),*))
+        }
+    }
+
+    fn serialize(&self) -> TokenStream2 {
+        let writes = self.fields.iter().enumerate().map(|(idx, (_, _, encoding))| {
+            encoding.serialize(Self::binding(idx).into_token_stream())
+        });
+        quote!(#(#writes)*)
+    }
+
+    fn deserialize(&self) -> TokenStream2 {
+        let reads = self.fields.iter().enumerate().map(|(idx, (_, label, encoding))| {
+            let binding = Self::binding(idx);
+            let read = encoding.deserialize(quote!(__rest));
+            quote! {
+                let ::mcproto_rs::Deserialized { value: #binding, data: __rest } = ::mcproto_rs::with_field_context(
+                    #read,
+                    #label,
+                    __data.len() - __rest.len(),
+                )?;
+            }
+        });
+        quote!(#(#reads)*)
+    }
+}
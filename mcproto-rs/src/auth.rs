@@ -0,0 +1,263 @@
+//! Online mode logins: the server hash a client sends to the session server when joining, and
+//! the `hasJoined` request the server makes to check the client really did.
+//!
+//! The HTTP request itself is left to a `SessionTransport`, so the caller decides how to reach
+//! the session server.
+
+use crate::{hash::Sha1, utils::hex, uuid::UUID4};
+use alloc::{fmt, format, string::String, vec::Vec};
+use core::future::Future;
+
+/// Mojang's session server, reachable only over HTTPS
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+const HAS_JOINED_PATH: &str = "/session/minecraft/hasJoined";
+
+/// The hash both sides send to the session server, SHA-1 over the server id, shared secret and
+/// public key, printed like Java's `BigInteger::toString(16)`: signed, without leading zeros
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut sha = Sha1::new();
+    sha.update(server_id.as_bytes());
+    sha.update(shared_secret);
+    sha.update(public_key);
+    let mut digest = sha.finalize();
+
+    let negative = digest[0] & 0x80 != 0;
+    if negative {
+        // Two's complement, so what's printed is the magnitude
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (value, overflow) = byte.overflowing_add(1);
+                *byte = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let digits = hex(&digest);
+    let digits = digits.trim_start_matches('0');
+    match (negative, digits.is_empty()) {
+        (_, true) => "0".into(),
+        (true, false) => format!("-{}", digits),
+        (false, false) => digits.into(),
+    }
+}
+
+/// The rule account names follow: 3 to 16 letters, digits and underscores. Names outside it can
+/// only come from a modified client
+pub fn is_valid_username(username: &str) -> bool {
+    (3..=16).contains(&username.len())
+        && username.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+/// Makes plain HTTP GET requests to a session server
+pub trait SessionTransport {
+    type Error;
+
+    /// GETs `path`, which includes the query string, returning the status code and body
+    fn get(&self, path: &str) -> impl Future<Output = Result<(u16, Vec<u8>), Self::Error>>;
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// The account the session server vouches for, its properties carry the skin textures
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct GameProfile {
+    pub id: UUID4,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+#[derive(Debug)]
+pub enum SessionErr<E> {
+    Transport(E),
+    Status(u16),
+    BadProfile(String),
+}
+
+impl<E: fmt::Debug> fmt::Display for SessionErr<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionErr::Transport(err) => f.write_fmt(format_args!("session server unreachable: {:?}", err)),
+            SessionErr::Status(status) => f.write_fmt(format_args!("session server answered {}", status)),
+            SessionErr::BadProfile(err) => f.write_fmt(format_args!("bad profile from session server: {}", err)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug> std::error::Error for SessionErr<E> {}
+
+/// Asks the session server whether `username` joined with `server_hash`. `None` means they
+/// didn't, so the login should be refused. Passing `ip` also checks they joined from there
+pub async fn has_joined<T: SessionTransport>(
+    transport: &T,
+    username: &str,
+    server_hash: &str,
+    ip: Option<&str>,
+) -> Result<Option<GameProfile>, SessionErr<T::Error>> {
+    let mut path = format!(
+        "{}?username={}&serverId={}",
+        HAS_JOINED_PATH,
+        url_encode(username),
+        url_encode(server_hash)
+    );
+    if let Some(ip) = ip {
+        path.push_str("&ip=");
+        path.push_str(&url_encode(ip));
+    }
+
+    let (status, body) = transport.get(&path).await.map_err(SessionErr::Transport)?;
+    match status {
+        200 => serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| SessionErr::BadProfile(format!("{}", err))),
+        204 => Ok(None),
+        status => Err(SessionErr::Status(status)),
+    }
+}
+
+fn url_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        pin::pin,
+        task::{Context, Poll, Waker},
+        thread,
+    };
+
+    #[test]
+    fn test_server_hash() {
+        // The examples from the protocol documentation
+        assert_eq!(server_hash("Notch", &[], &[]), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(server_hash("jeb_", &[], &[]), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(server_hash("simon", &[], &[]), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn test_valid_usernames() {
+        for name in ["Notch", "jeb_", "abc", "_under_score_16_"] {
+            assert!(is_valid_username(name), "{}", name);
+        }
+        for name in ["", "ab", "seventeen_letters", "has space", "dash-name", "ümlaut", "Notch\0"] {
+            assert!(!is_valid_username(name), "{}", name);
+        }
+    }
+
+    /// A blocking HTTP/1.0 client, enough to talk to the stand-in below
+    struct StdTransport {
+        address: String,
+    }
+
+    impl SessionTransport for StdTransport {
+        type Error = std::io::Error;
+
+        async fn get(&self, path: &str) -> Result<(u16, Vec<u8>), Self::Error> {
+            let mut stream = TcpStream::connect(&self.address)?;
+            write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, self.address)?;
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response)?;
+
+            let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = std::str::from_utf8(&response[..split]).unwrap();
+            let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+            Ok((status, response[split + 4..].to_vec()))
+        }
+    }
+
+    /// Answers one request with `status` and `body`, handing back the request line
+    fn stand_in_session_server(status: &'static str, body: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            write!(stream, "HTTP/1.0 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body).unwrap();
+            String::from_utf8(request).unwrap().lines().next().unwrap().to_string()
+        });
+        (address, handle)
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_has_joined() {
+        let profile = r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[
+            {"name":"textures","value":"ewogIC4uLgp9","signature":"c2lnbmVk"}]}"#;
+        let (address, server) = stand_in_session_server("200 OK", profile);
+
+        let transport = StdTransport { address };
+        let joined = block_on(has_joined(&transport, "Notch", "-7c9d5b", Some("10.0.0.2"))).unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            "GET /session/minecraft/hasJoined?username=Notch&serverId=-7c9d5b&ip=10.0.0.2 HTTP/1.0"
+        );
+        assert_eq!(
+            joined,
+            Some(GameProfile {
+                id: UUID4::parse("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(),
+                name: "Notch".into(),
+                properties: vec![ProfileProperty {
+                    name: "textures".into(),
+                    value: "ewogIC4uLgp9".into(),
+                    signature: Some("c2lnbmVk".into()),
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_has_not_joined() {
+        let (address, server) = stand_in_session_server("204 No Content", "");
+        let transport = StdTransport { address };
+        assert_eq!(block_on(has_joined(&transport, "Notch", "0", None)).unwrap(), None);
+        server.join().unwrap();
+
+        let (address, server) = stand_in_session_server("503 Service Unavailable", "");
+        let transport = StdTransport { address };
+        let result = block_on(has_joined(&transport, "Notch", "0", None));
+        assert!(matches!(result, Err(SessionErr::Status(503))));
+        server.join().unwrap();
+
+        let (address, server) = stand_in_session_server("200 OK", "{}");
+        let transport = StdTransport { address };
+        let result = block_on(has_joined(&transport, "Notch", "0", None));
+        assert!(matches!(result, Err(SessionErr::BadProfile(_))));
+        server.join().unwrap();
+    }
+}
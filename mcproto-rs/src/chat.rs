@@ -4,6 +4,7 @@ use serde::de::{Visitor, Error, IntoDeserializer, MapAccess};
 use serde::ser::SerializeMap;
 use serde_json::Value;
 use crate::{SerializeResult, DeserializeResult};
+use crate::nbt::{Tag, read_tag};
 
 pub type BoxedChat = Box<Chat>;
 
@@ -979,6 +980,147 @@ impl super::Deserialize for Chat {
     }
 }
 
+crate::borrow_as_owned!(Chat);
+
+// since 1.20.3 text components in play/configuration packets are sent as network nbt (an unnamed
+// root tag) instead of json. click and hover events are not carried over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NbtChat(pub Chat);
+
+impl NbtChat {
+    fn to_tag(chat: &Chat) -> Tag {
+        use Chat::*;
+
+        let base = chat.base();
+        if let Text(body) = chat {
+            if *base == BaseComponent::default() {
+                return Tag::String(body.text.clone());
+            }
+        }
+
+        let mut fields = Vec::new();
+        match chat {
+            Text(body) => fields.push(Tag::String(body.text.clone()).with_name("text")),
+            Translation(body) => {
+                fields.push(Tag::String(body.translate.clone()).with_name("translate"));
+                if !body.with.is_empty() {
+                    let with = body.with.iter().map(|arg| Self::to_compound(arg)).collect();
+                    fields.push(Tag::List(with).with_name("with"));
+                }
+            }
+            Keybind(body) => fields.push(Tag::String(body.keybind.clone()).with_name("keybind")),
+            Score(body) => {
+                let mut score = alloc::vec![Tag::String(body.score.name.clone()).with_name("name")];
+                if let Some(objective) = &body.score.objective {
+                    score.push(Tag::String(objective.clone()).with_name("objective"));
+                }
+                fields.push(Tag::Compound(score).with_name("score"));
+            }
+        }
+
+        for (flag, name) in [
+            (base.bold, "bold"),
+            (base.italic, "italic"),
+            (base.underlined, "underlined"),
+            (base.strikethrough, "strikethrough"),
+            (base.obfuscated, "obfuscated"),
+        ] {
+            if flag {
+                fields.push(Tag::Byte(1).with_name(name));
+            }
+        }
+        if let Some(color) = &base.color {
+            fields.push(Tag::String(color.name().to_owned()).with_name("color"));
+        }
+        if let Some(insertion) = &base.insertion {
+            fields.push(Tag::String(insertion.clone()).with_name("insertion"));
+        }
+        if !base.extra.is_empty() {
+            let extra = base.extra.iter().map(|sibling| Self::to_compound(sibling)).collect();
+            fields.push(Tag::List(extra).with_name("extra"));
+        }
+
+        Tag::Compound(fields)
+    }
+
+    // list elements must all share a tag type, so nested components are never collapsed to strings
+    fn to_compound(chat: &Chat) -> Tag {
+        match Self::to_tag(chat) {
+            Tag::String(text) => Tag::Compound(alloc::vec![Tag::String(text).with_name("text")]),
+            other => other,
+        }
+    }
+
+    fn from_tag(tag: Tag) -> Result<Chat, super::DeserializeErr> {
+        let fields = match tag {
+            Tag::String(text) => return Ok(Chat::from_text(text.as_str())),
+            Tag::Compound(fields) => fields,
+            other => return Err(super::DeserializeErr::CannotUnderstandValue(format!(
+                "cannot read chat from nbt tag {}", other
+            ))),
+        };
+
+        let mut base = BaseComponent::default();
+        let mut text = None;
+        let mut translate = None;
+        let mut with = Vec::new();
+        let mut keybind = None;
+        for field in fields {
+            match (field.name.as_str(), field.payload) {
+                ("text", Tag::String(v)) => text = Some(v),
+                ("translate", Tag::String(v)) => translate = Some(v),
+                ("keybind", Tag::String(v)) => keybind = Some(v),
+                ("with", Tag::List(args)) => for arg in args {
+                    with.push(Self::from_tag(arg)?.boxed());
+                },
+                ("extra", Tag::List(siblings)) => for sibling in siblings {
+                    base.extra.push(Self::from_tag(sibling)?.boxed());
+                },
+                ("bold", Tag::Byte(v)) => base.bold = v != 0,
+                ("italic", Tag::Byte(v)) => base.italic = v != 0,
+                ("underlined", Tag::Byte(v)) => base.underlined = v != 0,
+                ("strikethrough", Tag::Byte(v)) => base.strikethrough = v != 0,
+                ("obfuscated", Tag::Byte(v)) => base.obfuscated = v != 0,
+                ("color", Tag::String(v)) => base.color = ColorCode::from_name(v.as_str()),
+                ("insertion", Tag::String(v)) => base.insertion = Some(v),
+                _ => {}
+            }
+        }
+
+        Ok(if let Some(translate) = translate {
+            Chat::Translation(TranslationComponent { translate, with, base })
+        } else if let Some(keybind) = keybind {
+            Chat::Keybind(KeybindComponent { keybind, base })
+        } else {
+            Chat::Text(TextComponent { text: text.unwrap_or_default(), base })
+        })
+    }
+}
+
+impl From<Chat> for NbtChat {
+    fn from(chat: Chat) -> Self {
+        Self(chat)
+    }
+}
+
+impl super::Serialize for NbtChat {
+    fn mc_serialize<S: super::Serializer>(&self, to: &mut S) -> SerializeResult {
+        let tag = Self::to_tag(&self.0);
+        to.serialize_byte(tag.id())?;
+        to.serialize_bytes(tag.bytes().as_slice())
+    }
+}
+
+impl super::Deserialize for NbtChat {
+    fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
+        <u8 as super::Deserialize>::mc_deserialize(data)?
+            .and_then(move |tag_type, rest| read_tag(tag_type, rest))?
+            .try_map(move |tag| Self::from_tag(tag).map(NbtChat))
+    }
+}
+
+crate::borrow_as_owned!(NbtChat);
+
 #[cfg(all(test, feature = "std"))]
 use super::protocol::TestRandom;
 
@@ -990,6 +1132,13 @@ impl TestRandom for Chat {
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+impl TestRandom for NbtChat {
+    fn test_gen_random() -> Self {
+        NbtChat(Chat::test_gen_random())
+    }
+}
+
 fn read_event<'de, A>(
     access: &mut A,
 ) -> Result<(&'de str, Value), <A as MapAccess<'de>>::Error>
@@ -0,0 +1,459 @@
+use crate::{types::VarInt, DeserializeErr, Deserialized, Deserialize, Serialize};
+use crate::types::BytesSerializer;
+use alloc::{borrow::Cow, fmt, vec, vec::Vec};
+
+/// The largest uncompressed packet vanilla will accept (2^23 bytes)
+pub const MAX_UNCOMPRESSED_LENGTH: usize = 8 * 1024 * 1024;
+
+pub enum CompressionErr {
+    Deserialize(DeserializeErr),
+    TooLarge(usize),
+    BelowThreshold(usize),
+    Inflate,
+    LengthMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CompressionErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CompressionErr::*;
+        match self {
+            Deserialize(err) => f.write_fmt(format_args!("bad compressed frame: {}", err)),
+            TooLarge(length) => f.write_fmt(format_args!("uncompressed length {} is too large", length)),
+            BelowThreshold(length) => f.write_fmt(format_args!(
+                "compressed packet of {} bytes is below the threshold",
+                length
+            )),
+            Inflate => f.write_str("failed to inflate packet"),
+            LengthMismatch { expected, actual } => f.write_fmt(format_args!(
+                "packet inflated to {} bytes, expected {}",
+                actual, expected
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for CompressionErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        <dyn fmt::Display>::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CompressionErr {}
+
+impl From<DeserializeErr> for CompressionErr {
+    fn from(err: DeserializeErr) -> Self {
+        CompressionErr::Deserialize(err)
+    }
+}
+
+/// The compressed packet format, used once Set Compression has been sent. Each frame is a VarInt
+/// uncompressed length (0 if the packet was left uncompressed) followed by the zlib data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionCodec {
+    threshold: i32,
+    max_length: usize,
+}
+
+impl CompressionCodec {
+    /// Packets at least `threshold` bytes long get compressed. A negative threshold disables
+    /// compression, like it does in Set Compression
+    pub fn new(threshold: i32) -> Self {
+        Self {
+            threshold,
+            max_length: MAX_UNCOMPRESSED_LENGTH,
+        }
+    }
+
+    /// Refuses to inflate packets claiming to be longer than `max_length`, for when the vanilla
+    /// limit doesn't fit in memory
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    pub fn threshold(&self) -> i32 {
+        self.threshold
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold >= 0
+    }
+
+    /// Takes a serialized packet (id and body) and returns the frame contents that go after the
+    /// packet length
+    pub fn encode(&self, packet: &[u8]) -> Vec<u8> {
+        let mut out = BytesSerializer::with_capacity(packet.len() + 5);
+        if !self.is_enabled() || packet.len() < self.threshold as usize {
+            VarInt(0).mc_serialize(&mut out).expect("varint serialization can't fail");
+            let mut out = out.into_bytes();
+            out.extend_from_slice(packet);
+            return out;
+        }
+
+        VarInt(packet.len() as i32)
+            .mc_serialize(&mut out)
+            .expect("varint serialization can't fail");
+        let mut out = out.into_bytes();
+        zlib_compress_into(packet, &mut out);
+        out
+    }
+
+    /// The inverse of `encode`, borrowing when the packet wasn't compressed
+    pub fn decode<'a>(&self, frame: &'a [u8]) -> Result<Cow<'a, [u8]>, CompressionErr> {
+        let Deserialized { value: length, data } = VarInt::mc_deserialize(frame)?;
+        if length.0 == 0 {
+            return Ok(Cow::Borrowed(data));
+        }
+        if length.0 < 0 {
+            return Err(DeserializeErr::NegativeLength(length).into());
+        }
+
+        let expected = length.0 as usize;
+        if expected > self.max_length {
+            return Err(CompressionErr::TooLarge(expected));
+        }
+        if length.0 < self.threshold {
+            return Err(CompressionErr::BelowThreshold(expected));
+        }
+
+        let inflated = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, expected)
+            .map_err(|_| CompressionErr::Inflate)?;
+        if inflated.len() != expected {
+            return Err(CompressionErr::LengthMismatch {
+                expected,
+                actual: inflated.len(),
+            });
+        }
+        Ok(Cow::Owned(inflated))
+    }
+}
+
+// miniz_oxide's compressor keeps ~300 KiB of tables around, more than the whole heap on small
+// devices. This is a single-probe LZ77 with fixed huffman codes instead, its only state is the
+// hash table. Inflating is cheap so that side still goes through miniz_oxide
+const HASH_BITS: u32 = 11;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_DISTANCE: usize = 32768;
+const NO_POSITION: u32 = u32::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first, everything else least significant first
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn write_symbol(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol as u32, 8),
+            144..=255 => self.write_code(0x190 + (symbol as u32 - 144), 9),
+            256..=279 => self.write_code(symbol as u32 - 256, 7),
+            _ => self.write_code(0xC0 + (symbol as u32 - 280), 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap();
+        self.write_symbol(257 + index as u16);
+        self.write_bits((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+
+        let index = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap();
+        self.write_code(index as u32, 5);
+        self.write_bits(
+            (distance - DISTANCE_BASE[index] as usize) as u32,
+            DISTANCE_EXTRA[index] as u32,
+        );
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let key = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // Largest run that can't overflow before reducing
+    const RUN: usize = 5552;
+    let (mut a, mut b) = (1u32, 0u32);
+    for run in data.chunks(RUN) {
+        for byte in run {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+/// Deflates data that's all in memory a piece at a time, so its compressed stream never has to be
+/// held whole. The stream comes out the same however it's split into pieces, so one run can
+/// learn its length and a second write it out
+pub struct ZlibEncoder<'a> {
+    data: &'a [u8],
+    table: Vec<u32>,
+    position: usize,
+    bits: u64,
+    count: u32,
+    started: bool,
+    finished: bool,
+}
+
+impl<'a> ZlibEncoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            table: vec![NO_POSITION; 1 << HASH_BITS],
+            position: 0,
+            bits: 0,
+            count: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Appends the stream for at least the next `length` bytes of data to `out`, or for the rest
+    /// of it along with the trailer
+    pub fn encode(&mut self, length: usize, out: &mut Vec<u8>) {
+        if self.finished {
+            return;
+        }
+        let data = self.data;
+        let table = &mut self.table;
+        let mut position = self.position;
+        let mut writer = BitWriter {
+            out,
+            bits: self.bits,
+            count: self.count,
+        };
+        if !self.started {
+            // Deflate with a 32 KiB window, fastest level. The header has to be a multiple of 31
+            writer.out.extend_from_slice(&[0x78, 0x01]);
+            // Final block, fixed huffman codes
+            writer.write_bits(1, 1);
+            writer.write_bits(1, 2);
+            self.started = true;
+        }
+
+        let stop = position.saturating_add(length);
+        while position < stop && position + MIN_MATCH <= data.len() {
+            let slot = hash(&data[position..]);
+            let candidate = table[slot];
+            table[slot] = position as u32;
+
+            let length = if candidate != NO_POSITION && position - candidate as usize <= MAX_DISTANCE {
+                let candidate = candidate as usize;
+                let limit = MAX_MATCH.min(data.len() - position);
+                data[candidate..]
+                    .iter()
+                    .zip(&data[position..position + limit])
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            } else {
+                0
+            };
+
+            if length >= MIN_MATCH {
+                writer.write_match(length, position - candidate as usize);
+                for skipped in position + 1..(position + length).min(data.len() - MIN_MATCH + 1) {
+                    table[hash(&data[skipped..])] = skipped as u32;
+                }
+                position += length;
+            } else {
+                writer.write_symbol(data[position] as u16);
+                position += 1;
+            }
+        }
+
+        if position + MIN_MATCH > data.len() {
+            for byte in &data[position..] {
+                writer.write_symbol(*byte as u16);
+            }
+            position = data.len();
+            writer.write_symbol(256);
+            writer.flush();
+            writer.out.extend_from_slice(&adler32(data).to_be_bytes());
+            self.finished = true;
+        }
+        self.position = position;
+        self.bits = writer.bits;
+        self.count = writer.count;
+    }
+}
+
+/// Appends `data` as a zlib stream
+pub fn zlib_compress_into(data: &[u8], out: &mut Vec<u8>) {
+    ZlibEncoder::new(data).encode(data.len(), out);
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    zlib_compress_into(data, &mut out);
+    out
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn inflate_with_flate2(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        flate2::read::ZlibDecoder::new(data)
+            .read_to_end(&mut out)
+            .expect("valid zlib stream");
+        out
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabcabc".to_vec(),
+            vec![0u8; 100_000],
+            (0..70_000u32).map(|i| (i * 7 % 251) as u8).collect(),
+            (0..5000u32).map(|_| rand::random::<u8>()).collect(),
+            // Long runs that only match far back, past the 32 KiB window
+            (0..100_000u32).map(|i| if i % 40_000 < 20 { 1 } else { (i % 13) as u8 }).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_zlib_is_readable_by_flate2() {
+        for sample in samples() {
+            assert_eq!(inflate_with_flate2(&zlib_compress(&sample)), sample);
+        }
+    }
+
+    #[test]
+    fn test_compresses_repetitive_data() {
+        let chunk = vec![0u8; 16384];
+        assert!(zlib_compress(&chunk).len() < 200);
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let codec = CompressionCodec::new(256);
+        for sample in samples() {
+            let frame = codec.encode(&sample);
+            assert_eq!(codec.decode(&frame).expect("decodes").as_ref(), sample.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_codec_threshold() {
+        let codec = CompressionCodec::new(256);
+        let small = vec![1u8; 255];
+        let frame = codec.encode(&small);
+        assert_eq!(frame[0], 0);
+        assert_eq!(&frame[1..], small.as_slice());
+        assert!(matches!(codec.decode(&frame).unwrap(), Cow::Borrowed(_)));
+
+        let large = vec![1u8; 256];
+        let frame = codec.encode(&large);
+        assert_ne!(frame[0], 0);
+        assert!(frame.len() < large.len());
+
+        // Compressed packets under the threshold are a protocol violation
+        let frame = CompressionCodec::new(0).encode(&small);
+        assert!(matches!(codec.decode(&frame), Err(CompressionErr::BelowThreshold(255))));
+    }
+
+    #[test]
+    fn test_codec_rejects_bad_lengths() {
+        let codec = CompressionCodec::new(0);
+        let mut frame = codec.encode(&[5u8; 300]);
+        // Claim one more byte than was compressed
+        frame[0] += 1;
+        assert!(codec.decode(&frame).is_err());
+
+        let frame = codec.encode(&[5u8; 300]);
+        let codec = codec.with_max_length(299);
+        assert!(matches!(codec.decode(&frame), Err(CompressionErr::TooLarge(300))));
+    }
+
+    #[test]
+    fn test_pieces_match_the_whole_stream() {
+        for sample in samples() {
+            let whole = zlib_compress(&sample);
+            for piece in [1, 100, 4096] {
+                let mut encoder = ZlibEncoder::new(&sample);
+                let mut pieces = Vec::new();
+                while !encoder.is_finished() {
+                    let before = pieces.len();
+                    encoder.encode(piece, &mut pieces);
+                    // Never much more than the piece, even for data that doesn't compress
+                    assert!(pieces.len() - before <= piece * 9 / 8 + MAX_MATCH * 2 + 16);
+                }
+                assert_eq!(pieces, whole);
+            }
+        }
+    }
+
+    #[test]
+    fn test_codec_reads_flate2_frames() {
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::io::Write;
+
+        let packet = vec![9u8; 1000];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&packet).unwrap();
+        let mut frame = BytesSerializer::default();
+        VarInt(packet.len() as i32).mc_serialize(&mut frame).unwrap();
+        let mut frame = frame.into_bytes();
+        frame.extend_from_slice(&encoder.finish().unwrap());
+
+        let codec = CompressionCodec::new(256);
+        assert_eq!(codec.decode(&frame).unwrap().as_ref(), packet.as_slice());
+    }
+}
@@ -0,0 +1,63 @@
+//! Signed cookies, so state a server hands the client to carry across a transfer comes back
+//! unchanged. Clients store cookies as given and send them to any server that asks, so anything
+//! trusted in one has to be signed.
+//!
+//! A signed cookie is an HMAC-SHA256 over the key and the payload, then the payload itself.
+
+use crate::hash::{hmac_sha256, hmac_sha256_matches, HMAC_SHA256_LENGTH};
+use alloc::vec::Vec;
+
+/// The most a client stores under one key, signature included
+pub const MAX_COOKIE_LENGTH: usize = 5120;
+/// Room left for the payload of a signed cookie
+pub const MAX_SIGNED_PAYLOAD: usize = MAX_COOKIE_LENGTH - HMAC_SHA256_LENGTH;
+
+// The key is signed too, so a cookie can't be moved to a key that means something else
+fn signed_message(key: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(key.len() + 1 + payload.len());
+    message.extend_from_slice(key.as_bytes());
+    message.push(0);
+    message.extend_from_slice(payload);
+    message
+}
+
+/// The cookie to store under `key`, `None` if the payload is too long for a client to keep
+pub fn sign(key: &str, payload: &[u8], secret: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() > MAX_SIGNED_PAYLOAD {
+        return None;
+    }
+    let mut cookie = hmac_sha256(secret, &signed_message(key, payload)).to_vec();
+    cookie.extend_from_slice(payload);
+    Some(cookie)
+}
+
+/// The payload of a cookie the client sent back under `key`, `None` unless it was signed with
+/// `secret`
+pub fn verify<'a>(key: &str, cookie: &'a [u8], secret: &[u8]) -> Option<&'a [u8]> {
+    if cookie.len() < HMAC_SHA256_LENGTH {
+        return None;
+    }
+    let (signature, payload) = cookie.split_at(HMAC_SHA256_LENGTH);
+    hmac_sha256_matches(secret, &signed_message(key, payload), signature).then_some(payload)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_cookies() {
+        let cookie = sign("blockchain:transfer", b"state", b"secret").unwrap();
+        assert_eq!(verify("blockchain:transfer", &cookie, b"secret"), Some(&b"state"[..]));
+
+        assert_eq!(verify("blockchain:transfer", &cookie, b"other"), None);
+        assert_eq!(verify("blockchain:other", &cookie, b"secret"), None);
+        let mut tampered = cookie.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(verify("blockchain:transfer", &tampered, b"secret"), None);
+        assert_eq!(verify("blockchain:transfer", &cookie[..8], b"secret"), None);
+
+        assert!(sign("key", &[0; MAX_SIGNED_PAYLOAD], b"secret").is_some());
+        assert!(sign("key", &[0; MAX_SIGNED_PAYLOAD + 1], b"secret").is_none());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::types::VarInt;
 use alloc::{vec::Vec, string::{FromUtf8Error, String}, fmt};
+#[cfg(feature = "error-context")]
+use alloc::boxed::Box;
 
 pub enum DeserializeErr {
     Eof,
@@ -12,6 +14,12 @@ pub enum DeserializeErr {
     NbtInvalidStartTag(u8),
     CannotUnderstandValue(String),
     FailedJsonDeserialize(String),
+    TooLong { length: usize, max: usize },
+    AllocationBudgetExceeded { requested: usize, remaining: usize },
+    /// Where in a packet another error happened: the packet and field names leading to the
+    /// field that failed, and the offset that field starts at
+    #[cfg(feature = "error-context")]
+    InField { path: Vec<&'static str>, offset: usize, source: Box<DeserializeErr> },
 }
 
 impl fmt::Display for DeserializeErr {
@@ -44,6 +52,23 @@ impl fmt::Display for DeserializeErr {
             FailedJsonDeserialize(data) => {
                 f.write_fmt(format_args!("failed to deserialize json: {:?}", data))
             }
+            TooLong { length, max } => {
+                f.write_fmt(format_args!("length {} is over the maximum of {}", length, max))
+            }
+            AllocationBudgetExceeded { requested, remaining } => f.write_fmt(format_args!(
+                "allocation of {} bytes is over the remaining budget of {}",
+                requested, remaining
+            )),
+            #[cfg(feature = "error-context")]
+            InField { path, offset, source } => {
+                for (index, name) in path.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(".")?;
+                    }
+                    f.write_str(name)?;
+                }
+                f.write_fmt(format_args!(" at byte {}: {}", offset, source))
+            }
         }
     }
 }
@@ -56,6 +81,44 @@ impl fmt::Debug for DeserializeErr {
 #[cfg(feature = "std")]
 impl std::error::Error for DeserializeErr {}
 
+impl DeserializeErr {
+    /// Records that this error happened in the field `name`, which starts `offset` bytes into
+    /// whatever contains it. Does nothing without the `error-context` feature
+    #[allow(unused_variables)]
+    pub fn in_field(self, name: &'static str, offset: usize) -> Self {
+        #[cfg(feature = "error-context")]
+        {
+            match self {
+                DeserializeErr::InField { mut path, offset: inner, source } => {
+                    path.insert(0, name);
+                    DeserializeErr::InField { path, offset: offset + inner, source }
+                }
+                other => DeserializeErr::InField {
+                    path: alloc::vec![name],
+                    offset,
+                    source: Box::new(other),
+                },
+            }
+        }
+        #[cfg(not(feature = "error-context"))]
+        self
+    }
+
+    /// The error without any field context around it
+    pub fn root_cause(&self) -> &DeserializeErr {
+        match self {
+            #[cfg(feature = "error-context")]
+            DeserializeErr::InField { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+}
+
+/// `in_field` for the result of deserializing a field that starts `offset` bytes in
+pub fn with_field_context<T>(result: Result<T, DeserializeErr>, name: &'static str, offset: usize) -> Result<T, DeserializeErr> {
+    result.map_err(|err| err.in_field(name, offset))
+}
+
 impl<'b, R> Into<DeserializeResult<'b, R>> for DeserializeErr {
     fn into(self) -> DeserializeResult<'b, R> {
         Err(self)
@@ -132,3 +195,190 @@ pub type DeserializeResult<'b, R> = Result<Deserialized<'b, R>, DeserializeErr>;
 pub trait Deserialize: Sized {
     fn mc_deserialize(data: &[u8]) -> DeserializeResult<Self>;
 }
+
+/// Deserializes with a tighter length cap than the type's protocol maximum, for fields marked
+/// `#[max_length = N]` in `define_protocol!`. Strings count characters, arrays count elements
+pub trait DeserializeLimited: Deserialize {
+    fn mc_deserialize_limited(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self>;
+}
+
+pub fn check_length(length: usize, max: usize) -> Result<(), DeserializeErr> {
+    if length > max {
+        Err(DeserializeErr::TooLong { length, max })
+    } else {
+        Ok(())
+    }
+}
+
+// Length prefixes come straight off the wire, so what deserializing a packet may allocate is
+// capped by a budget. It has to live outside the `Deserialize` calls, which take nothing but the
+// data. Without std there are no threads, deserialization never yields, and the budget is a
+// plain static
+#[cfg(feature = "std")]
+std::thread_local! {
+    static ALLOCATION_BUDGET: core::cell::Cell<Option<usize>> = const { core::cell::Cell::new(None) };
+}
+
+#[cfg(feature = "std")]
+fn allocation_budget() -> Option<usize> {
+    ALLOCATION_BUDGET.with(|budget| budget.get())
+}
+
+#[cfg(feature = "std")]
+fn set_allocation_budget(value: Option<usize>) {
+    ALLOCATION_BUDGET.with(|budget| budget.set(value))
+}
+
+#[cfg(not(feature = "std"))]
+static ALLOCATION_BUDGET: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(NO_BUDGET);
+
+#[cfg(not(feature = "std"))]
+const NO_BUDGET: usize = usize::MAX;
+
+#[cfg(not(feature = "std"))]
+fn allocation_budget() -> Option<usize> {
+    match ALLOCATION_BUDGET.load(core::sync::atomic::Ordering::Relaxed) {
+        NO_BUDGET => None,
+        remaining => Some(remaining),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn set_allocation_budget(value: Option<usize>) {
+    ALLOCATION_BUDGET.store(value.unwrap_or(NO_BUDGET), core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Runs `f` with at most `budget` bytes of allocations allowed to the strings, arrays and byte
+/// buffers it deserializes. Going over fails with `AllocationBudgetExceeded`
+pub fn with_allocation_budget<R>(budget: usize, f: impl FnOnce() -> R) -> R {
+    let outer = allocation_budget();
+    let budget = outer.map_or(budget, |outer| outer.min(budget));
+    set_allocation_budget(Some(budget));
+    let result = f();
+    let used = budget - allocation_budget().unwrap_or(budget);
+    set_allocation_budget(outer.map(|outer| outer - used));
+    result
+}
+
+/// Takes `bytes` out of the current budget, if there is one
+pub fn charge_allocation(bytes: usize) -> Result<(), DeserializeErr> {
+    match allocation_budget() {
+        Some(remaining) if bytes > remaining => Err(DeserializeErr::AllocationBudgetExceeded {
+            requested: bytes,
+            remaining,
+        }),
+        Some(remaining) => {
+            set_allocation_budget(Some(remaining - bytes));
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Deserialization that borrows strings and byte arrays from the input instead of copying them
+/// out. `Borrowed` is the form `Self` takes in a borrowed packet body, types with nothing to
+/// borrow use themselves
+pub trait DeserializeBorrowed<'a>: Sized {
+    type Borrowed: fmt::Debug + Clone + PartialEq;
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, Self::Borrowed>;
+
+    fn from_borrowed(borrowed: Self::Borrowed) -> Self;
+}
+
+/// `DeserializeLimited` for borrowed packet bodies
+pub trait DeserializeBorrowedLimited<'a>: DeserializeBorrowed<'a> {
+    fn mc_deserialize_borrowed_limited(data: &'a [u8], max_length: usize) -> DeserializeResult<'a, Self::Borrowed>;
+}
+
+/// How a counted array of `Self` is borrowed. Byte arrays become one slice of the input, other
+/// elements are borrowed one at a time
+pub trait DeserializeBorrowedElements<'a>: Sized {
+    type Elements: fmt::Debug + Clone + PartialEq;
+
+    fn mc_deserialize_elements(count: usize, data: &'a [u8]) -> DeserializeResult<'a, Self::Elements>;
+
+    fn elements_to_vec(elements: Self::Elements) -> Vec<Self>;
+}
+
+pub fn deserialize_borrowed_elements<'a, T>(count: usize, mut data: &'a [u8]) -> DeserializeResult<'a, Vec<T::Borrowed>>
+where
+    T: DeserializeBorrowed<'a>,
+{
+    charge_allocation(count.saturating_mul(core::mem::size_of::<T::Borrowed>()))?;
+    // Every element takes at least a byte, don't trust the count any further than that
+    let mut elements = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        let Deserialized { value, data: rest } = T::mc_deserialize_borrowed(data)?;
+        data = rest;
+        elements.push(value);
+    }
+    Deserialized::ok(elements, data)
+}
+
+/// Implements the borrowed traits for types that have nothing to borrow
+#[macro_export]
+macro_rules! borrow_as_owned {
+    ($($typ: ty),+ $(,)?) => {
+        $(
+            impl<'a> $crate::DeserializeBorrowed<'a> for $typ {
+                type Borrowed = Self;
+
+                fn mc_deserialize_borrowed(data: &'a [u8]) -> $crate::DeserializeResult<'a, Self> {
+                    <Self as $crate::Deserialize>::mc_deserialize(data)
+                }
+
+                fn from_borrowed(borrowed: Self) -> Self {
+                    borrowed
+                }
+            }
+
+            $crate::borrow_elements!($typ);
+        )+
+    };
+}
+
+/// Lets counted arrays of these types borrow each element
+#[macro_export]
+macro_rules! borrow_elements {
+    ($($typ: ty),+ $(,)?) => {
+        $(
+            impl<'a> $crate::DeserializeBorrowedElements<'a> for $typ {
+                type Elements = alloc::vec::Vec<<Self as $crate::DeserializeBorrowed<'a>>::Borrowed>;
+
+                fn mc_deserialize_elements(count: usize, data: &'a [u8]) -> $crate::DeserializeResult<'a, Self::Elements> {
+                    $crate::deserialize_borrowed_elements::<Self>(count, data)
+                }
+
+                fn elements_to_vec(elements: Self::Elements) -> alloc::vec::Vec<Self> {
+                    elements
+                        .into_iter()
+                        .map(<Self as $crate::DeserializeBorrowed<'a>>::from_borrowed)
+                        .collect()
+                }
+            }
+        )+
+    };
+}
+
+/// Reads a field declared in `define_protocol!` or `proto_struct!`, applying its `max_length`
+#[macro_export]
+macro_rules! deserialize_field {
+    ($data: expr, $typ: ty) => {
+        <$typ as $crate::Deserialize>::mc_deserialize($data)
+    };
+    ($data: expr, $typ: ty, $max: literal) => {
+        <$typ as $crate::DeserializeLimited>::mc_deserialize_limited($data, $max)
+    };
+}
+
+/// `deserialize_field!` for borrowed packet bodies
+#[macro_export]
+macro_rules! deserialize_borrowed_field {
+    ($data: expr, $typ: ty) => {
+        <$typ as $crate::DeserializeBorrowed<'_>>::mc_deserialize_borrowed($data)
+    };
+    ($data: expr, $typ: ty, $max: literal) => {
+        <$typ as $crate::DeserializeBorrowedLimited<'_>>::mc_deserialize_borrowed_limited($data, $max)
+    };
+}
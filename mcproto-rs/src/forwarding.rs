@@ -0,0 +1,182 @@
+//! Player info forwarded by a proxy, so a server behind one sees the real player rather than the
+//! proxy's connection.
+//!
+//! Velocity's modern forwarding answers a login plugin request on `velocity:player_info` with the
+//! info signed by a secret shared with the server. BungeeCord's legacy forwarding appends it to
+//! the handshake's server address, unsigned, so only the proxy must be able to reach the server.
+
+use crate::{
+    auth::ProfileProperty,
+    hash::{hmac_sha256_matches, HMAC_SHA256_LENGTH},
+    types::VarInt,
+    uuid::UUID4,
+    v1_21_8::LoginSuccessProperty,
+    Deserialize, Deserialized, McDeserialize,
+};
+use alloc::{fmt, format, string::String, vec::Vec};
+
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+/// The only forwarding version we ask for, it carries no chat signing keys
+pub const VELOCITY_FORWARDING_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardedPlayer {
+    /// The player's own ip, as the proxy saw it
+    pub address: String,
+    pub uuid: UUID4,
+    /// Velocity sends the name, with BungeeCord it comes from Login Start as usual
+    pub username: Option<String>,
+    pub properties: Vec<LoginSuccessProperty>,
+}
+
+#[derive(Debug)]
+pub enum ForwardingErr {
+    /// Velocity's signature doesn't match, the secrets differ or the info was forged
+    BadSignature,
+    UnsupportedVersion(i32),
+    /// BungeeCord's fields are missing, so the proxy isn't forwarding
+    NotForwarded,
+    Malformed(String),
+}
+
+impl fmt::Display for ForwardingErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardingErr::BadSignature => f.write_str("forwarded player info has a bad signature"),
+            ForwardingErr::UnsupportedVersion(version) => {
+                f.write_fmt(format_args!("unsupported forwarding version {}", version))
+            }
+            ForwardingErr::NotForwarded => f.write_str("no forwarded player info"),
+            ForwardingErr::Malformed(err) => f.write_fmt(format_args!("malformed forwarded player info: {}", err)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ForwardingErr {}
+
+#[derive(McDeserialize)]
+struct VelocityPlayerInfo {
+    #[mc(varint)]
+    version: i32,
+    address: String,
+    uuid: UUID4,
+    #[mc(max_length = 16)]
+    username: String,
+    #[mc(count = VarInt)]
+    properties: Vec<LoginSuccessProperty>,
+}
+
+/// Checks and reads the data of a login plugin response on `VELOCITY_CHANNEL`: an HMAC-SHA256
+/// signature, then the player info it signs
+pub fn parse_velocity(data: &[u8], secret: &[u8]) -> Result<ForwardedPlayer, ForwardingErr> {
+    if data.len() < HMAC_SHA256_LENGTH {
+        return Err(ForwardingErr::BadSignature);
+    }
+    let (signature, info) = data.split_at(HMAC_SHA256_LENGTH);
+    if !hmac_sha256_matches(secret, info, signature) {
+        return Err(ForwardingErr::BadSignature);
+    }
+
+    let Deserialized { value: info, .. } = VelocityPlayerInfo::mc_deserialize(info)
+        .map_err(|err| ForwardingErr::Malformed(format!("{}", err)))?;
+    if info.version != VELOCITY_FORWARDING_VERSION as i32 {
+        return Err(ForwardingErr::UnsupportedVersion(info.version));
+    }
+    Ok(ForwardedPlayer {
+        address: info.address,
+        uuid: info.uuid,
+        username: Some(info.username),
+        properties: info.properties,
+    })
+}
+
+/// Splits a handshake's server address forwarded by BungeeCord, `host\0ip\0uuid\0properties`
+/// where the properties are JSON and may be left off. Returns the host and the player
+pub fn parse_bungeecord(server_address: &str) -> Result<(&str, ForwardedPlayer), ForwardingErr> {
+    let mut fields = server_address.split('\0');
+    let (Some(host), Some(address), Some(uuid)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(ForwardingErr::NotForwarded);
+    };
+    let uuid = UUID4::parse(uuid).ok_or_else(|| ForwardingErr::Malformed(format!("bad uuid {}", uuid)))?;
+    let properties = match fields.next() {
+        Some(json) => serde_json::from_str::<Vec<ProfileProperty>>(json)
+            .map_err(|err| ForwardingErr::Malformed(format!("{}", err)))?
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let player = ForwardedPlayer {
+        address: address.into(),
+        uuid,
+        username: None,
+        properties,
+    };
+    Ok((host, player))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{hash::hmac_sha256, types::BytesSerializer, Serialize};
+
+    fn velocity_info(version: i32) -> Vec<u8> {
+        let mut out = BytesSerializer::default();
+        VarInt(version).mc_serialize(&mut out).unwrap();
+        String::from("10.0.0.2").mc_serialize(&mut out).unwrap();
+        UUID4::from(7).mc_serialize(&mut out).unwrap();
+        String::from("alice").mc_serialize(&mut out).unwrap();
+        VarInt(1).mc_serialize(&mut out).unwrap();
+        let property = LoginSuccessProperty {
+            name: "textures".into(),
+            value: "e30=".into(),
+            signature: Some("c2ln".into()),
+        };
+        property.mc_serialize(&mut out).unwrap();
+        out.into_bytes()
+    }
+
+    fn signed(secret: &[u8], info: &[u8]) -> Vec<u8> {
+        [&hmac_sha256(secret, info)[..], info].concat()
+    }
+
+    #[test]
+    fn test_parse_velocity() {
+        let info = velocity_info(1);
+        let player = parse_velocity(&signed(b"secret", &info), b"secret").unwrap();
+        assert_eq!(player.address, "10.0.0.2");
+        assert_eq!(player.uuid, UUID4::from(7));
+        assert_eq!(player.username.as_deref(), Some("alice"));
+        assert_eq!(player.properties.len(), 1);
+        assert_eq!(player.properties[0].signature.as_deref(), Some("c2ln"));
+
+        assert!(matches!(parse_velocity(&signed(b"other", &info), b"secret"), Err(ForwardingErr::BadSignature)));
+        let mut tampered = signed(b"secret", &info);
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(parse_velocity(&tampered, b"secret"), Err(ForwardingErr::BadSignature)));
+        assert!(matches!(parse_velocity(&[0; 4], b"secret"), Err(ForwardingErr::BadSignature)));
+
+        let newer = signed(b"secret", &velocity_info(4));
+        assert!(matches!(parse_velocity(&newer, b"secret"), Err(ForwardingErr::UnsupportedVersion(4))));
+    }
+
+    #[test]
+    fn test_parse_bungeecord() {
+        let address = "play.example.com\u{0}10.0.0.2\u{0}069a79f444e94726a5befca90e38aaf5\u{0}\
+            [{\"name\":\"textures\",\"value\":\"e30=\",\"signature\":\"c2ln\"}]";
+        let (host, player) = parse_bungeecord(address).unwrap();
+        assert_eq!(host, "play.example.com");
+        assert_eq!(player.address, "10.0.0.2");
+        assert_eq!(player.uuid, UUID4::parse("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap());
+        assert_eq!(player.username, None);
+        assert_eq!(player.properties[0].name, "textures");
+
+        let (_, player) = parse_bungeecord("host\u{0}10.0.0.2\u{0}069a79f444e94726a5befca90e38aaf5").unwrap();
+        assert!(player.properties.is_empty());
+
+        assert!(matches!(parse_bungeecord("play.example.com"), Err(ForwardingErr::NotForwarded)));
+        assert!(matches!(parse_bungeecord("host\u{0}10.0.0.2\u{0}nope"), Err(ForwardingErr::Malformed(_))));
+    }
+}
@@ -0,0 +1,594 @@
+use crate::protocol::{Id, PacketDirection, State};
+use crate::types::VarInt;
+use crate::{Deserialize, DeserializeErr, Deserialized};
+#[cfg(feature = "compression")]
+use crate::compression::{CompressionCodec, CompressionErr};
+use alloc::{borrow::Cow, fmt, vec::Vec};
+
+/// Frames longer than this are streamed instead of buffered, unless configured otherwise
+pub const DEFAULT_MAX_BUFFERED: usize = 32 * 1024;
+/// The largest frame vanilla will send (2^21 - 1 bytes, the most a 3 byte VarInt can hold)
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = (1 << 21) - 1;
+// Enough to hold the data length and packet id of a frame being streamed
+const STREAM_HEAD_LENGTH: usize = 10;
+
+pub enum FrameErr {
+    Deserialize(DeserializeErr),
+    TooLarge(usize),
+    #[cfg(feature = "compression")]
+    Compression(CompressionErr),
+}
+
+impl fmt::Display for FrameErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use FrameErr::*;
+        match self {
+            Deserialize(err) => f.write_fmt(format_args!("bad frame: {}", err)),
+            TooLarge(length) => f.write_fmt(format_args!("frame of {} bytes is too large", length)),
+            #[cfg(feature = "compression")]
+            Compression(err) => f.write_fmt(format_args!("bad compressed frame: {}", err)),
+        }
+    }
+}
+
+impl fmt::Debug for FrameErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        <dyn fmt::Display>::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameErr {}
+
+impl From<DeserializeErr> for FrameErr {
+    fn from(err: DeserializeErr) -> Self {
+        FrameErr::Deserialize(err)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl From<CompressionErr> for FrameErr {
+    fn from(err: CompressionErr) -> Self {
+        FrameErr::Compression(err)
+    }
+}
+
+/// A whole packet, its body starts right after the id
+pub struct Frame<'a> {
+    pub id: Id,
+    pub body: Cow<'a, [u8]>,
+}
+
+pub enum FrameEvent<'a> {
+    Frame(Frame<'a>),
+    /// A frame too large to buffer. Its body follows in `LargeChunk`s, then `LargeEnd`
+    LargeStart { id: Id, length: usize },
+    LargeChunk(&'a [u8]),
+    LargeEnd,
+    /// A compressed frame too large to buffer. Its `length` bytes are dropped as they come in,
+    /// inflating it would take a ~40 KiB window
+    #[cfg(feature = "compression")]
+    Skipped { length: usize },
+}
+
+#[derive(Clone, Copy)]
+enum Pending {
+    /// Waiting for the rest of a frame whose length prefix has been read
+    Buffered { header: usize, length: usize },
+    /// Waiting for the head of a frame that will be streamed
+    StreamHead { header: usize, length: usize },
+    Streaming { remaining: usize },
+    /// A compressed frame too large to buffer, `Skipped` hasn't been returned for it yet
+    #[cfg(feature = "compression")]
+    SkipStart { length: usize },
+    #[cfg(feature = "compression")]
+    Skipping { remaining: usize },
+    StreamEnd,
+}
+
+/// Splits a byte stream into packet frames. Bytes can come in any sized pieces, a length prefix
+/// split between two reads is fine
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    pending: Option<Pending>,
+    direction: PacketDirection,
+    state: State,
+    max_buffered: usize,
+    max_frame_length: usize,
+    #[cfg(feature = "compression")]
+    compression: Option<CompressionCodec>,
+}
+
+impl FrameDecoder {
+    /// `direction` is the direction of the packets being decoded, so `ServerBound` on a server
+    pub fn new(direction: PacketDirection) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            end: 0,
+            pending: None,
+            direction,
+            state: State::Handshaking,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+
+    /// Frames up to `max_buffered` bytes are returned whole, larger ones up to
+    /// `max_frame_length` are streamed, anything larger is an error. Compressed frames are
+    /// returned whole only if they also inflate to no more than `max_buffered`, otherwise they're
+    /// skipped without being inflated
+    pub fn with_limits(mut self, max_buffered: usize, max_frame_length: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// The state used for the ids of the frames that follow
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, compression: Option<CompressionCodec>) {
+        self.compression = compression;
+    }
+
+    /// Nothing buffered and not part way through a frame
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end && self.pending.is_none()
+    }
+
+    /// Space for at most `length` more bytes, to read straight into. Follow with `commit`
+    pub fn spare(&mut self, length: usize) -> &mut [u8] {
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.buffer.len() < self.end + length {
+            self.buffer.resize(self.end + length, 0);
+        }
+        &mut self.buffer[self.end..self.end + length]
+    }
+
+    /// Marks `length` bytes written to `spare` as received. `transform` sees them first, which is
+    /// where a stream cipher decrypts them
+    pub fn commit<F: FnOnce(&mut [u8])>(&mut self, length: usize, transform: F) {
+        transform(&mut self.buffer[self.end..self.end + length]);
+        self.end += length;
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.push_with(data, |_| {});
+    }
+
+    pub fn push_with<F: FnOnce(&mut [u8])>(&mut self, data: &[u8], transform: F) {
+        self.spare(data.len()).copy_from_slice(data);
+        self.commit(data.len(), transform);
+    }
+
+    fn buffered(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    /// Whether `next_event` has something to return. Errors are the same as `next_event` would give
+    pub fn ready(&mut self) -> Result<bool, FrameErr> {
+        let pending = match self.pending {
+            Some(pending) => pending,
+            None => match self.read_header()? {
+                Some(pending) => pending,
+                None => return Ok(false),
+            },
+        };
+        self.pending = Some(pending);
+
+        let available = self.end - self.start;
+        #[cfg(feature = "compression")]
+        if let Pending::Skipping { remaining } = pending {
+            let taken = remaining.min(available);
+            self.start += taken;
+            if taken < remaining {
+                self.pending = Some(Pending::Skipping { remaining: remaining - taken });
+                return Ok(false);
+            }
+            self.pending = None;
+            return self.ready();
+        }
+        Ok(match pending {
+            Pending::Buffered { header, length } => available >= header + length,
+            Pending::StreamHead { header, length } => {
+                available >= header + length.min(STREAM_HEAD_LENGTH)
+            }
+            Pending::Streaming { .. } => available > 0,
+            #[cfg(feature = "compression")]
+            Pending::SkipStart { .. } => true,
+            #[cfg(feature = "compression")]
+            Pending::Skipping { .. } => unreachable!("skipped above"),
+            Pending::StreamEnd => true,
+        })
+    }
+
+    fn read_header(&mut self) -> Result<Option<Pending>, FrameErr> {
+        loop {
+            let buffered = self.buffered();
+            let (length, header) = match VarInt::mc_deserialize(buffered) {
+                Ok(Deserialized { value, data }) => (value.0, buffered.len() - data.len()),
+                Err(DeserializeErr::Eof) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            if length < 0 {
+                return Err(DeserializeErr::NegativeLength(VarInt(length)).into());
+            }
+            let length = length as usize;
+            if length == 0 {
+                // Nothing to decode, skip it
+                self.start += header;
+                continue;
+            }
+            if length > self.max_frame_length {
+                return Err(FrameErr::TooLarge(length));
+            }
+
+            #[cfg(feature = "compression")]
+            if self.compression.is_some() {
+                let frame = &buffered[header..buffered.len().min(header + length)];
+                let data_length = match VarInt::mc_deserialize(frame) {
+                    Ok(Deserialized { value, .. }) => value.0,
+                    Err(DeserializeErr::Eof) if frame.len() < length => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                };
+                if data_length < 0 {
+                    return Err(DeserializeErr::NegativeLength(VarInt(data_length)).into());
+                }
+                let data_length = data_length as usize;
+                // Compressed frames are skipped whenever either side doesn't fit the buffer
+                if data_length != 0 && (data_length > self.max_buffered || length > self.max_buffered) {
+                    self.start += header;
+                    return Ok(Some(Pending::SkipStart { length }));
+                }
+            }
+
+            return Ok(Some(if length <= self.max_buffered {
+                Pending::Buffered { header, length }
+            } else {
+                Pending::StreamHead { header, length }
+            }));
+        }
+    }
+
+    /// Large frames that were compressed are skipped instead, so this one was sent uncompressed
+    fn skip_data_length<'b>(&self, head: &'b [u8]) -> Result<&'b [u8], FrameErr> {
+        #[cfg(feature = "compression")]
+        if self.compression.is_some() {
+            return Ok(VarInt::mc_deserialize(head)?.data);
+        }
+        Ok(head)
+    }
+
+    fn id(&self, id: VarInt) -> Id {
+        Id {
+            id: id.0,
+            state: self.state,
+            direction: self.direction,
+        }
+    }
+
+    /// The next frame, or piece of a large frame, if enough has been received
+    pub fn next_event(&mut self) -> Result<Option<FrameEvent<'_>>, FrameErr> {
+        if !self.ready()? {
+            return Ok(None);
+        }
+
+        match self.pending.take().expect("ready decoder has a pending frame") {
+            Pending::Buffered { header, length } => {
+                let from = self.start + header;
+                self.start = from + length;
+                let frame = &self.buffer[from..from + length];
+
+                // Frames that inflate past `max_buffered` were skipped instead, and decoding
+                // never inflates past the data length
+                #[cfg(feature = "compression")]
+                let frame = match &self.compression {
+                    Some(codec) => codec.decode(frame)?,
+                    None => Cow::Borrowed(frame),
+                };
+                #[cfg(not(feature = "compression"))]
+                let frame = Cow::Borrowed(frame);
+
+                let (id, body) = match frame {
+                    Cow::Borrowed(frame) => {
+                        let Deserialized { value, data } = VarInt::mc_deserialize(frame)?;
+                        (value, Cow::Borrowed(data))
+                    }
+                    Cow::Owned(mut frame) => {
+                        let Deserialized { value, data } = VarInt::mc_deserialize(&frame)?;
+                        let id_length = frame.len() - data.len();
+                        frame.drain(..id_length);
+                        (value, Cow::Owned(frame))
+                    }
+                };
+                Ok(Some(FrameEvent::Frame(Frame {
+                    id: self.id(id),
+                    body,
+                })))
+            }
+            Pending::StreamHead { header, length } => {
+                let head = &self.buffer[self.start + header..self.end];
+                let rest = self.skip_data_length(head)?;
+                let Deserialized { value, data } = VarInt::mc_deserialize(rest)?;
+                let consumed = head.len() - data.len();
+                if consumed > length {
+                    return Err(DeserializeErr::Eof.into());
+                }
+
+                self.start += header + consumed;
+                let body = length - consumed;
+                self.pending = Some(if body > 0 {
+                    Pending::Streaming { remaining: body }
+                } else {
+                    Pending::StreamEnd
+                });
+                Ok(Some(FrameEvent::LargeStart {
+                    id: self.id(value),
+                    length: body,
+                }))
+            }
+            Pending::Streaming { remaining } => {
+                let from = self.start;
+                let taken = remaining.min(self.end - from);
+                self.start += taken;
+                self.pending = Some(if remaining > taken {
+                    Pending::Streaming { remaining: remaining - taken }
+                } else {
+                    Pending::StreamEnd
+                });
+                Ok(Some(FrameEvent::LargeChunk(&self.buffer[from..from + taken])))
+            }
+            #[cfg(feature = "compression")]
+            Pending::SkipStart { length } => {
+                self.pending = Some(Pending::Skipping { remaining: length });
+                Ok(Some(FrameEvent::Skipped { length }))
+            }
+            #[cfg(feature = "compression")]
+            Pending::Skipping { .. } => unreachable!("ready never stops on a skipped frame"),
+            Pending::StreamEnd => Ok(Some(FrameEvent::LargeEnd)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::types::BytesSerializer;
+    use crate::Serialize;
+
+    fn frame(id: i32, body: &[u8]) -> Vec<u8> {
+        let mut packet = BytesSerializer::default();
+        VarInt(id).mc_serialize(&mut packet).unwrap();
+        let mut packet = packet.into_bytes();
+        packet.extend_from_slice(body);
+        prefix(&packet)
+    }
+
+    fn prefix(data: &[u8]) -> Vec<u8> {
+        let mut out = BytesSerializer::default();
+        VarInt(data.len() as i32).mc_serialize(&mut out).unwrap();
+        let mut out = out.into_bytes();
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn expect_frame(decoder: &mut FrameDecoder, id: i32, body: &[u8]) {
+        match decoder.next_event().expect("decodes") {
+            Some(FrameEvent::Frame(frame)) => {
+                assert_eq!(frame.id.id, id);
+                assert_eq!(frame.body.as_ref(), body);
+            }
+            _ => panic!("expected a frame"),
+        }
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        // 300 bytes needs a two byte length prefix
+        let body = vec![7u8; 300];
+        let data = frame(0x12, &body);
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound);
+        for byte in &data[..data.len() - 1] {
+            decoder.push(&[*byte]);
+            assert!(decoder.next_event().unwrap().is_none());
+        }
+        decoder.push(&data[data.len() - 1..]);
+        expect_frame(&mut decoder, 0x12, &body);
+        assert!(decoder.next_event().unwrap().is_none());
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_many_frames_in_one_chunk() {
+        let mut data = frame(1, b"one");
+        data.extend(frame(2, b""));
+        data.extend(frame(3, b"three"));
+        data.extend(&frame(4, b"four")[..3]);
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound);
+        decoder.set_state(State::Play);
+        decoder.push(&data);
+        expect_frame(&mut decoder, 1, b"one");
+        expect_frame(&mut decoder, 2, b"");
+        expect_frame(&mut decoder, 3, b"three");
+        assert!(decoder.next_event().unwrap().is_none());
+
+        decoder.push(&frame(4, b"four")[3..]);
+        match decoder.next_event().unwrap() {
+            Some(FrameEvent::Frame(frame)) => assert_eq!(frame.id.state, State::Play),
+            _ => panic!("expected a frame"),
+        }
+    }
+
+    #[test]
+    fn test_transform_hook() {
+        let data = frame(5, b"secret");
+        let scrambled: Vec<u8> = data.iter().map(|b| b ^ 0x55).collect();
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound);
+        for piece in scrambled.chunks(3) {
+            let spare = decoder.spare(piece.len());
+            spare.copy_from_slice(piece);
+            decoder.commit(piece.len(), |data| data.iter_mut().for_each(|b| *b ^= 0x55));
+        }
+        expect_frame(&mut decoder, 5, b"secret");
+    }
+
+    #[test]
+    fn test_bad_length() {
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound);
+        decoder.push(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(decoder.next_event().is_err());
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound).with_limits(16, 64);
+        decoder.push(&frame(1, &[0u8; 100]));
+        assert!(matches!(decoder.next_event(), Err(FrameErr::TooLarge(101))));
+    }
+
+    #[test]
+    fn test_large_frames_stream() {
+        let body: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut data = frame(0x20, &body);
+        data.extend(frame(0x21, b"after"));
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound).with_limits(64, 4096);
+        let mut streamed = Vec::new();
+        let mut started = false;
+        let mut ended = false;
+        for piece in data.chunks(50) {
+            decoder.push(piece);
+            // The buffer never has to hold the whole frame
+            assert!(decoder.buffer.len() <= 64 + 50);
+            while let Some(event) = decoder.next_event().unwrap() {
+                match event {
+                    FrameEvent::LargeStart { id, length } => {
+                        assert_eq!(id.id, 0x20);
+                        assert_eq!(length, body.len());
+                        started = true;
+                    }
+                    FrameEvent::LargeChunk(chunk) => streamed.extend_from_slice(chunk),
+                    FrameEvent::LargeEnd => ended = true,
+                    FrameEvent::Frame(frame) => {
+                        assert!(ended);
+                        assert_eq!(frame.id.id, 0x21);
+                        assert_eq!(frame.body.as_ref(), b"after");
+                    }
+                    #[cfg(feature = "compression")]
+                    FrameEvent::Skipped { .. } => panic!("nothing was compressed"),
+                }
+            }
+        }
+        assert!(started && ended);
+        assert_eq!(streamed, body);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_frames() {
+        let codec = CompressionCodec::new(64);
+        let mut data = Vec::new();
+        for (id, body) in [(1, vec![3u8; 500]), (2, b"tiny".to_vec())] {
+            let mut packet = BytesSerializer::default();
+            VarInt(id).mc_serialize(&mut packet).unwrap();
+            let mut packet = packet.into_bytes();
+            packet.extend_from_slice(&body);
+            data.extend(prefix(&codec.encode(&packet)));
+        }
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound);
+        decoder.set_compression(Some(codec));
+        for piece in data.chunks(7) {
+            decoder.push(piece);
+        }
+        expect_frame(&mut decoder, 1, &[3u8; 500]);
+        expect_frame(&mut decoder, 2, b"tiny");
+    }
+
+    #[cfg(feature = "compression")]
+    fn compressed(codec: &CompressionCodec, id: i32, body: &[u8]) -> Vec<u8> {
+        let mut packet = BytesSerializer::default();
+        VarInt(id).mc_serialize(&mut packet).unwrap();
+        let mut packet = packet.into_bytes();
+        packet.extend_from_slice(body);
+        prefix(&codec.encode(&packet))
+    }
+
+    /// Pushes `data` in `piece` sized reads, returning the length of the skipped frame and the id
+    /// of each whole frame after it
+    #[cfg(feature = "compression")]
+    fn skip_compressed(decoder: &mut FrameDecoder, data: &[u8], piece: usize) -> (usize, Vec<i32>) {
+        let mut skipped = None;
+        let mut after = Vec::new();
+        for piece in data.chunks(piece) {
+            decoder.push(piece);
+            while let Some(event) = decoder.next_event().unwrap() {
+                match event {
+                    FrameEvent::Skipped { length } => skipped = Some(length),
+                    FrameEvent::Frame(frame) => after.push(frame.id.id),
+                    _ => panic!("compressed frames are never streamed"),
+                }
+            }
+        }
+        (skipped.expect("frame was skipped"), after)
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_large_compressed_frames_are_skipped() {
+        let codec = CompressionCodec::new(0);
+        // Noise barely compresses, so the frame is well past what's buffered
+        let mut seed = 0x2545F491u32;
+        let body: Vec<u8> = (0..3000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let large = compressed(&codec, 0x20, &body);
+        assert!(large.len() > 1000);
+        let mut data = large.clone();
+        data.extend(compressed(&codec, 0x21, b"after"));
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound).with_limits(64, 1 << 16);
+        decoder.set_compression(Some(codec));
+        let (length, after) = skip_compressed(&mut decoder, &data, 50);
+        // Everything after the length prefix
+        assert_eq!(length, large.len() - 2);
+        assert_eq!(after, [0x21]);
+        assert!(decoder.is_empty());
+        assert!(decoder.buffer.len() <= 64 + 50);
+
+        // Read all at once it comes out the same
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound).with_limits(64, 1 << 16);
+        decoder.set_compression(Some(codec));
+        assert_eq!(skip_compressed(&mut decoder, &data, data.len()), (length, vec![0x21]));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_small_frames_that_inflate_past_the_buffer_are_skipped() {
+        let codec = CompressionCodec::new(0);
+        let mut data = compressed(&codec, 0x20, &[0u8; 4000]);
+        assert!(data.len() < 64);
+        data.extend(compressed(&codec, 0x21, b"after"));
+
+        let mut decoder = FrameDecoder::new(PacketDirection::ServerBound).with_limits(64, 1 << 16);
+        decoder.set_compression(Some(codec));
+        let (_, after) = skip_compressed(&mut decoder, &data, 7);
+        assert_eq!(after, [0x21]);
+    }
+}
@@ -0,0 +1,69 @@
+//! The server list ping used before 1.7, which old clients and some LAN scanners still send.
+//! It isn't framed like modern packets, the server answers with a single kick packet.
+
+use alloc::{format, vec::Vec};
+
+/// First byte of a legacy ping. A modern handshake is far too short for its length to start with it
+pub const LEGACY_PING: u8 = 0xFE;
+const KICK: u8 = 0xFF;
+/// What modern servers answer legacy pings with, so legacy clients show them as incompatible
+pub const LEGACY_PROTOCOL: i32 = 127;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyStatus<'a> {
+    pub protocol: i32,
+    pub version: &'a str,
+    pub motd: &'a str,
+    pub online: i32,
+    pub max: i32,
+}
+
+impl LegacyStatus<'_> {
+    /// The kick packet answering a `0xFE 0x01` ping: `§1`, then the protocol, version, MOTD, online
+    /// and max players separated by NUL, as a UTF-16BE string behind its length in code units
+    pub fn encode(&self) -> Vec<u8> {
+        let text = format!(
+            "§1\0{}\0{}\0{}\0{}\0{}",
+            self.protocol, self.version, self.motd, self.online, self.max
+        );
+        let units = text.encode_utf16().collect::<Vec<_>>();
+
+        let mut out = Vec::with_capacity(3 + units.len() * 2);
+        out.push(KICK);
+        out.extend_from_slice(&(units.len() as u16).to_be_bytes());
+        for unit in units {
+            out.extend_from_slice(&unit.to_be_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_status() {
+        let status = LegacyStatus {
+            protocol: LEGACY_PROTOCOL,
+            version: "1.21.8",
+            motd: "hi",
+            online: 1,
+            max: 10,
+        };
+        let bytes = status.encode();
+
+        let text = "§1\u{0}127\u{0}1.21.8\u{0}hi\u{0}1\u{0}10";
+        let units = text.encode_utf16().count();
+        assert_eq!(bytes[0], 0xFF);
+        assert_eq!(u16::from_be_bytes([bytes[1], bytes[2]]) as usize, units);
+        assert_eq!(bytes.len(), 3 + units * 2);
+        // § is U+00A7, the rest is ascii
+        assert_eq!(&bytes[3..9], &[0x00, 0xA7, 0x00, b'1', 0x00, 0x00]);
+        let decoded = bytes[3..]
+            .chunks(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(String::from_utf16(&decoded).unwrap(), text);
+    }
+}
@@ -3,6 +3,8 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate alloc;
+// lets the derives refer to ::mcproto_rs from inside this crate too
+extern crate self as mcproto_rs;
 
 #[cfg(all(test, feature = "std", feature = "bench"))]
 extern crate test;
@@ -17,6 +19,16 @@ pub mod utils;
 pub mod uuid;
 mod chat;
 pub mod byte_order;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod frame;
+pub mod hash;
+pub mod auth;
+pub mod cookie;
+pub mod legacy;
+pub mod query;
+pub mod rcon;
+pub mod proxy_protocol;
 
 #[cfg(feature = "v1_15_2")]
 pub mod v1_15_2;
@@ -24,9 +36,22 @@ pub mod v1_15_2;
 pub mod v1_16_3;
 #[cfg(feature = "v1_21_8")]
 pub mod v1_21_8;
+#[cfg(feature = "v1_21_8")]
+pub mod v1_21;
+#[cfg(feature = "v1_21_8")]
+pub mod forwarding;
 
 pub use deserialize::*;
 pub use serialize::*;
 
+#[cfg(feature = "derive")]
+pub use mcproto_rs_derive::{McDeserialize, McSerialize};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::format;
+    pub use alloc::vec::Vec;
+}
+
 #[cfg(all(test, feature = "std"))]
 mod test_macros;
@@ -118,6 +118,16 @@ pub trait HasPacketBody {
     fn mc_serialize_body<S>(&self, to: &mut S) -> SerializeResult where S: Serializer;
 }
 
+/// A packet's id followed by its body, which is what goes inside a frame
+pub struct PacketWithId<'a, P>(pub &'a P);
+
+impl<'a, P: HasPacketId + HasPacketBody> Serialize for PacketWithId<'a, P> {
+    fn mc_serialize<S: Serializer>(&self, to: &mut S) -> SerializeResult {
+        self.0.id().mc_serialize(to)?;
+        self.0.mc_serialize_body(to)
+    }
+}
+
 pub trait RawPacket<'a>: HasPacketId + Sized {
 
     type Packet: Packet;
@@ -137,6 +147,16 @@ pub enum PacketErr {
     ExtraData(Vec<u8>),
 }
 
+impl PacketErr {
+    /// Puts the packet's name at the front of a deserialize error's field path
+    pub fn in_packet(self, name: &'static str) -> Self {
+        match self {
+            PacketErr::DeserializeFailed(err) => PacketErr::DeserializeFailed(err.in_field(name, 0)),
+            other => other,
+        }
+    }
+}
+
 impl fmt::Display for PacketErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use PacketErr::*;
@@ -171,6 +191,23 @@ pub trait TestRandom {
     fn test_gen_random() -> Self;
 }
 
+/// Random values that fit a field's `max_length`
+#[cfg(all(test, feature = "std"))]
+pub trait TestRandomLimited {
+    fn test_gen_random_limited(max_length: usize) -> Self;
+}
+
+#[cfg(all(test, feature = "std"))]
+#[macro_export]
+macro_rules! test_gen_field {
+    ($typ: ty) => {
+        <$typ as $crate::protocol::TestRandom>::test_gen_random()
+    };
+    ($typ: ty, $max: literal) => {
+        <$typ as $crate::protocol::TestRandomLimited>::test_gen_random_limited($max)
+    };
+}
+
 #[macro_export]
 macro_rules! as_item {
     ($i:item) => {
@@ -204,7 +241,7 @@ macro_rules! proto_struct {
         }
     };
     ($bodyt: ident $(<$($g: ident),*>)? {
-        $($fname: ident: $ftyp: ty ),+
+        $($(#[max_length = $fmax: literal])? $fname: ident: $ftyp: ty ),+
     }) => {
         $crate::as_item! {
             #[derive(Debug, Clone, PartialEq)]
@@ -223,8 +260,13 @@ macro_rules! proto_struct {
         }
 
         impl$(<$($g),*>)? Deserialize for $bodyt$(<$($g),*> where $($g: Deserialize + alloc::fmt::Debug + Clone + PartialEq),*)? {
-            fn mc_deserialize(_rest: &[u8]) -> DeserializeResult<'_, Self> {
-                $(let Deserialized{ value: $fname, data: _rest } = <$ftyp>::mc_deserialize(_rest)?;)+
+            fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
+                let _rest = data;
+                $(let Deserialized{ value: $fname, data: _rest } = $crate::with_field_context(
+                    $crate::deserialize_field!(_rest, $ftyp $(, $fmax)?),
+                    stringify!($fname),
+                    data.len() - _rest.len(),
+                )?;)+
 
                 Deserialized::ok(Self{ $($fname),+ }, _rest)
             }
@@ -248,7 +290,7 @@ macro_rules! proto_struct {
         #[cfg(all(test, feature = "std"))]
         impl$(<$($g),*>)? TestRandom for $bodyt$(<$($g),*> where $($g: TestRandom + alloc::fmt::Debug + Clone + PartialEq),*)? {
             fn test_gen_random() -> Self {
-                Self{ $($fname: <$ftyp>::test_gen_random()),+ }
+                Self{ $($fname: $crate::test_gen_field!($ftyp $(, $fmax)?)),+ }
             }
         }
     }
@@ -256,9 +298,104 @@ macro_rules! proto_struct {
 
 #[macro_export]
 macro_rules! define_protocol {
+    ($version: literal, $packett: ident, $rawpackett: ident, $rawdt: ident, $kindt: ident, $borrowedt: ident => {
+        $($nam: ident, $id: literal, $state: ident, $direction: ident => $body: ident {
+            $($(#[max_length = $fmax: literal])? $fnam: ident: $ftyp: ty),* }),*
+        }
+    ) => {
+        $crate::define_protocol!($version, $packett, $rawpackett, $rawdt, $kindt => {
+            $($nam, $id, $state, $direction => $body {
+                $($(#[max_length = $fmax])? $fnam: $ftyp),* }),*
+        });
+
+        /// Packet bodies that borrow their strings and byte arrays from the packet data, same
+        /// names as the owned bodies
+        pub mod borrowed {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                #[derive(Debug, Clone, PartialEq)]
+                pub struct $body<'a> {
+                    $(pub $fnam: <$ftyp as $crate::DeserializeBorrowed<'a>>::Borrowed,)*
+                    pub(super) _data: core::marker::PhantomData<&'a [u8]>,
+                }
+            )*
+        }
+
+        $(
+            impl<'a> $crate::DeserializeBorrowed<'a> for $body {
+                type Borrowed = borrowed::$body<'a>;
+
+                fn mc_deserialize_borrowed(data: &'a [u8]) -> $crate::DeserializeResult<'a, Self::Borrowed> {
+                    let _rest = data;
+                    $(let $crate::Deserialized { value: $fnam, data: _rest } = $crate::with_field_context(
+                        $crate::deserialize_borrowed_field!(_rest, $ftyp $(, $fmax)?),
+                        stringify!($fnam),
+                        data.len() - _rest.len(),
+                    )?;)*
+
+                    $crate::Deserialized::ok(borrowed::$body { $($fnam,)* _data: core::marker::PhantomData }, _rest)
+                }
+
+                #[allow(unused_variables)]
+                fn from_borrowed(body: Self::Borrowed) -> Self {
+                    Self { $($fnam: <$ftyp as $crate::DeserializeBorrowed<'a>>::from_borrowed(body.$fnam)),* }
+                }
+            }
+        )*
+
+        $crate::as_item! {
+            #[derive(Debug, PartialEq, Clone)]
+            pub enum $borrowedt<'a> {
+                $($nam(borrowed::$body<'a>)),*,
+            }
+        }
+
+        impl<'a> $crate::protocol::HasPacketKind for $borrowedt<'a> {
+            type Kind = $kindt;
+
+            fn kind(&self) -> Self::Kind {
+                match self {
+                    $($borrowedt::$nam(_) => $kindt::$nam),*,
+                }
+            }
+        }
+
+        impl<'a> $crate::protocol::HasPacketId for $borrowedt<'a> {
+            fn id(&self) -> $crate::protocol::Id {
+                $crate::protocol::HasPacketKind::kind(self).id()
+            }
+
+            fn version() -> $crate::types::VarInt {
+                $crate::types::VarInt($version)
+            }
+        }
+
+        impl<'a> $borrowedt<'a> {
+            pub fn into_owned(self) -> $packett {
+                match self {
+                    $($borrowedt::$nam(body) => $packett::$nam(<$body as $crate::DeserializeBorrowed<'a>>::from_borrowed(body))),*,
+                }
+            }
+        }
+
+        impl<'a> $rawpackett<'a> {
+            /// Like `deserialize`, but strings and byte arrays point into the packet data instead
+            /// of being copied out
+            pub fn deserialize_borrowed(&self) -> Result<$borrowedt<'a>, $crate::protocol::PacketErr> {
+                match self {
+                    $($rawpackett::$nam(bod) => bod
+                        .deserialize_borrowed()
+                        .map($borrowedt::$nam)
+                        .map_err(|err| err.in_packet(stringify!($nam)))),*,
+                }
+            }
+        }
+    };
     ($version: literal, $packett: ident, $rawpackett: ident, $rawdt: ident, $kindt: ident => {
         $($nam: ident, $id: literal, $state: ident, $direction: ident => $body: ident {
-            $($fnam: ident: $ftyp: ty),* }),*
+            $($(#[max_length = $fmax: literal])? $fnam: ident: $ftyp: ty),* }),*
         }
     ) => {
         $crate::as_item! {
@@ -384,7 +521,7 @@ macro_rules! define_protocol {
                     $($rawpackett::$nam(bod) => {
                         let Deserialized { value: body, data: rest } =
                             $body::mc_deserialize(bod.data)
-                                .map_err(move |err| DeserializeFailed(err))?;
+                                .map_err(move |err| DeserializeFailed(err.in_field(stringify!($nam), 0)))?;
                         if !rest.is_empty() {
                             Err(ExtraData(rest.to_vec()))
                         } else {
@@ -414,6 +551,19 @@ macro_rules! define_protocol {
             }
         }
 
+        impl<'a, T> $rawdt<'a, T> where T: $crate::DeserializeBorrowed<'a> {
+            pub fn deserialize_borrowed(&self) -> Result<T::Borrowed, $crate::protocol::PacketErr> {
+                use $crate::protocol::PacketErr::*;
+
+                let Deserialized { value: body, data: rest } = T::mc_deserialize_borrowed(self.data).map_err(DeserializeFailed)?;
+                if !rest.is_empty() {
+                    Err(ExtraData(rest.to_vec()))
+                } else {
+                    Ok(body)
+                }
+            }
+        }
+
         impl crate::protocol::HasPacketId for $kindt {
             fn id(&self) -> crate::protocol::Id {
                 use self::$kindt::*;
@@ -465,7 +615,7 @@ macro_rules! define_protocol {
             }
         }
 
-        $($crate::proto_struct!($body { $($fnam: $ftyp),* });)*
+        $($crate::proto_struct!($body { $($(#[max_length = $fmax])? $fnam: $ftyp),* });)*
     };
 }
 
@@ -574,6 +724,8 @@ macro_rules! proto_enum_with_type {
                 panic!("cannot generate random {}", stringify!($typname));
             }
         }
+
+        $crate::borrow_as_owned!($typname);
     }
 }
 
@@ -685,6 +837,8 @@ macro_rules! proto_str_enum {
                 panic!("cannot generate random {}", stringify!($typname));
             }
         }
+
+        $crate::borrow_as_owned!($typname);
     }
 }
 
@@ -730,5 +884,7 @@ macro_rules! proto_byte_flag {
                 out
             }
         }
+
+        $crate::borrow_as_owned!($typname);
     }
 }
\ No newline at end of file
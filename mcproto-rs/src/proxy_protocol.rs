@@ -0,0 +1,241 @@
+//! HAProxy's PROXY protocol, a header load balancers and tunnels put before the connection's own
+//! bytes to say who the client really is.
+//!
+//! Version 1 is a line of text, `PROXY TCP4 <source> <destination> <source port> <destination
+//! port>\r\n`. Version 2 is a 12 byte signature, version and command, address family, a big
+//! endian length and then the addresses followed by optional TLVs, which are skipped.
+
+use alloc::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The longest a version 1 header can be, line ending included
+pub const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// Signature, version and command, family and length
+const V2_HEADER_LENGTH: usize = 16;
+const V2_COMMAND_LOCAL: u8 = 0x0;
+const V2_COMMAND_PROXY: u8 = 0x1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyErr {
+    /// Neither signature, the connection didn't come through a proxy
+    Missing,
+    UnsupportedVersion(u8),
+    Malformed(&'static str),
+}
+
+impl fmt::Display for ProxyErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyErr::Missing => f.write_str("no proxy protocol header"),
+            ProxyErr::UnsupportedVersion(version) => {
+                f.write_fmt(format_args!("unsupported proxy protocol version {}", version))
+            }
+            ProxyErr::Malformed(err) => f.write_fmt(format_args!("malformed proxy protocol header: {}", err)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProxyErr {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyParse {
+    /// At least this many more bytes are needed, reading no more than that never eats into what
+    /// comes after the header
+    Incomplete(usize),
+    /// The header took up `length` bytes. `source` is `None` for the proxy's own health checks
+    /// and for connections it couldn't describe
+    Complete { source: Option<SocketAddr>, length: usize },
+}
+
+/// Reads the header at the start of `data`, either version
+pub fn parse(data: &[u8]) -> Result<ProxyParse, ProxyErr> {
+    // Both signatures differ from the first byte, so that's enough to pick one
+    match data.first() {
+        None => Ok(ProxyParse::Incomplete(1)),
+        Some(b'P') => parse_v1(data),
+        Some(b'\r') => parse_v2(data),
+        Some(_) => Err(ProxyErr::Missing),
+    }
+}
+
+fn parse_v1(data: &[u8]) -> Result<ProxyParse, ProxyErr> {
+    let prefix = data.len().min(V1_PREFIX.len());
+    if data[..prefix] != V1_PREFIX[..prefix] {
+        return Err(ProxyErr::Missing);
+    }
+    let Some(end) = data.windows(2).position(|window| window == b"\r\n") else {
+        if data.len() >= V1_MAX_LENGTH {
+            return Err(ProxyErr::Malformed("header too long"));
+        }
+        // The line ending could be anywhere, only one more byte is certain
+        return Ok(ProxyParse::Incomplete(1));
+    };
+
+    let line = core::str::from_utf8(&data[V1_PREFIX.len()..end])
+        .map_err(|_| ProxyErr::Malformed("header isn't text"))?;
+    let mut fields = line.split(' ');
+    let source = match fields.next() {
+        Some("UNKNOWN") => None,
+        Some("TCP4" | "TCP6") => {
+            let (Some(address), Some(_), Some(port), Some(_), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(ProxyErr::Malformed("wrong number of fields"));
+            };
+            let address: IpAddr = address.parse().map_err(|_| ProxyErr::Malformed("bad source address"))?;
+            let port: u16 = port.parse().map_err(|_| ProxyErr::Malformed("bad source port"))?;
+            Some(SocketAddr::new(address, port))
+        }
+        _ => return Err(ProxyErr::Malformed("unknown protocol")),
+    };
+    Ok(ProxyParse::Complete {
+        source,
+        length: end + 2,
+    })
+}
+
+fn parse_v2(data: &[u8]) -> Result<ProxyParse, ProxyErr> {
+    let signature = data.len().min(V2_SIGNATURE.len());
+    if data[..signature] != V2_SIGNATURE[..signature] {
+        return Err(ProxyErr::Missing);
+    }
+    if data.len() < V2_HEADER_LENGTH {
+        return Ok(ProxyParse::Incomplete(V2_HEADER_LENGTH - data.len()));
+    }
+
+    let version = data[12] >> 4;
+    if version != 2 {
+        return Err(ProxyErr::UnsupportedVersion(version));
+    }
+    let length = V2_HEADER_LENGTH + u16::from_be_bytes([data[14], data[15]]) as usize;
+    if data.len() < length {
+        return Ok(ProxyParse::Incomplete(length - data.len()));
+    }
+
+    let addresses = &data[V2_HEADER_LENGTH..length];
+    let source = match data[12] & 0x0F {
+        V2_COMMAND_LOCAL => None,
+        V2_COMMAND_PROXY => match data[13] >> 4 {
+            // IPv4, source and destination address then source and destination port
+            0x1 if addresses.len() >= 12 => {
+                let address = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+                Some(SocketAddr::new(address.into(), port))
+            }
+            0x2 if addresses.len() >= 36 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[..16]);
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+                Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+            }
+            0x1 | 0x2 => return Err(ProxyErr::Malformed("addresses too short")),
+            // Unspecified or unix sockets, nothing we could use
+            _ => None,
+        },
+        _ => return Err(ProxyErr::Malformed("unknown command")),
+    };
+    Ok(ProxyParse::Complete { source, length })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Feeds `data` in the amounts `parse` asks for, like a socket would
+    fn parse_incrementally(data: &[u8]) -> Result<ProxyParse, ProxyErr> {
+        let mut read = 0;
+        loop {
+            match parse(&data[..read])? {
+                ProxyParse::Incomplete(needed) => {
+                    read += needed;
+                    assert!(read <= data.len(), "asked for more than the header");
+                }
+                complete => return Ok(complete),
+            }
+        }
+    }
+
+    #[test]
+    fn test_v1() {
+        let mut data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n".to_vec();
+        let length = data.len();
+        data.extend_from_slice(&[0x10, 0x00]);
+        assert_eq!(
+            parse_incrementally(&data),
+            Ok(ProxyParse::Complete {
+                source: Some("192.168.0.1:56324".parse().unwrap()),
+                length
+            })
+        );
+
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 25565\r\n";
+        assert_eq!(
+            parse(data),
+            Ok(ProxyParse::Complete {
+                source: Some("[2001:db8::1]:4000".parse().unwrap()),
+                length: data.len()
+            })
+        );
+        assert_eq!(
+            parse(b"PROXY UNKNOWN\r\n"),
+            Ok(ProxyParse::Complete { source: None, length: 15 })
+        );
+
+        assert_eq!(parse(b"PROXY TCP4 1.2.3.4\r\n"), Err(ProxyErr::Malformed("wrong number of fields")));
+        assert_eq!(parse(b"PROXY TCP4 1.2"), Ok(ProxyParse::Incomplete(1)));
+        let mut long = b"PROXY TCP4 ".to_vec();
+        long.resize(V1_MAX_LENGTH, b'1');
+        assert_eq!(parse(&long), Err(ProxyErr::Malformed("header too long")));
+        assert_eq!(parse(&[0x10, 0x00]), Err(ProxyErr::Missing));
+        assert_eq!(parse(b"PING"), Err(ProxyErr::Missing));
+    }
+
+    #[test]
+    fn test_v2() {
+        let mut data: Vec<u8> = V2_SIGNATURE.to_vec();
+        // PROXY over TCP4, 12 bytes of addresses and a 3 byte TLV
+        data.extend_from_slice(&[0x21, 0x11, 0x00, 15]);
+        data.extend_from_slice(&[10, 0, 0, 7, 10, 0, 0, 1]);
+        data.extend_from_slice(&50000u16.to_be_bytes());
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        data.extend_from_slice(&[0x04, 0x00, 0x00]);
+        let length = data.len();
+        data.extend_from_slice(&[0x10, 0x00]);
+        assert_eq!(
+            parse_incrementally(&data),
+            Ok(ProxyParse::Complete {
+                source: Some("10.0.0.7:50000".parse().unwrap()),
+                length
+            })
+        );
+
+        let mut data: Vec<u8> = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x21, 0x21, 0x00, 36]);
+        data.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&443u16.to_be_bytes());
+        data.extend_from_slice(&[0; 2]);
+        assert_eq!(
+            parse(&data),
+            Ok(ProxyParse::Complete {
+                source: Some("[2001:db8::1]:443".parse().unwrap()),
+                length: data.len()
+            })
+        );
+
+        // A health check from the proxy itself
+        let mut data: Vec<u8> = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(parse(&data), Ok(ProxyParse::Complete { source: None, length: 16 }));
+
+        data[12] = 0x11;
+        assert_eq!(parse(&data), Err(ProxyErr::UnsupportedVersion(1)));
+        data[12] = 0x21;
+        data[13] = 0x11;
+        assert_eq!(parse(&data), Err(ProxyErr::Malformed("addresses too short")));
+    }
+}
@@ -0,0 +1,242 @@
+//! The GameSpy4 based Query protocol, spoken over UDP by monitoring bots and server lists.
+//!
+//! A client first asks for a challenge token, then sends it back with every stat request. All
+//! numbers are big endian except the host port of a basic stat, which is little endian.
+
+use alloc::{format, vec::Vec};
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+// Constant padding vanilla puts around the sections of a full stat
+const SPLITNUM: &[u8] = b"splitnum\0\x80\0";
+const PLAYER_SECTION: &[u8] = b"\x01player_\0\0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryRequest {
+    Handshake { session: i32 },
+    BasicStat { session: i32, token: i32 },
+    FullStat { session: i32, token: i32 },
+}
+
+impl QueryRequest {
+    /// `None` for anything that isn't a query, so stray datagrams can be ignored
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 7 || data[..2] != MAGIC {
+            return None;
+        }
+        let session = read_i32(&data[3..])?;
+        let payload = &data[7..];
+        match (data[2], payload.len()) {
+            (TYPE_HANDSHAKE, 0) => Some(QueryRequest::Handshake { session }),
+            (TYPE_STAT, 4) => Some(QueryRequest::BasicStat { session, token: read_i32(payload)? }),
+            // A full stat is a basic one padded with 4 bytes
+            (TYPE_STAT, 8) => Some(QueryRequest::FullStat { session, token: read_i32(payload)? }),
+            _ => None,
+        }
+    }
+
+    pub fn session(&self) -> i32 {
+        match self {
+            QueryRequest::Handshake { session }
+            | QueryRequest::BasicStat { session, .. }
+            | QueryRequest::FullStat { session, .. } => *session,
+        }
+    }
+}
+
+fn read_i32(data: &[u8]) -> Option<i32> {
+    let bytes = data.get(..4)?;
+    Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Tokens handed out by `issue` stay valid for two rotations, so one issued just before a rotation
+/// still works right after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChallengeTokens {
+    current: u32,
+    previous: u32,
+}
+
+impl ChallengeTokens {
+    pub fn new(secret: u32) -> Self {
+        Self { current: secret, previous: secret }
+    }
+
+    /// Invalidates every token issued before the last rotation
+    pub fn rotate(&mut self, secret: u32) {
+        self.previous = self.current;
+        self.current = secret;
+    }
+
+    /// The token for a client, `client` being anything identifying it such as its address
+    pub fn issue(&self, client: u32) -> i32 {
+        Self::token(self.current, client)
+    }
+
+    pub fn is_valid(&self, client: u32, token: i32) -> bool {
+        token == Self::token(self.current, client) || token == Self::token(self.previous, client)
+    }
+
+    // Kept positive, some clients parse the token string as an unsigned number
+    fn token(secret: u32, client: u32) -> i32 {
+        ((secret ^ client.wrapping_mul(0x9E37_79B1)) & 0x7FFF_FFFF) as i32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryStatus<'a> {
+    pub motd: &'a str,
+    pub game_type: &'a str,
+    pub map: &'a str,
+    pub version: &'a str,
+    pub online: i32,
+    pub max: i32,
+    pub host_port: u16,
+    pub host_ip: &'a str,
+    pub players: &'a [&'a str],
+}
+
+fn header(kind: u8, session: i32) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.push(kind);
+    out.extend_from_slice(&session.to_be_bytes());
+    out
+}
+
+fn push_str(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+/// Answers a handshake with the token as a NUL terminated decimal string
+pub fn encode_handshake(session: i32, token: i32) -> Vec<u8> {
+    let mut out = header(TYPE_HANDSHAKE, session);
+    push_str(&mut out, &format!("{}", token));
+    out
+}
+
+impl QueryStatus<'_> {
+    pub fn encode_basic(&self, session: i32) -> Vec<u8> {
+        let mut out = header(TYPE_STAT, session);
+        push_str(&mut out, self.motd);
+        push_str(&mut out, self.game_type);
+        push_str(&mut out, self.map);
+        push_str(&mut out, &format!("{}", self.online));
+        push_str(&mut out, &format!("{}", self.max));
+        out.extend_from_slice(&self.host_port.to_le_bytes());
+        push_str(&mut out, self.host_ip);
+        out
+    }
+
+    /// Key value pairs, then the player names, each section ending with an empty string
+    pub fn encode_full(&self, session: i32) -> Vec<u8> {
+        let mut out = header(TYPE_STAT, session);
+        out.extend_from_slice(SPLITNUM);
+        let pairs = [
+            ("hostname", self.motd),
+            ("gametype", self.game_type),
+            ("game_id", "MINECRAFT"),
+            ("version", self.version),
+            ("plugins", ""),
+            ("map", self.map),
+            ("numplayers", &format!("{}", self.online)),
+            ("maxplayers", &format!("{}", self.max)),
+            ("hostport", &format!("{}", self.host_port)),
+            ("hostip", self.host_ip),
+        ];
+        for (key, value) in pairs.iter() {
+            push_str(&mut out, key);
+            push_str(&mut out, value);
+        }
+        out.push(0);
+
+        out.extend_from_slice(PLAYER_SECTION);
+        for player in self.players {
+            push_str(&mut out, player);
+        }
+        out.push(0);
+        out
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    const STATUS: QueryStatus<'static> = QueryStatus {
+        motd: "A Server",
+        game_type: "SMP",
+        map: "world",
+        version: "1.21.8",
+        online: 2,
+        max: 10,
+        host_port: 25565,
+        host_ip: "10.0.0.2",
+        players: &["alice", "bob"],
+    };
+
+    #[test]
+    fn test_parse_requests() {
+        let session = [0x00, 0x00, 0x00, 0x01];
+        let handshake = [&[0xFE, 0xFD, 0x09][..], &session].concat();
+        assert_eq!(QueryRequest::parse(&handshake), Some(QueryRequest::Handshake { session: 1 }));
+
+        let token = 9513307i32.to_be_bytes();
+        let basic = [&[0xFE, 0xFD, 0x00][..], &session, &token].concat();
+        assert_eq!(QueryRequest::parse(&basic), Some(QueryRequest::BasicStat { session: 1, token: 9513307 }));
+
+        let full = [&basic[..], &[0, 0, 0, 0]].concat();
+        assert_eq!(QueryRequest::parse(&full), Some(QueryRequest::FullStat { session: 1, token: 9513307 }));
+
+        assert_eq!(QueryRequest::parse(&[0xFE, 0xFD, 0x09, 0x00]), None);
+        assert_eq!(QueryRequest::parse(&[&[0xFE, 0xFC, 0x09][..], &session].concat()), None);
+        assert_eq!(QueryRequest::parse(&[&basic[..], &[0]].concat()), None);
+    }
+
+    #[test]
+    fn test_encode_handshake() {
+        assert_eq!(encode_handshake(1, 9513307), b"\x09\x00\x00\x00\x019513307\0");
+    }
+
+    #[test]
+    fn test_encode_basic() {
+        let expected = [
+            &b"\x00\x00\x00\x00\x01"[..],
+            b"A Server\0SMP\0world\x002\x0010\0",
+            &25565u16.to_le_bytes(),
+            b"10.0.0.2\0",
+        ]
+        .concat();
+        assert_eq!(STATUS.encode_basic(1), expected);
+    }
+
+    #[test]
+    fn test_encode_full() {
+        let expected = [
+            &b"\x00\x00\x00\x00\x01splitnum\0\x80\0"[..],
+            b"hostname\0A Server\0gametype\0SMP\0game_id\0MINECRAFT\0version\x001.21.8\0plugins\0\0",
+            b"map\0world\0numplayers\x002\0maxplayers\x0010\0hostport\x0025565\0hostip\x0010.0.0.2\0\0",
+            b"\x01player_\0\0alice\0bob\0\0",
+        ]
+        .concat();
+        assert_eq!(STATUS.encode_full(1), expected);
+    }
+
+    #[test]
+    fn test_challenge_tokens_rotate() {
+        let mut tokens = ChallengeTokens::new(0x1234_5678);
+        let client = 0x0A00_0002;
+        let token = tokens.issue(client);
+        assert!(token >= 0);
+        assert!(tokens.is_valid(client, token));
+        assert!(!tokens.is_valid(client + 1, token));
+
+        tokens.rotate(0x0BAD_F00D);
+        assert!(tokens.is_valid(client, token));
+        assert_ne!(tokens.issue(client), token);
+
+        tokens.rotate(0x0DEF_ACED);
+        assert!(!tokens.is_valid(client, token));
+    }
+}
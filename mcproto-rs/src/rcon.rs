@@ -0,0 +1,144 @@
+//! The Source RCON protocol, which Minecraft uses for its remote console.
+//!
+//! Every packet is a little endian length, request id and type, then the body with a NUL after it
+//! and one more NUL as padding. The length counts everything after itself.
+
+use alloc::{fmt, vec::Vec};
+
+pub const TYPE_RESPONSE: i32 = 0;
+pub const TYPE_COMMAND: i32 = 2;
+pub const TYPE_AUTH_RESPONSE: i32 = 2;
+pub const TYPE_AUTH: i32 = 3;
+/// Request id of the auth response to a wrong password
+pub const AUTH_FAILED: i32 = -1;
+/// Vanilla's limit for a packet sent to the server, length prefix included
+pub const MAX_REQUEST_LENGTH: usize = 1460;
+/// The longest body vanilla puts in one response, longer output is split over several
+pub const MAX_RESPONSE_BODY: usize = 4096;
+// Request id, type and the two NULs
+const MIN_LENGTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RconErr {
+    BadLength(i32),
+}
+
+impl fmt::Display for RconErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RconErr::BadLength(length) => f.write_fmt(format_args!("bad rcon packet length {}", length)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RconErr {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RconPacket<'a> {
+    pub request_id: i32,
+    pub kind: i32,
+    pub body: &'a [u8],
+}
+
+impl<'a> RconPacket<'a> {
+    /// Reads the packet at the start of `data` and how many bytes it took up, `None` until all of
+    /// it has arrived
+    pub fn parse(data: &'a [u8]) -> Result<Option<(Self, usize)>, RconErr> {
+        let Some(length) = read_i32(data) else {
+            return Ok(None);
+        };
+        if length < MIN_LENGTH as i32 || length as usize + 4 > MAX_REQUEST_LENGTH {
+            return Err(RconErr::BadLength(length));
+        }
+        let total = length as usize + 4;
+        if data.len() < total {
+            return Ok(None);
+        }
+
+        let body = &data[12..total - 1];
+        let body = &body[..body.iter().position(|b| *b == 0).unwrap_or(body.len())];
+        let packet = Self {
+            request_id: read_i32(&data[4..]).unwrap_or_default(),
+            kind: read_i32(&data[8..]).unwrap_or_default(),
+            body,
+        };
+        Ok(Some((packet, total)))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let length = (MIN_LENGTH + self.body.len()) as i32;
+        let mut out = Vec::with_capacity(4 + length as usize);
+        out.extend_from_slice(&length.to_le_bytes());
+        out.extend_from_slice(&self.request_id.to_le_bytes());
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        out.extend_from_slice(self.body);
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+}
+
+fn read_i32(data: &[u8]) -> Option<i32> {
+    let bytes = data.get(..4)?;
+    Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Splits a response into bodies that each fit one packet, never in the middle of a character.
+/// Empty output is still one empty body, every command gets an answer
+pub fn fragments(output: &str) -> Vec<&str> {
+    let mut fragments = Vec::new();
+    let mut rest = output;
+    loop {
+        let mut end = rest.len().min(MAX_RESPONSE_BODY);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (fragment, tail) = rest.split_at(end);
+        fragments.push(fragment);
+        rest = tail;
+        if rest.is_empty() {
+            return fragments;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rcon_round_trip() {
+        let packet = RconPacket { request_id: 7, kind: TYPE_AUTH, body: b"hunter2" };
+        let bytes = packet.encode();
+        assert_eq!(&bytes[..12], &[17, 0, 0, 0, 7, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(&bytes[12..], b"hunter2\0\0");
+
+        // Arrives in pieces, followed by the start of the next packet
+        let stream = [&bytes[..], &bytes[..3]].concat();
+        assert_eq!(RconPacket::parse(&stream[..3]), Ok(None));
+        assert_eq!(RconPacket::parse(&stream[..bytes.len() - 1]), Ok(None));
+        assert_eq!(RconPacket::parse(&stream), Ok(Some((packet, bytes.len()))));
+    }
+
+    #[test]
+    fn test_rcon_bad_length() {
+        assert_eq!(RconPacket::parse(&[9, 0, 0, 0]), Err(RconErr::BadLength(9)));
+        assert_eq!(RconPacket::parse(&(-1i32).to_le_bytes()), Err(RconErr::BadLength(-1)));
+        let too_long = (MAX_REQUEST_LENGTH as i32 - 3).to_le_bytes();
+        assert_eq!(RconPacket::parse(&too_long), Err(RconErr::BadLength(MAX_REQUEST_LENGTH as i32 - 3)));
+    }
+
+    #[test]
+    fn test_fragments() {
+        assert_eq!(fragments(""), vec![""]);
+        assert_eq!(fragments("short"), vec!["short"]);
+
+        // § is two bytes, an even split would cut the last one in half
+        let output = "a".repeat(MAX_RESPONSE_BODY - 1) + "§§";
+        let pieces = fragments(&output);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].len(), MAX_RESPONSE_BODY - 1);
+        assert_eq!(pieces[1], "§§");
+        assert_eq!(pieces.concat(), output);
+    }
+}
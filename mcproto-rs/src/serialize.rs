@@ -45,3 +45,34 @@ pub trait Serializer: Sized {
         other.mc_serialize(self)
     }
 }
+
+/// Counts bytes instead of storing them, for sizing a value before writing it
+#[derive(Default)]
+pub struct CountingSerializer {
+    count: usize,
+}
+
+impl Serializer for CountingSerializer {
+    fn serialize_bytes(&mut self, data: &[u8]) -> SerializeResult {
+        self.count += data.len();
+        Ok(())
+    }
+}
+
+impl CountingSerializer {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+pub trait SerializedSize {
+    fn serialized_size(&self) -> Result<usize, SerializeErr>;
+}
+
+impl<T: Serialize> SerializedSize for T {
+    fn serialized_size(&self) -> Result<usize, SerializeErr> {
+        let mut counter = CountingSerializer::default();
+        self.mc_serialize(&mut counter)?;
+        Ok(counter.count())
+    }
+}
@@ -44,6 +44,8 @@ impl McDeserialize for StatusSpec {
     }
 }
 
+crate::borrow_as_owned!(StatusSpec);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for StatusSpec {
     fn test_gen_random() -> Self {
@@ -59,6 +61,7 @@ impl TestRandom for StatusSpec {
             },
             favicon: None,
             description: Chat::test_gen_random(),
+            enforces_secure_chat: rand::random(),
         }
     }
 }
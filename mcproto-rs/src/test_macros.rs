@@ -14,6 +14,15 @@ macro_rules! packet_test_cases {
                 packet.mc_serialize_body(&mut out).expect("serialize succeeds");
                 let bytes = out.into_bytes();
 
+                if k == 0 {
+                    use crate::{Serialize, SerializedSize};
+                    let framed = crate::protocol::PacketWithId(&packet);
+                    let mut whole = crate::types::BytesSerializer::default();
+                    framed.mc_serialize(&mut whole).expect("serialize succeeds");
+                    let whole = whole.into_bytes();
+                    assert_eq!(framed.serialized_size().expect("serialize succeeds"), whole.len(), "counted size matches");
+                }
+
                 let raw_packet = $rawnam::create(packet.id(), bytes.as_slice()).expect("valid id");
                 let deserialized = match raw_packet.deserialize() {
                     Err(err) => {
@@ -67,6 +76,46 @@ macro_rules! packet_test_cases {
     };
 }
 
+#[cfg(all(test, feature = "std"))]
+#[macro_export]
+macro_rules! borrowed_packet_test_cases {
+    ($rawnam: ident, $pnam: ident, $varnam: ident, $bodnam: ident, $testnam: ident, $benchnam: ident) => {
+        #[test]
+        fn $testnam() {
+            use crate::protocol::{RawPacket, HasPacketBody, HasPacketId};
+            for _ in 0..50 {
+                let packet = $pnam::$varnam($bodnam::test_gen_random());
+                let mut out = crate::types::BytesSerializer::default();
+                packet.mc_serialize_body(&mut out).expect("serialize succeeds");
+                let bytes = out.into_bytes();
+
+                let raw_packet = $rawnam::create(packet.id(), bytes.as_slice()).expect("valid id");
+                let borrowed = raw_packet.deserialize_borrowed().expect("deserialize succeeds");
+                assert_eq!(borrowed.id(), packet.id());
+                assert_eq!(borrowed.into_owned(), packet, "borrowed deserialize(serialize(packet)) == packet");
+            }
+        }
+
+        #[cfg(feature = "bench")]
+        #[bench]
+        fn $benchnam(b: &mut test::Bencher) {
+            use crate::protocol::{RawPacket, HasPacketBody, HasPacketId};
+            let packet = $pnam::$varnam($bodnam::test_gen_random());
+            let mut serializer = crate::types::BytesSerializer::default();
+            packet
+                .mc_serialize_body(&mut serializer)
+                .expect("serialize succeeds");
+
+            let bytes = serializer.into_bytes();
+            b.bytes = bytes.len() as u64;
+            let raw_packet = $rawnam::create(packet.id(), bytes.as_slice()).expect("valid id");
+            b.iter(|| {
+                raw_packet.deserialize_borrowed().expect("deserialize succeeds");
+            })
+        }
+    };
+}
+
 #[cfg(all(test, feature = "std", feature = "bench"))]
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct BenchSerializer {
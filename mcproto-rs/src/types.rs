@@ -9,7 +9,7 @@ pub use super::chat::*;
 
 use crate::byte_order::{ByteOrder, ProtoByteOrder};
 #[cfg(all(test, feature = "std"))]
-use crate::protocol::TestRandom;
+use crate::protocol::{TestRandom, TestRandomLimited};
 
 // bool
 impl Serialize for bool {
@@ -72,6 +72,33 @@ def_primitive!(i128, read_2long, write_2long);
 def_primitive!(f32, read_float, write_float);
 def_primitive!(f64, read_double, write_double);
 
+borrow_as_owned!(bool, i8, u16, i16, u32, i32, u64, i64, u128, i128, f32, f64);
+
+impl<'a> DeserializeBorrowed<'a> for u8 {
+    type Borrowed = u8;
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, u8> {
+        u8::mc_deserialize(data)
+    }
+
+    fn from_borrowed(borrowed: u8) -> Self {
+        borrowed
+    }
+}
+
+// Byte arrays borrow as one slice
+impl<'a> DeserializeBorrowedElements<'a> for u8 {
+    type Elements = &'a [u8];
+
+    fn mc_deserialize_elements(count: usize, data: &'a [u8]) -> DeserializeResult<'a, &'a [u8]> {
+        take(count, data)
+    }
+
+    fn elements_to_vec(elements: &'a [u8]) -> Vec<u8> {
+        elements.to_vec()
+    }
+}
+
 // VAR INT AND VAR LONG
 macro_rules! def_varnum {
     ($nam: ident, $data_type: ty, $working_type: ty, $max_bytes: literal) => {
@@ -179,6 +206,7 @@ macro_rules! def_varnum {
 
 def_varnum!(VarInt, i32, u32, 5);
 def_varnum!(VarLong, i64, u64, 10);
+borrow_as_owned!(VarInt, VarLong);
 
 // STRING
 impl Serialize for String {
@@ -188,20 +216,70 @@ impl Serialize for String {
     }
 }
 
-impl Deserialize for String {
-    fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
-        VarInt::mc_deserialize(data)?.and_then(move |length, rest| {
-            if length.0 < 0 {
-                Err(DeserializeErr::NegativeLength(length))
-            } else {
-                take(length.0 as usize, rest)?.try_map(move |taken| {
-                    String::from_utf8(taken.to_vec()).map_err(DeserializeErr::BadStringEncoding)
-                })
+/// Vanilla's limit, in UTF-16 code units like Java counts them
+pub const MAX_STRING_LENGTH: usize = 32767;
+
+fn deserialize_str(data: &[u8], max_length: usize) -> DeserializeResult<'_, &str> {
+    VarInt::mc_deserialize(data)?.and_then(move |length, rest| {
+        if length.0 < 0 {
+            return Err(DeserializeErr::NegativeLength(length));
+        }
+        // A UTF-16 unit is at most three bytes of UTF-8, so this bounds the length before
+        // anything is read
+        let length = length.0 as usize;
+        check_length(length, max_length.saturating_mul(3))?;
+        take(length, rest)?.try_map(move |taken| {
+            // Only copies to build the same error as String::from_utf8
+            let string = core::str::from_utf8(taken).map_err(|_| {
+                DeserializeErr::BadStringEncoding(String::from_utf8(taken.to_vec()).unwrap_err())
+            })?;
+            if length > max_length {
+                check_length(string.encode_utf16().count(), max_length)?;
             }
+            Ok(string)
         })
+    })
+}
+
+fn deserialize_string(data: &[u8], max_length: usize) -> DeserializeResult<'_, String> {
+    deserialize_str(data, max_length)?.try_map(|string| {
+        charge_allocation(string.len())?;
+        Ok(string.into())
+    })
+}
+
+impl Deserialize for String {
+    fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
+        deserialize_string(data, MAX_STRING_LENGTH)
+    }
+}
+
+impl DeserializeLimited for String {
+    fn mc_deserialize_limited(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self> {
+        deserialize_string(data, max_length)
+    }
+}
+
+impl<'a> DeserializeBorrowed<'a> for String {
+    type Borrowed = &'a str;
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, &'a str> {
+        deserialize_str(data, MAX_STRING_LENGTH)
+    }
+
+    fn from_borrowed(borrowed: &'a str) -> Self {
+        borrowed.into()
+    }
+}
+
+impl<'a> DeserializeBorrowedLimited<'a> for String {
+    fn mc_deserialize_borrowed_limited(data: &'a [u8], max_length: usize) -> DeserializeResult<'a, &'a str> {
+        deserialize_str(data, max_length)
     }
 }
 
+borrow_elements!(String);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for String {
     fn test_gen_random() -> Self {
@@ -224,6 +302,15 @@ impl TestRandom for String {
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+impl TestRandomLimited for String {
+    fn test_gen_random_limited(max_length: usize) -> Self {
+        let mut out = Self::test_gen_random();
+        out.truncate(max_length);
+        out
+    }
+}
+
 // position
 #[derive(Clone, Copy, PartialEq, Hash, Debug)]
 pub struct IntPosition {
@@ -289,6 +376,8 @@ impl Deserialize for IntPosition {
     }
 }
 
+borrow_as_owned!(IntPosition);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for IntPosition {
     fn test_gen_random() -> Self {
@@ -317,6 +406,8 @@ impl Deserialize for Angle {
     }
 }
 
+borrow_as_owned!(Angle);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for Angle {
     fn test_gen_random() -> Self {
@@ -341,6 +432,8 @@ impl Deserialize for UUID4 {
     }
 }
 
+borrow_as_owned!(UUID4);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for UUID4 {
     fn test_gen_random() -> Self {
@@ -371,6 +464,8 @@ impl Deserialize for NamedNbtTag {
     }
 }
 
+borrow_as_owned!(NamedNbtTag);
+
 impl From<nbt::NamedTag> for NamedNbtTag {
     fn from(root: nbt::NamedTag) -> Self {
         Self { root }
@@ -409,6 +504,8 @@ impl Deserialize for FixedInt {
     }
 }
 
+borrow_as_owned!(FixedInt);
+
 impl FixedInt {
     pub fn new(data: f64, fractional_bytes: usize) -> Self {
         Self {
@@ -482,6 +579,57 @@ where
     }
 }
 
+impl<'a, T> DeserializeBorrowed<'a> for Option<T>
+where
+    T: DeserializeBorrowed<'a>,
+{
+    type Borrowed = Option<T::Borrowed>;
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, Self::Borrowed> {
+        bool::mc_deserialize(data)?.and_then(move |is_present, data| {
+            if is_present {
+                Ok(T::mc_deserialize_borrowed(data)?.map(Some))
+            } else {
+                Deserialized::ok(None, data)
+            }
+        })
+    }
+
+    fn from_borrowed(borrowed: Self::Borrowed) -> Self {
+        borrowed.map(T::from_borrowed)
+    }
+}
+
+impl<T> DeserializeLimited for Option<T>
+where
+    T: DeserializeLimited,
+{
+    fn mc_deserialize_limited(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self> {
+        bool::mc_deserialize(data)?.and_then(move |is_present, data| {
+            if is_present {
+                Ok(T::mc_deserialize_limited(data, max_length)?.map(Some))
+            } else {
+                Deserialized::ok(None, data)
+            }
+        })
+    }
+}
+
+impl<'a, T> DeserializeBorrowedLimited<'a> for Option<T>
+where
+    T: DeserializeBorrowedLimited<'a>,
+{
+    fn mc_deserialize_borrowed_limited(data: &'a [u8], max_length: usize) -> DeserializeResult<'a, Self::Borrowed> {
+        bool::mc_deserialize(data)?.and_then(move |is_present, data| {
+            if is_present {
+                Ok(T::mc_deserialize_borrowed_limited(data, max_length)?.map(Some))
+            } else {
+                Deserialized::ok(None, data)
+            }
+        })
+    }
+}
+
 #[cfg(all(test, feature = "std"))]
 impl<T> TestRandom for Option<T>
 where
@@ -497,6 +645,20 @@ where
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+impl<T> TestRandomLimited for Option<T>
+where
+    T: TestRandomLimited,
+{
+    fn test_gen_random_limited(max_length: usize) -> Self {
+        if rand::random() {
+            Some(T::test_gen_random_limited(max_length))
+        } else {
+            None
+        }
+    }
+}
+
 // SLOT
 #[derive(Debug, PartialEq, Clone)]
 pub struct ItemStack {
@@ -547,6 +709,8 @@ impl Deserialize for ItemStack {
     }
 }
 
+borrow_as_owned!(ItemStack);
+
 #[cfg(all(test, feature = "std"))]
 impl TestRandom for ItemStack {
     fn test_gen_random() -> Self {
@@ -755,12 +919,35 @@ where
     C: ArrayCounter,
 {
     fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
+        Self::deserialize_counted(data, usize::MAX)
+    }
+}
+
+impl<E, C> DeserializeLimited for CountedArray<E, C>
+where
+    E: Deserialize,
+    C: ArrayCounter,
+{
+    fn mc_deserialize_limited(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self> {
+        Self::deserialize_counted(data, max_length)
+    }
+}
+
+impl<E, C> CountedArray<E, C>
+where
+    E: Deserialize,
+    C: ArrayCounter,
+{
+    fn deserialize_counted(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self> {
         let Deserialized {
             value: count,
             mut data,
         } = C::mc_deserialize(data)?;
         let count = count.as_count();
-        let mut elems = Vec::with_capacity(count);
+        check_length(count, max_length)?;
+        charge_allocation(count.saturating_mul(core::mem::size_of::<E>()))?;
+        // Every element takes at least a byte, don't trust the count any further than that
+        let mut elems = Vec::with_capacity(count.min(data.len()));
         for _ in 0..count {
             let Deserialized {
                 value: elem,
@@ -780,6 +967,36 @@ where
     }
 }
 
+impl<'a, E, C> DeserializeBorrowed<'a> for CountedArray<E, C>
+where
+    E: DeserializeBorrowedElements<'a>,
+    C: ArrayCounter,
+{
+    type Borrowed = E::Elements;
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, Self::Borrowed> {
+        C::mc_deserialize(data)?.and_then(move |count, rest| E::mc_deserialize_elements(count.as_count(), rest))
+    }
+
+    fn from_borrowed(borrowed: Self::Borrowed) -> Self {
+        E::elements_to_vec(borrowed).into()
+    }
+}
+
+impl<'a, E, C> DeserializeBorrowedLimited<'a> for CountedArray<E, C>
+where
+    E: DeserializeBorrowedElements<'a>,
+    C: ArrayCounter,
+{
+    fn mc_deserialize_borrowed_limited(data: &'a [u8], max_length: usize) -> DeserializeResult<'a, Self::Borrowed> {
+        C::mc_deserialize(data)?.and_then(move |count, rest| {
+            let count = count.as_count();
+            check_length(count, max_length)?;
+            E::mc_deserialize_elements(count, rest)
+        })
+    }
+}
+
 impl<E, C> core::ops::Deref for CountedArray<E, C>
 where
     C: ArrayCounter,
@@ -838,6 +1055,19 @@ where
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+impl<E, C> TestRandomLimited for CountedArray<E, C>
+where
+    E: TestRandom,
+    C: ArrayCounter,
+{
+    fn test_gen_random_limited(max_length: usize) -> Self {
+        let mut out = Self::test_gen_random();
+        out.data.truncate(max_length);
+        out
+    }
+}
+
 impl ArrayCounter for VarInt {
     fn as_count(&self) -> usize {
         self.0 as usize
@@ -891,6 +1121,7 @@ impl Serialize for RemainingBytes {
 
 impl Deserialize for RemainingBytes {
     fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
+        charge_allocation(data.len())?;
         Deserialized::ok(
             RemainingBytes {
                 data: Vec::from(data),
@@ -900,6 +1131,34 @@ impl Deserialize for RemainingBytes {
     }
 }
 
+impl DeserializeLimited for RemainingBytes {
+    fn mc_deserialize_limited(data: &[u8], max_length: usize) -> DeserializeResult<'_, Self> {
+        check_length(data.len(), max_length)?;
+        Self::mc_deserialize(data)
+    }
+}
+
+impl<'a> DeserializeBorrowed<'a> for RemainingBytes {
+    type Borrowed = &'a [u8];
+
+    fn mc_deserialize_borrowed(data: &'a [u8]) -> DeserializeResult<'a, &'a [u8]> {
+        Deserialized::ok(data, &[])
+    }
+
+    fn from_borrowed(borrowed: &'a [u8]) -> Self {
+        RemainingBytes {
+            data: Vec::from(borrowed),
+        }
+    }
+}
+
+impl<'a> DeserializeBorrowedLimited<'a> for RemainingBytes {
+    fn mc_deserialize_borrowed_limited(data: &'a [u8], max_length: usize) -> DeserializeResult<'a, &'a [u8]> {
+        check_length(data.len(), max_length)?;
+        Deserialized::ok(data, &[])
+    }
+}
+
 impl Into<Vec<u8>> for RemainingBytes {
     fn into(self) -> Vec<u8> {
         self.data
@@ -939,6 +1198,15 @@ impl TestRandom for RemainingBytes {
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+impl TestRandomLimited for RemainingBytes {
+    fn test_gen_random_limited(max_length: usize) -> Self {
+        let mut out = Self::test_gen_random();
+        out.data.truncate(max_length);
+        out
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1052,6 +1320,12 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_nbt_chat() {
+        test_type(NbtChat(Chat::from_text("hello my name is joey 123")));
+        test_type(NbtChat(Chat::from_traditional("&ehello &7my name is &l&bjoey", true)));
+    }
+
     #[test]
     fn test_int_position() {
         test_type(IntPosition {
@@ -1121,6 +1395,130 @@ mod tests {
         test_type(Angle { value: 8 });
     }
 
+    #[test]
+    fn test_borrowed_string_points_into_input() {
+        let mut out = BytesSerializer::default();
+        String::from("hello world").mc_serialize(&mut out).unwrap();
+        let bytes = out.into_bytes();
+
+        let Deserialized { value, data } = String::mc_deserialize_borrowed(&bytes).unwrap();
+        assert_eq!(value, "hello world");
+        assert!(data.is_empty());
+        assert_eq!(value.as_ptr(), bytes[1..].as_ptr());
+
+        let mut bad = bytes.clone();
+        bad[1] = 0xFF;
+        assert!(matches!(
+            String::mc_deserialize_borrowed(&bad),
+            Err(DeserializeErr::BadStringEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_borrowed_byte_array_points_into_input() {
+        let array: CountedArray<u8, VarInt> = alloc::vec![1, 2, 3, 4].into();
+        let mut out = BytesSerializer::default();
+        array.mc_serialize(&mut out).unwrap();
+        out.serialize_byte(9).unwrap();
+        let bytes = out.into_bytes();
+
+        let Deserialized { value, data } = CountedArray::<u8, VarInt>::mc_deserialize_borrowed(&bytes).unwrap();
+        assert_eq!(value, &[1, 2, 3, 4]);
+        assert_eq!(value.as_ptr(), bytes[1..].as_ptr());
+        assert_eq!(data, &[9]);
+        assert_eq!(CountedArray::<u8, VarInt>::from_borrowed(value), array);
+
+        let strings: CountedArray<String, VarInt> = alloc::vec!["a".to_owned(), "bc".to_owned()].into();
+        let mut out = BytesSerializer::default();
+        strings.mc_serialize(&mut out).unwrap();
+        let bytes = out.into_bytes();
+        let borrowed = CountedArray::<String, VarInt>::mc_deserialize_borrowed(&bytes).unwrap().value;
+        assert_eq!(borrowed, alloc::vec!["a", "bc"]);
+        assert_eq!(CountedArray::<String, VarInt>::from_borrowed(borrowed), strings);
+    }
+
+    #[test]
+    fn test_string_length_limits() {
+        let serialize = |value: &str| {
+            let mut out = BytesSerializer::default();
+            value.to_owned().mc_serialize(&mut out).unwrap();
+            out.into_bytes()
+        };
+
+        let longest = "a".repeat(MAX_STRING_LENGTH);
+        assert_eq!(String::mc_deserialize(&serialize(&longest)).unwrap().value, longest);
+        let too_long = serialize(&"a".repeat(MAX_STRING_LENGTH + 1));
+        assert!(matches!(String::mc_deserialize(&too_long), Err(DeserializeErr::TooLong { .. })));
+        assert!(matches!(String::mc_deserialize_borrowed(&too_long), Err(DeserializeErr::TooLong { .. })));
+
+        // Three bytes each, but still within the limit in characters
+        let wide = "\u{20AC}".repeat(MAX_STRING_LENGTH);
+        assert_eq!(String::mc_deserialize(&serialize(&wide)).unwrap().value, wide);
+
+        let name = serialize("seventeen_letters");
+        assert!(String::mc_deserialize_limited(&name, 17).is_ok());
+        assert!(matches!(
+            String::mc_deserialize_limited(&name, 16),
+            Err(DeserializeErr::TooLong { length: 17, max: 16 })
+        ));
+
+        // A huge prefix fails on the length, not on reading or allocating
+        let mut bomb = BytesSerializer::default();
+        VarInt(i32::MAX).mc_serialize(&mut bomb).unwrap();
+        assert!(matches!(String::mc_deserialize(&bomb.into_bytes()), Err(DeserializeErr::TooLong { .. })));
+    }
+
+    #[test]
+    fn test_counted_array_limits() {
+        let mut bomb = BytesSerializer::default();
+        VarInt(i32::MAX).mc_serialize(&mut bomb).unwrap();
+        bomb.serialize_bytes(&[0; 8]).unwrap();
+        let bomb = bomb.into_bytes();
+        assert!(matches!(CountedArray::<i64, VarInt>::mc_deserialize(&bomb), Err(DeserializeErr::Eof)));
+        assert!(matches!(
+            CountedArray::<i64, VarInt>::mc_deserialize_limited(&bomb, 4),
+            Err(DeserializeErr::TooLong { .. })
+        ));
+        assert!(matches!(
+            CountedArray::<u8, VarInt>::mc_deserialize_borrowed_limited(&bomb, 4),
+            Err(DeserializeErr::TooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn test_allocation_budget() {
+        let mut out = BytesSerializer::default();
+        "a".repeat(100).mc_serialize(&mut out).unwrap();
+        "a".repeat(100).mc_serialize(&mut out).unwrap();
+        let bytes = out.into_bytes();
+        let read_both = || -> Result<(), DeserializeErr> {
+            let first = String::mc_deserialize(&bytes)?;
+            String::mc_deserialize(first.data)?;
+            Ok(())
+        };
+
+        assert!(with_allocation_budget(200, read_both).is_ok());
+        assert!(matches!(
+            with_allocation_budget(150, read_both),
+            Err(DeserializeErr::AllocationBudgetExceeded { requested: 100, remaining: 50 })
+        ));
+        // Budgets only apply inside with_allocation_budget, and nest
+        assert!(read_both().is_ok());
+        with_allocation_budget(150, || {
+            assert!(with_allocation_budget(1000, || String::mc_deserialize(&bytes)).is_ok());
+            assert!(String::mc_deserialize(&bytes).is_err());
+        });
+
+        let mut array = BytesSerializer::default();
+        VarInt(1000).mc_serialize(&mut array).unwrap();
+        array.serialize_bytes(&[0; 8000]).unwrap();
+        let array = array.into_bytes();
+        assert!(matches!(
+            with_allocation_budget(4000, || CountedArray::<i64, VarInt>::mc_deserialize(&array)),
+            Err(DeserializeErr::AllocationBudgetExceeded { requested: 8000, .. })
+        ));
+    }
+
     fn test_type<S: Serialize + Deserialize + PartialEq + Debug>(value: S) {
         let bytes = {
             let mut test = BytesSerializer::default();
@@ -1,6 +1,6 @@
 use crate::utils::*;
 use serde::{Deserializer, Serializer};
-use alloc::{fmt, string::{ToString, String}};
+use alloc::{fmt, string::{ToString, String}, vec::Vec};
 use fmt::{Display, Debug, Formatter};
 
 #[derive(Copy, Clone, PartialEq, Hash, Eq)]
@@ -78,6 +78,24 @@ impl UUID4 {
         }
     }
 
+    /// A version 3 UUID from the MD5 of `name`, like Java's `UUID.nameUUIDFromBytes`
+    pub fn from_name_bytes(name: &[u8]) -> Self {
+        let mut bytes = md5(name);
+        bytes[6] = (bytes[6] & 0x0F) | 0x30;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+        UUID4 {
+            raw: u128::from_be_bytes(bytes),
+        }
+    }
+
+    /// The UUID an offline mode server gives `username`, the same one vanilla does
+    pub fn offline_player(username: &str) -> Self {
+        let mut name = Vec::with_capacity(14 + username.len());
+        name.extend_from_slice(b"OfflinePlayer:");
+        name.extend_from_slice(username.as_bytes());
+        Self::from_name_bytes(&name)
+    }
+
     pub fn to_u128(self) -> u128 {
         self.raw
     }
@@ -95,6 +113,65 @@ impl UUID4 {
     }
 }
 
+// Per-round shift amounts and the sines table from RFC 1321
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let mut m = [0u32; 16];
+        for (word, bytes) in m.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(MD5_K[i])
+                .wrapping_add(m[g])
+                .rotate_left(MD5_SHIFTS[(i / 16) * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+
+    let mut out = [0u8; 16];
+    for (bytes, word) in out.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
 struct RawUUID<'a> {
     parts: [&'a str; 5],
 }
@@ -235,6 +312,19 @@ mod tests {
         assert_eq!(uuid_hex.as_str(), VALID_UUID)
     }
 
+    #[test]
+    fn test_md5() {
+        assert_eq!(crate::utils::hex(&super::md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(crate::utils::hex(&super::md5(&[b'a'; 1000])), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+    }
+
+    #[test]
+    fn test_offline_player_uuid() {
+        // Checked against what vanilla gives these names in offline mode
+        assert_eq!(UUID4::offline_player("Notch").hex(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+        assert_eq!(UUID4::offline_player("jeb_").hex(), "a762f560-4fce-3236-812a-b80efff0b62b");
+    }
+
     #[test]
     fn test_uuid4_equal() {
         let uuid_a = UUID4::parse(VALID_UUID).expect("should parse valid uuid correctly");
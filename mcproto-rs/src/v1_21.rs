@@ -0,0 +1,548 @@
+//! Protocols 767 (1.21) through 772 (1.21.8), all spoken with the packets in `v1_21_8`.
+//!
+//! Across this range the packets we define only move ids and gain or lose a trailing field, so
+//! older clients are handled by translating ids and those few bodies instead of another protocol.
+
+use crate::protocol::{HasPacketBody, HasPacketId, HasPacketKind, Id, PacketErr, PacketKind, RawPacket};
+use crate::types::{CountedArray, IntPosition, VarInt};
+use crate::uuid::UUID4;
+use crate::v1_21_8::*;
+use crate::{Deserialize, Deserialized, McDeserialize, McSerialize, Serialize, SerializeResult, Serializer};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// 1.21 and 1.21.1
+    V1_21,
+    /// 1.21.2 and 1.21.3
+    V1_21_2,
+    V1_21_4,
+    V1_21_5,
+    V1_21_6,
+    /// 1.21.7 and 1.21.8
+    V1_21_7,
+}
+
+impl ProtocolVersion {
+    pub const ALL: [ProtocolVersion; 6] = [
+        ProtocolVersion::V1_21,
+        ProtocolVersion::V1_21_2,
+        ProtocolVersion::V1_21_4,
+        ProtocolVersion::V1_21_5,
+        ProtocolVersion::V1_21_6,
+        ProtocolVersion::V1_21_7,
+    ];
+
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V1_21_7;
+
+    pub fn from_protocol(protocol: i32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|version| version.protocol() == protocol)
+    }
+
+    pub fn protocol(self) -> i32 {
+        use ProtocolVersion::*;
+        match self {
+            V1_21 => 767,
+            V1_21_2 => 768,
+            V1_21_4 => 769,
+            V1_21_5 => 770,
+            V1_21_6 => 771,
+            V1_21_7 => 772,
+        }
+    }
+
+    /// The newest release speaking this protocol
+    pub fn name(self) -> &'static str {
+        use ProtocolVersion::*;
+        match self {
+            V1_21 => "1.21.1",
+            V1_21_2 => "1.21.3",
+            V1_21_4 => "1.21.4",
+            V1_21_5 => "1.21.5",
+            V1_21_6 => "1.21.6",
+            V1_21_7 => "1.21.8",
+        }
+    }
+
+    // Ids that differ from 1.21.8, every other packet has the same id across the range
+    fn id_changes(self) -> &'static [(Packet772Kind, i32)] {
+        use Packet772Kind::*;
+        use ProtocolVersion::*;
+        match self {
+            V1_21 => &[
+                (PlayBlockUpdate, 0x09),
+                (PlayCookieRequest, 0x16),
+                (PlayDisconnect, 0x1D),
+                (PlayStoreCookie, 0x6B),
+                (PlaySystemChatMessage, 0x6C),
+                (PlayTransfer, 0x73),
+                (PlayChatCommand, 0x04),
+                (PlayCookieResponse, 0x11),
+                (PlayPlayerAction, 0x24),
+                (PlaySynchronizePlayerPosition, 0x40),
+                (PlayChatMessage, 0x06),
+                (PlaySetPlayerPosition, 0x1A),
+                (PlaySetPlayerPositionAndRotation, 0x1B),
+                (PlaySetHeldItem, 0x2F),
+                (PlaySetCreativeModeSlot, 0x32),
+                (PlayUseItemOn, 0x38),
+            ],
+            V1_21_2 => &[
+                (PlayBlockUpdate, 0x09),
+                (PlayCookieRequest, 0x16),
+                (PlayDisconnect, 0x1D),
+                (PlayGameEvent, 0x23),
+                (PlayStoreCookie, 0x72),
+                (PlaySystemChatMessage, 0x73),
+                (PlayChatCommand, 0x05),
+                (PlayCookieResponse, 0x13),
+                (PlayPlayerAction, 0x26),
+                (PlaySynchronizePlayerPosition, 0x42),
+                (PlayChatMessage, 0x07),
+                (PlaySetPlayerPosition, 0x1C),
+                (PlaySetPlayerPositionAndRotation, 0x1D),
+                (PlaySetHeldItem, 0x31),
+                (PlaySetCreativeModeSlot, 0x34),
+                (PlayUseItemOn, 0x3A),
+            ],
+            V1_21_4 => &[
+                (PlayBlockUpdate, 0x09),
+                (PlayCookieRequest, 0x16),
+                (PlayDisconnect, 0x1D),
+                (PlayGameEvent, 0x23),
+                (PlayStoreCookie, 0x72),
+                (PlaySystemChatMessage, 0x73),
+                (PlayChatCommand, 0x05),
+                (PlayCookieResponse, 0x13),
+                (PlayPlayerAction, 0x27),
+                (PlaySynchronizePlayerPosition, 0x42),
+                (PlayChatMessage, 0x07),
+                (PlaySetPlayerPosition, 0x1C),
+                (PlaySetPlayerPositionAndRotation, 0x1D),
+                (PlaySetHeldItem, 0x33),
+                (PlaySetCreativeModeSlot, 0x36),
+                (PlayUseItemOn, 0x3C),
+            ],
+            V1_21_5 => &[
+                (PlayChatCommand, 0x05),
+                (PlayCookieResponse, 0x13),
+                (PlayPlayerAction, 0x27),
+                (PlayChatMessage, 0x07),
+                (PlaySetPlayerPosition, 0x1C),
+                (PlaySetPlayerPositionAndRotation, 0x1D),
+                (PlaySetHeldItem, 0x33),
+                (PlaySetCreativeModeSlot, 0x36),
+                (PlayUseItemOn, 0x3E),
+            ],
+            V1_21_6 | V1_21_7 => &[],
+        }
+    }
+
+    /// The id this version uses for a packet
+    pub fn id_for(self, kind: Packet772Kind) -> Id {
+        let mut id = kind.id();
+        if let Some((_, changed)) = self.id_changes().iter().find(|(changed, _)| *changed == kind) {
+            id.id = *changed;
+        }
+        id
+    }
+
+    /// Maps an id read from a client on this version to the 1.21.8 id of the same packet, `None`
+    /// if it's a packet we don't define
+    pub fn to_latest_id(self, id: Id) -> Option<Id> {
+        let changes = self.id_changes();
+        let moved = changes
+            .iter()
+            .map(|(kind, changed)| (kind.id(), *changed))
+            .find(|(latest, changed)| latest.state == id.state && latest.direction == id.direction && *changed == id.id);
+        if let Some((latest, _)) = moved {
+            return Some(latest);
+        }
+
+        // Our packets that moved leave their 1.21.8 id to something else on this version
+        match Packet772Kind::from_id(id) {
+            Some(kind) if changes.iter().all(|(changed, _)| *changed != kind) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Reads a packet sent by this version, `raw` having been created with the id from `to_latest_id`
+    pub fn deserialize(self, raw: &RawPacket772<'_>) -> Result<Packet772, PacketErr> {
+        match (self, raw) {
+            (ProtocolVersion::V1_21, RawPacket772::ConfigurationClientInformation(body)) => {
+                deserialize_body::<ClientInformation767>(body.data, "ConfigurationClientInformation")
+                    .map(|spec| Packet772::ConfigurationClientInformation(spec.into()))
+            }
+            (ProtocolVersion::V1_21, RawPacket772::LoginSuccess(body)) => {
+                deserialize_body::<LoginSuccess767>(body.data, "LoginSuccess")
+                    .map(|spec| Packet772::LoginSuccess(spec.into()))
+            }
+            (ProtocolVersion::V1_21, RawPacket772::PlaySynchronizePlayerPosition(body)) => {
+                deserialize_body::<SynchronizePlayerPosition767>(body.data, "PlaySynchronizePlayerPosition")
+                    .map(|spec| Packet772::PlaySynchronizePlayerPosition(spec.into()))
+            }
+            (ProtocolVersion::V1_21, RawPacket772::PlayUseItemOn(body)) => {
+                deserialize_body::<UseItemOn767>(body.data, "PlayUseItemOn")
+                    .map(|spec| Packet772::PlayUseItemOn(spec.into()))
+            }
+            _ => raw.deserialize(),
+        }
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        Self::LATEST
+    }
+}
+
+fn deserialize_body<T: Deserialize>(data: &[u8], name: &'static str) -> Result<T, PacketErr> {
+    let Deserialized { value, data: rest } =
+        T::mc_deserialize(data).map_err(|err| PacketErr::DeserializeFailed(err.in_field(name, 0)))?;
+    if !rest.is_empty() {
+        return Err(PacketErr::ExtraData(rest.to_vec()));
+    }
+    Ok(value)
+}
+
+/// Writes a packet, id included, the way `version` expects it
+pub struct VersionedPacket<'a> {
+    pub version: ProtocolVersion,
+    pub packet: &'a Packet772,
+}
+
+impl Serialize for VersionedPacket<'_> {
+    fn mc_serialize<S: Serializer>(&self, to: &mut S) -> SerializeResult {
+        self.version.id_for(self.packet.kind()).mc_serialize(to)?;
+        match (self.version, self.packet) {
+            (ProtocolVersion::V1_21, Packet772::ConfigurationClientInformation(spec)) => {
+                to.serialize_other(&ClientInformation767::from(spec.clone()))
+            }
+            (ProtocolVersion::V1_21, Packet772::LoginSuccess(spec)) => {
+                to.serialize_other(&LoginSuccess767::from(spec.clone()))
+            }
+            (ProtocolVersion::V1_21, Packet772::PlaySynchronizePlayerPosition(spec)) => {
+                to.serialize_other(&SynchronizePlayerPosition767::from(spec.clone()))
+            }
+            (ProtocolVersion::V1_21, Packet772::PlayUseItemOn(spec)) => {
+                to.serialize_other(&UseItemOn767::from(spec.clone()))
+            }
+            _ => self.packet.mc_serialize_body(to),
+        }
+    }
+}
+
+// 1.21.2 added particle_status to the end of Client Information
+#[derive(Debug, Clone, PartialEq, McSerialize, McDeserialize)]
+struct ClientInformation767 {
+    #[mc(max_length = 16)]
+    locale: String,
+    view_distance: u8,
+    chat_mode: ChatMode,
+    chat_colours: bool,
+    display_skin_parts: u8,
+    main_hand: MainHand,
+    text_filtering: bool,
+    allow_list_players: bool,
+}
+
+impl From<ClientInformation767> for ConfigurationClientInformationSpec {
+    fn from(other: ClientInformation767) -> Self {
+        Self {
+            locale: other.locale,
+            view_distance: other.view_distance,
+            chat_mode: other.chat_mode,
+            chat_colours: other.chat_colours,
+            display_skin_parts: other.display_skin_parts,
+            main_hand: other.main_hand,
+            text_filtering: other.text_filtering,
+            allow_list_players: other.allow_list_players,
+            particle_status: ParticleStatus::All,
+        }
+    }
+}
+
+impl From<ConfigurationClientInformationSpec> for ClientInformation767 {
+    fn from(other: ConfigurationClientInformationSpec) -> Self {
+        Self {
+            locale: other.locale,
+            view_distance: other.view_distance,
+            chat_mode: other.chat_mode,
+            chat_colours: other.chat_colours,
+            display_skin_parts: other.display_skin_parts,
+            main_hand: other.main_hand,
+            text_filtering: other.text_filtering,
+            allow_list_players: other.allow_list_players,
+        }
+    }
+}
+
+// 1.21.2 dropped strict_error_handling from the end of Login Success
+#[derive(Debug, Clone, PartialEq, McSerialize, McDeserialize)]
+struct LoginSuccess767 {
+    uuid: UUID4,
+    username: String,
+    #[mc(count = VarInt)]
+    properties: Vec<LoginSuccessProperty>,
+    strict_error_handling: bool,
+}
+
+impl From<LoginSuccess767> for LoginSuccessSpec {
+    fn from(other: LoginSuccess767) -> Self {
+        Self {
+            uuid: other.uuid,
+            username: other.username,
+            properties: CountedArray::from(other.properties),
+        }
+    }
+}
+
+impl From<LoginSuccessSpec> for LoginSuccess767 {
+    fn from(other: LoginSuccessSpec) -> Self {
+        Self {
+            uuid: other.uuid,
+            username: other.username,
+            properties: other.properties.into(),
+            strict_error_handling: false,
+        }
+    }
+}
+
+// 1.21.2 moved the teleport id to the front, added velocity and widened the flags to an int
+#[derive(Debug, Clone, PartialEq, McSerialize, McDeserialize)]
+struct SynchronizePlayerPosition767 {
+    x: f64,
+    y: f64,
+    z: f64,
+    yaw: f32,
+    pitch: f32,
+    flags: u8,
+    teleport_id: VarInt,
+}
+
+impl From<SynchronizePlayerPosition767> for PlaySynchronizePlayerPositionSpec {
+    fn from(other: SynchronizePlayerPosition767) -> Self {
+        Self {
+            teleport_id: other.teleport_id,
+            x: other.x,
+            y: other.y,
+            z: other.z,
+            velocity_x: 0.0,
+            velocity_y: 0.0,
+            velocity_z: 0.0,
+            yaw: other.yaw,
+            pitch: other.pitch,
+            flags: other.flags.into(),
+        }
+    }
+}
+
+impl From<PlaySynchronizePlayerPositionSpec> for SynchronizePlayerPosition767 {
+    fn from(other: PlaySynchronizePlayerPositionSpec) -> Self {
+        Self {
+            x: other.x,
+            y: other.y,
+            z: other.z,
+            yaw: other.yaw,
+            pitch: other.pitch,
+            // Only the position and rotation flags existed, and they were the low bits
+            flags: other.flags as u8,
+            teleport_id: other.teleport_id,
+        }
+    }
+}
+
+// 1.21.2 added world_border_hit before the sequence of Use Item On
+#[derive(Debug, Clone, PartialEq, McSerialize, McDeserialize)]
+struct UseItemOn767 {
+    hand: Hand,
+    location: IntPosition,
+    face: BlockFace,
+    cursor_x: f32,
+    cursor_y: f32,
+    cursor_z: f32,
+    inside_block: bool,
+    sequence: VarInt,
+}
+
+impl From<UseItemOn767> for PlayUseItemOnSpec {
+    fn from(other: UseItemOn767) -> Self {
+        Self {
+            hand: other.hand,
+            location: other.location,
+            face: other.face,
+            cursor_x: other.cursor_x,
+            cursor_y: other.cursor_y,
+            cursor_z: other.cursor_z,
+            inside_block: other.inside_block,
+            world_border_hit: false,
+            sequence: other.sequence,
+        }
+    }
+}
+
+impl From<PlayUseItemOnSpec> for UseItemOn767 {
+    fn from(other: PlayUseItemOnSpec) -> Self {
+        Self {
+            hand: other.hand,
+            location: other.location,
+            face: other.face,
+            cursor_x: other.cursor_x,
+            cursor_y: other.cursor_y,
+            cursor_z: other.cursor_z,
+            inside_block: other.inside_block,
+            sequence: other.sequence,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::protocol::{PacketDirection, PacketWithId, State, TestRandom};
+    use crate::types::BytesSerializer;
+
+    fn write(packet: impl Serialize) -> Vec<u8> {
+        let mut out = BytesSerializer::default();
+        packet.mc_serialize(&mut out).unwrap();
+        out.into_bytes()
+    }
+
+    // every packet we define, found by trying each id in each state
+    fn all_kinds() -> Vec<Packet772Kind> {
+        let states = [State::Handshaking, State::Status, State::Login, State::Configuration, State::Play];
+        let directions = [PacketDirection::ClientBound, PacketDirection::ServerBound];
+        let mut kinds = Vec::new();
+        for state in states.iter() {
+            for direction in directions.iter() {
+                for id in 0..0x100 {
+                    kinds.extend(Packet772Kind::from_id(Id { id, state: *state, direction: *direction }));
+                }
+            }
+        }
+        kinds
+    }
+
+    #[test]
+    fn test_protocol_numbers() {
+        for version in ProtocolVersion::ALL.iter() {
+            assert_eq!(ProtocolVersion::from_protocol(version.protocol()), Some(*version));
+        }
+        assert_eq!(ProtocolVersion::LATEST.protocol(), Packet772::version().0);
+        assert_eq!(ProtocolVersion::from_protocol(766), None);
+        assert_eq!(ProtocolVersion::from_protocol(773), None);
+    }
+
+    #[test]
+    fn test_ids_round_trip() {
+        let kinds = all_kinds();
+        assert!(!kinds.is_empty());
+        for version in ProtocolVersion::ALL.iter() {
+            for kind in kinds.iter() {
+                let id = version.id_for(*kind);
+                assert_eq!(version.to_latest_id(id), Some(kind.id()), "{:?} {:?}", version, kind);
+            }
+        }
+    }
+
+    #[test]
+    fn test_moved_ids() {
+        let chat_command = |id| Id { id, state: State::Play, direction: PacketDirection::ServerBound };
+        let v1_21 = ProtocolVersion::V1_21;
+        assert_eq!(v1_21.to_latest_id(chat_command(0x04)), Some(Packet772Kind::PlayChatCommand.id()));
+        // 0x06 is chat command on 1.21.8 but a chat message on 1.21
+        assert_eq!(v1_21.to_latest_id(chat_command(0x06)), Some(Packet772Kind::PlayChatMessage.id()));
+        // and 0x08 is chat message on 1.21.8 but chunk batch received on 1.21
+        assert_eq!(v1_21.to_latest_id(chat_command(0x08)), None);
+        assert_eq!(ProtocolVersion::LATEST.to_latest_id(chat_command(0x06)), Some(Packet772Kind::PlayChatCommand.id()));
+
+        let login_start = Packet772Kind::LoginStart.id();
+        assert_eq!(v1_21.to_latest_id(login_start), Some(login_start));
+    }
+
+    #[test]
+    fn test_latest_writes_unchanged() {
+        for _ in 0..20 {
+            let packet = Packet772::LoginSuccess(LoginSuccessSpec::test_gen_random());
+            let versioned = VersionedPacket { version: ProtocolVersion::LATEST, packet: &packet };
+            assert_eq!(write(versioned), write(PacketWithId(&packet)));
+        }
+    }
+
+    #[test]
+    fn test_login_success_1_21() {
+        let packet = Packet772::LoginSuccess(LoginSuccessSpec::test_gen_random());
+        let bytes = write(VersionedPacket { version: ProtocolVersion::V1_21, packet: &packet });
+        let mut expected = write(PacketWithId(&packet));
+        expected.push(0);
+        assert_eq!(bytes, expected);
+
+        let raw = RawPacket772::create(Packet772Kind::LoginSuccess.id(), &bytes[1..]).unwrap();
+        assert_eq!(ProtocolVersion::V1_21.deserialize(&raw).unwrap(), packet);
+        assert!(ProtocolVersion::V1_21_2.deserialize(&raw).is_err());
+    }
+
+    #[test]
+    fn test_client_information_1_21() {
+        let spec = ConfigurationClientInformationSpec {
+            particle_status: ParticleStatus::All,
+            ..ConfigurationClientInformationSpec::test_gen_random()
+        };
+        let packet = Packet772::ConfigurationClientInformation(spec);
+        let bytes = write(VersionedPacket { version: ProtocolVersion::V1_21, packet: &packet });
+        let latest = write(PacketWithId(&packet));
+        assert_eq!(bytes[..], latest[..latest.len() - 1]);
+
+        let raw = RawPacket772::create(Packet772Kind::ConfigurationClientInformation.id(), &bytes[1..]).unwrap();
+        assert_eq!(ProtocolVersion::V1_21.deserialize(&raw).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_synchronize_player_position_1_21() {
+        let packet = Packet772::PlaySynchronizePlayerPosition(PlaySynchronizePlayerPositionSpec {
+            teleport_id: VarInt(7),
+            x: 1.5,
+            y: 64.0,
+            z: -3.5,
+            velocity_x: 0.0,
+            velocity_y: 0.0,
+            velocity_z: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            flags: TELEPORT_RELATIVE_YAW | TELEPORT_RELATIVE_PITCH,
+        });
+        let bytes = write(VersionedPacket { version: ProtocolVersion::V1_21, packet: &packet });
+        assert_eq!(bytes[0], 0x40);
+        // Position, rotation, one flag byte and the teleport id last
+        assert_eq!(bytes.len(), 1 + 3 * 8 + 2 * 4 + 1 + 1);
+        assert_eq!(bytes[bytes.len() - 2..], [0x18, 7]);
+
+        let raw = RawPacket772::create(Packet772Kind::PlaySynchronizePlayerPosition.id(), &bytes[1..]).unwrap();
+        assert_eq!(ProtocolVersion::V1_21.deserialize(&raw).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_use_item_on_1_21() {
+        let packet = Packet772::PlayUseItemOn(PlayUseItemOnSpec {
+            hand: Hand::MainHand,
+            location: IntPosition { x: 1, y: 64, z: -2 },
+            face: BlockFace::Top,
+            cursor_x: 0.5,
+            cursor_y: 1.0,
+            cursor_z: 0.5,
+            inside_block: false,
+            world_border_hit: false,
+            sequence: VarInt(3),
+        });
+        let bytes = write(VersionedPacket { version: ProtocolVersion::V1_21, packet: &packet });
+        assert_eq!(bytes[0], 0x38);
+        let latest = write(PacketWithId(&packet));
+        // Everything but the world border flag just before the sequence
+        assert_eq!(bytes[1..bytes.len() - 1], latest[1..latest.len() - 2]);
+        assert_eq!(bytes[bytes.len() - 1], 3);
+
+        let raw = RawPacket772::create(Packet772Kind::PlayUseItemOn.id(), &bytes[1..]).unwrap();
+        assert_eq!(ProtocolVersion::V1_21.deserialize(&raw).unwrap(), packet);
+    }
+}
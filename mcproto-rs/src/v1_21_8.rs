@@ -4,6 +4,9 @@ use alloc::fmt;
 use alloc::string::String;
 use fmt::Debug;
 
+#[cfg(all(test, feature = "std"))]
+use crate::protocol::TestRandom;
+
 proto_byte_enum!(HandshakeIntent,
     0x01 :: Status,
     0x02 :: Login,
@@ -27,7 +30,48 @@ proto_byte_enum!(ParticleStatus,
     0x02 :: Minimal
 );
 
-define_protocol!(772, Packet772, RawPacket772, RawPacket772Body, Packet772Kind => {
+proto_varint_enum!(PlayerActionStatus,
+    0x00 :: StartedDigging,
+    0x01 :: CancelledDigging,
+    0x02 :: FinishedDigging,
+    0x03 :: DropItemStack,
+    0x04 :: DropItem,
+    0x05 :: ShootArrowOrFinishEating,
+    0x06 :: SwapItemInHand
+);
+
+proto_byte_enum!(GameEvent,
+    0x00 :: NoRespawnBlockAvailable,
+    0x01 :: BeginRaining,
+    0x02 :: EndRaining,
+    0x03 :: ChangeGameMode,
+    0x04 :: WinGame,
+    0x05 :: DemoEvent,
+    0x06 :: ArrowHitPlayer,
+    0x07 :: RainLevelChange,
+    0x08 :: ThunderLevelChange,
+    0x09 :: PlayPufferfishStingSound,
+    0x0A :: PlayElderGuardianMobAppearance,
+    0x0B :: EnableRespawnScreen,
+    0x0C :: LimitedCrafting,
+    0x0D :: StartWaitingForLevelChunks
+);
+
+proto_byte_enum!(BlockFace,
+    0x00 :: Bottom,
+    0x01 :: Top,
+    0x02 :: North,
+    0x03 :: South,
+    0x04 :: West,
+    0x05 :: East
+);
+
+proto_varint_enum!(Hand,
+    0x00 :: MainHand,
+    0x01 :: OffHand
+);
+
+define_protocol!(772, Packet772, RawPacket772, RawPacket772Body, Packet772Kind, Packet772Borrowed => {
     PingRequest, 0x01, Status, ServerBound => PingRequestSpec {
         payload: u64
     },
@@ -36,17 +80,21 @@ define_protocol!(772, Packet772, RawPacket772, RawPacket772Body, Packet772Kind =
     },
     Handshake, 0x00, Handshaking, ServerBound => HandshakeSpec {
         protocol_version: VarInt,
-        server_address: String,
+        // Longer than vanilla's 255, BungeeCord forwarding packs the player's ip, uuid and skin in here
+        #[max_length = 32767] server_address: String,
         server_port: u16,
         intent: HandshakeIntent
     },
     StatusRequest, 0x00, Status, ServerBound => StatusRequestSpec {
     },
     StatusResponse, 0x00, Status, ClientBound => StatusResponseSpec {
-        response: super::status::StatusSpec
+        response: crate::status::StatusSpec
+    },
+    LoginDisconnect, 0x00, Login, ClientBound => LoginDisconnectSpec {
+        reason: Chat
     },
     LoginStart, 0x00, Login, ServerBound => LoginStartSpec {
-        name: String,
+        #[max_length = 16] name: String,
         uuid: UUID4
     },
     LoginEncryptionRequest, 0x01, Login, ClientBound => LoginEncryptionRequestSpec {
@@ -64,10 +112,31 @@ define_protocol!(772, Packet772, RawPacket772, RawPacket772Body, Packet772Kind =
         username: String,
         properties: CountedArray<LoginSuccessProperty, VarInt>
     },
+    LoginSetCompression, 0x03, Login, ClientBound => LoginSetCompressionSpec {
+        threshold: VarInt
+    },
     LoginAcknowledged, 0x03, Login, ServerBound => LoginAcknowledgedSpec {
     },
+    LoginPluginRequest, 0x04, Login, ClientBound => LoginPluginRequestSpec {
+        message_id: VarInt,
+        channel: String,
+        #[max_length = 1048576] data: RemainingBytes
+    },
+    LoginPluginResponse, 0x02, Login, ServerBound => LoginPluginResponseSpec {
+        message_id: VarInt,
+        // None if the client didn't understand the channel
+        #[max_length = 1048576] data: Option<RemainingBytes>
+    },
+    LoginCookieRequest, 0x05, Login, ClientBound => LoginCookieRequestSpec {
+        key: String
+    },
+    LoginCookieResponse, 0x04, Login, ServerBound => LoginCookieResponseSpec {
+        key: String,
+        // None if the client has no cookie with this key
+        #[max_length = 5120] payload: Option<CountedArray<u8, VarInt>>
+    },
     ConfigurationClientInformation, 0x00, Configuration, ServerBound => ConfigurationClientInformationSpec {
-        locale: String,
+        #[max_length = 16] locale: String,
         view_distance: u8,
         chat_mode: ChatMode,
         chat_colours: bool,
@@ -77,45 +146,517 @@ define_protocol!(772, Packet772, RawPacket772, RawPacket772Body, Packet772Kind =
         allow_list_players: bool,
         particle_status: ParticleStatus
     },
+    ConfigurationCookieRequest, 0x00, Configuration, ClientBound => ConfigurationCookieRequestSpec {
+        key: String
+    },
+    ConfigurationCookieResponse, 0x01, Configuration, ServerBound => ConfigurationCookieResponseSpec {
+        key: String,
+        #[max_length = 5120] payload: Option<CountedArray<u8, VarInt>>
+    },
     ServerBoundPluginMessage, 0x02, Configuration, ServerBound => ServerBoundPluginMessageSpec {
         id: String,
-        data: RemainingBytes
+        #[max_length = 32767] data: RemainingBytes
+    },
+    ConfigurationDisconnect, 0x02, Configuration, ClientBound => ConfigurationDisconnectSpec {
+        reason: NbtChat
     },
     ConfigurationFinish, 0x03, Configuration, ClientBound => ConfigurationFinishSpec {
     },
     ConfigurationFinishAck, 0x03, Configuration, ServerBound => ConfigurationFinishAckSpec {
+    },
+    ConfigurationStoreCookie, 0x0A, Configuration, ClientBound => ConfigurationStoreCookieSpec {
+        key: String,
+        #[max_length = 5120] payload: CountedArray<u8, VarInt>
+    },
+    ConfigurationTransfer, 0x0B, Configuration, ClientBound => ConfigurationTransferSpec {
+        host: String,
+        port: VarInt
+    },
+    PlayBlockUpdate, 0x08, Play, ClientBound => PlayBlockUpdateSpec {
+        location: IntPosition,
+        block_id: VarInt
+    },
+    PlayCookieRequest, 0x15, Play, ClientBound => PlayCookieRequestSpec {
+        key: String
+    },
+    PlayDisconnect, 0x1C, Play, ClientBound => PlayDisconnectSpec {
+        reason: NbtChat
+    },
+    PlayGameEvent, 0x22, Play, ClientBound => PlayGameEventSpec {
+        event: GameEvent,
+        value: f32
+    },
+    PlaySystemChatMessage, 0x72, Play, ClientBound => PlaySystemChatMessageSpec {
+        content: NbtChat,
+        overlay: bool
+    },
+    PlayStoreCookie, 0x71, Play, ClientBound => PlayStoreCookieSpec {
+        key: String,
+        #[max_length = 5120] payload: CountedArray<u8, VarInt>
+    },
+    PlayTransfer, 0x7A, Play, ClientBound => PlayTransferSpec {
+        host: String,
+        port: VarInt
+    },
+    PlaySynchronizePlayerPosition, 0x41, Play, ClientBound => PlaySynchronizePlayerPositionSpec {
+        teleport_id: VarInt,
+        x: f64,
+        y: f64,
+        z: f64,
+        velocity_x: f64,
+        velocity_y: f64,
+        velocity_z: f64,
+        yaw: f32,
+        pitch: f32,
+        // Which of the above are relative to where the player is, see `TELEPORT_RELATIVE_*`
+        flags: i32
+    },
+    PlayChatCommand, 0x06, Play, ServerBound => PlayChatCommandSpec {
+        #[max_length = 256] command: String
+    },
+    PlayChatMessage, 0x08, Play, ServerBound => PlayChatMessageSpec {
+        #[max_length = 256] message: String,
+        // Timestamp, salt, signature and acknowledgements, none of which we check
+        #[max_length = 512] signing: RemainingBytes
+    },
+    PlaySetPlayerPosition, 0x1D, Play, ServerBound => PlaySetPlayerPositionSpec {
+        x: f64,
+        feet_y: f64,
+        z: f64,
+        // Just on_ground before 1.21.2, which is the same byte as the first flag
+        flags: u8
+    },
+    PlaySetPlayerPositionAndRotation, 0x1E, Play, ServerBound => PlaySetPlayerPositionAndRotationSpec {
+        x: f64,
+        feet_y: f64,
+        z: f64,
+        yaw: f32,
+        pitch: f32,
+        flags: u8
+    },
+    PlayCookieResponse, 0x14, Play, ServerBound => PlayCookieResponseSpec {
+        key: String,
+        #[max_length = 5120] payload: Option<CountedArray<u8, VarInt>>
+    },
+    PlayPlayerAction, 0x28, Play, ServerBound => PlayPlayerActionSpec {
+        status: PlayerActionStatus,
+        location: IntPosition,
+        face: BlockFace,
+        sequence: VarInt
+    },
+    PlaySetHeldItem, 0x34, Play, ServerBound => PlaySetHeldItemSpec {
+        // 0 to 8, across the hotbar
+        slot: i16
+    },
+    PlaySetCreativeModeSlot, 0x37, Play, ServerBound => PlaySetCreativeModeSlotSpec {
+        // Numbered like the player's inventory window, the hotbar is 36 to 44
+        slot: i16,
+        // Count, id and components, which can hold a whole book. See `item_id`
+        #[max_length = 8192] item: RemainingBytes
+    },
+    PlayUseItemOn, 0x3F, Play, ServerBound => PlayUseItemOnSpec {
+        hand: Hand,
+        location: IntPosition,
+        // A VarInt here, which is the same single byte as Player Action's face for every face
+        face: BlockFace,
+        cursor_x: f32,
+        cursor_y: f32,
+        cursor_z: f32,
+        inside_block: bool,
+        world_border_hit: bool,
+        sequence: VarInt
     }
 });
 
-#[derive(Debug, Clone, PartialEq)]
+/// `PlaySynchronizePlayerPositionSpec::flags` that keep the player's own yaw and pitch
+pub const TELEPORT_RELATIVE_YAW: i32 = 0x08;
+pub const TELEPORT_RELATIVE_PITCH: i32 = 0x10;
+
+impl PlaySetCreativeModeSlotSpec {
+    /// The id of the item put in the slot, `None` if it was emptied
+    pub fn item_id(&self) -> Option<i32> {
+        let Deserialized { value: count, data } = VarInt::mc_deserialize(&self.item.data).ok()?;
+        if count.0 <= 0 {
+            return None;
+        }
+        VarInt::mc_deserialize(data).ok().map(|id| id.value.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, McSerialize, McDeserialize)]
 pub struct LoginSuccessProperty {
-    name: String,
-    value: String,
-    signature: Option<String>,
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+borrow_as_owned!(LoginSuccessProperty);
+
+impl From<crate::auth::ProfileProperty> for LoginSuccessProperty {
+    fn from(property: crate::auth::ProfileProperty) -> Self {
+        Self {
+            name: property.name,
+            value: property.value,
+            signature: property.signature,
+        }
+    }
 }
 
-impl Serialize for LoginSuccessProperty {
-    fn mc_serialize<S: Serializer>(&self, to: &mut S) -> SerializeResult {
-        to.serialize_other(&self.name)?;
-        to.serialize_other(&self.value)?;
-        to.serialize_other(&self.signature)?;
-        Ok(())
+#[cfg(all(test, feature = "std"))]
+impl TestRandom for LoginSuccessProperty {
+    fn test_gen_random() -> Self {
+        Self {
+            name: String::test_gen_random(),
+            value: String::test_gen_random(),
+            signature: <Option<String>>::test_gen_random(),
+        }
     }
 }
 
-impl Deserialize for LoginSuccessProperty {
-    fn mc_deserialize(data: &[u8]) -> DeserializeResult<'_, Self> {
-        let name = String::mc_deserialize(data)?;
-        let value = String::mc_deserialize(name.data)?;
-        let signature = Option::<String>::mc_deserialize(value.data)?;
-
-        Ok(Deserialized {
-            value: Self {
-                name: name.value,
-                value: value.value,
-                signature: signature.value,
-            },
-            data: signature.data,
-        })
+#[cfg(all(test, feature = "std"))]
+pub mod tests {
+    use super::*;
+    use crate::{borrowed_packet_test_cases, packet_test_cases};
+
+    packet_test_cases!(RawPacket772, Packet772, Handshake, HandshakeSpec,
+        test_handshake, bench_write_handshake, bench_read_handshake);
+
+    packet_test_cases!(RawPacket772, Packet772, StatusResponse, StatusResponseSpec,
+        test_status_response, bench_write_status_response, bench_read_status_response);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginDisconnect, LoginDisconnectSpec,
+        test_login_disconnect, bench_write_login_disconnect, bench_read_login_disconnect);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginStart, LoginStartSpec,
+        test_login_start, bench_write_login_start, bench_read_login_start);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginEncryptionRequest, LoginEncryptionRequestSpec,
+        test_login_encryption_request, bench_write_login_encryption_request, bench_read_login_encryption_request);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginSuccess, LoginSuccessSpec,
+        test_login_success, bench_write_login_success, bench_read_login_success);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginSetCompression, LoginSetCompressionSpec,
+        test_login_set_compression, bench_write_login_set_compression, bench_read_login_set_compression);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationClientInformation, ConfigurationClientInformationSpec,
+        test_configuration_client_information, bench_write_configuration_client_information, bench_read_configuration_client_information);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationDisconnect, ConfigurationDisconnectSpec,
+        test_configuration_disconnect, bench_write_configuration_disconnect, bench_read_configuration_disconnect);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayBlockUpdate, PlayBlockUpdateSpec,
+        test_play_block_update, bench_write_play_block_update, bench_read_play_block_update);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayDisconnect, PlayDisconnectSpec,
+        test_play_disconnect, bench_write_play_disconnect, bench_read_play_disconnect);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayGameEvent, PlayGameEventSpec,
+        test_play_game_event, bench_write_play_game_event, bench_read_play_game_event);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySystemChatMessage, PlaySystemChatMessageSpec,
+        test_play_system_chat_message, bench_write_play_system_chat_message, bench_read_play_system_chat_message);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginPluginRequest, LoginPluginRequestSpec,
+        test_login_plugin_request, bench_write_login_plugin_request, bench_read_login_plugin_request);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginPluginResponse, LoginPluginResponseSpec,
+        test_login_plugin_response, bench_write_login_plugin_response, bench_read_login_plugin_response);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginCookieRequest, LoginCookieRequestSpec,
+        test_login_cookie_request, bench_write_login_cookie_request, bench_read_login_cookie_request);
+
+    packet_test_cases!(RawPacket772, Packet772, LoginCookieResponse, LoginCookieResponseSpec,
+        test_login_cookie_response, bench_write_login_cookie_response, bench_read_login_cookie_response);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationCookieRequest, ConfigurationCookieRequestSpec,
+        test_configuration_cookie_request, bench_write_configuration_cookie_request, bench_read_configuration_cookie_request);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationCookieResponse, ConfigurationCookieResponseSpec,
+        test_configuration_cookie_response, bench_write_configuration_cookie_response, bench_read_configuration_cookie_response);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationStoreCookie, ConfigurationStoreCookieSpec,
+        test_configuration_store_cookie, bench_write_configuration_store_cookie, bench_read_configuration_store_cookie);
+
+    packet_test_cases!(RawPacket772, Packet772, ConfigurationTransfer, ConfigurationTransferSpec,
+        test_configuration_transfer, bench_write_configuration_transfer, bench_read_configuration_transfer);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayCookieRequest, PlayCookieRequestSpec,
+        test_play_cookie_request, bench_write_play_cookie_request, bench_read_play_cookie_request);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayCookieResponse, PlayCookieResponseSpec,
+        test_play_cookie_response, bench_write_play_cookie_response, bench_read_play_cookie_response);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayStoreCookie, PlayStoreCookieSpec,
+        test_play_store_cookie, bench_write_play_store_cookie, bench_read_play_store_cookie);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayTransfer, PlayTransferSpec,
+        test_play_transfer, bench_write_play_transfer, bench_read_play_transfer);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayChatCommand, PlayChatCommandSpec,
+        test_play_chat_command, bench_write_play_chat_command, bench_read_play_chat_command);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayPlayerAction, PlayPlayerActionSpec,
+        test_play_player_action, bench_write_play_player_action, bench_read_play_player_action);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySynchronizePlayerPosition, PlaySynchronizePlayerPositionSpec,
+        test_play_synchronize_player_position, bench_write_play_synchronize_player_position, bench_read_play_synchronize_player_position);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayChatMessage, PlayChatMessageSpec,
+        test_play_chat_message, bench_write_play_chat_message, bench_read_play_chat_message);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySetPlayerPosition, PlaySetPlayerPositionSpec,
+        test_play_set_player_position, bench_write_play_set_player_position, bench_read_play_set_player_position);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySetPlayerPositionAndRotation, PlaySetPlayerPositionAndRotationSpec,
+        test_play_set_player_position_and_rotation, bench_write_play_set_player_position_and_rotation, bench_read_play_set_player_position_and_rotation);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySetHeldItem, PlaySetHeldItemSpec,
+        test_play_set_held_item, bench_write_play_set_held_item, bench_read_play_set_held_item);
+
+    packet_test_cases!(RawPacket772, Packet772, PlaySetCreativeModeSlot, PlaySetCreativeModeSlotSpec,
+        test_play_set_creative_mode_slot, bench_write_play_set_creative_mode_slot, bench_read_play_set_creative_mode_slot);
+
+    packet_test_cases!(RawPacket772, Packet772, PlayUseItemOn, PlayUseItemOnSpec,
+        test_play_use_item_on, bench_write_play_use_item_on, bench_read_play_use_item_on);
+
+    #[test]
+    fn test_creative_slot_item_id() {
+        let slot = |item: &[u8]| PlaySetCreativeModeSlotSpec { slot: 36, item: RemainingBytes { data: item.to_vec() } };
+        assert_eq!(slot(&[0x00]).item_id(), None);
+        // 64 dirt with no components
+        assert_eq!(slot(&[0x40, 0x1C, 0x00, 0x00]).item_id(), Some(28));
+        assert_eq!(slot(&[0x01, 0xAC, 0x02, 0x01, 0x00]).item_id(), Some(300));
+        assert_eq!(slot(&[]).item_id(), None);
+        assert_eq!(slot(&[0x01]).item_id(), None);
+    }
+
+    #[test]
+    fn test_field_max_length() {
+        use crate::protocol::{HasPacketId, PacketErr, RawPacket};
+
+        let login_start = |name: &str| {
+            let mut out = BytesSerializer::default();
+            LoginStartSpec { name: name.to_owned(), uuid: UUID4::random() }
+                .mc_serialize(&mut out)
+                .unwrap();
+            out.into_bytes()
+        };
+        let raw = |data| RawPacket772::create(Packet772Kind::LoginStart.id(), data).unwrap();
+
+        let valid = login_start("sixteen_letters_");
+        assert!(raw(&valid).deserialize().is_ok());
+        assert!(raw(&valid).deserialize_borrowed().is_ok());
+
+        let invalid = login_start("seventeen_letters");
+        for result in [raw(&invalid).deserialize().map(|_| ()), raw(&invalid).deserialize_borrowed().map(|_| ())] {
+            match result {
+                Err(PacketErr::DeserializeFailed(err)) => {
+                    assert!(matches!(err.root_cause(), DeserializeErr::TooLong { length: 17, max: 16 }))
+                }
+                other => panic!("expected a deserialize error, got {:?}", other),
+            }
+        }
+    }
+
+    #[cfg(feature = "error-context")]
+    #[test]
+    fn test_error_names_packet_and_field() {
+        use crate::protocol::{HasPacketId, RawPacket};
+
+        let mut out = BytesSerializer::default();
+        ConfigurationClientInformationSpec {
+            locale: "en_us".to_owned(),
+            view_distance: 8,
+            chat_mode: ChatMode::Enabled,
+            chat_colours: true,
+            display_skin_parts: 0x7F,
+            main_hand: MainHand::Right,
+            text_filtering: false,
+            allow_list_players: true,
+            particle_status: ParticleStatus::All,
+        }
+        .mc_serialize(&mut out)
+        .unwrap();
+        let mut bytes = out.into_bytes();
+        // chat_colours comes after the 6 byte locale, view distance and chat mode
+        bytes[8] = 5;
+
+        let raw = RawPacket772::create(Packet772Kind::ConfigurationClientInformation.id(), &bytes).unwrap();
+        let expected = "ConfigurationClientInformation.chat_colours at byte 8: could not decode boolean, unexpected byte: 5";
+        assert_eq!(raw.deserialize().unwrap_err().to_string(), alloc::format!("failed to deserialize packet: {}", expected));
+        assert_eq!(raw.deserialize_borrowed().unwrap_err().to_string(), alloc::format!("failed to deserialize packet: {}", expected));
+    }
+
+    // the derives have to write exactly what the protocol macros write
+    macro_rules! derive_matches_macro {
+        ($test: ident, $spec: ty, $derived: ty) => {
+            #[test]
+            fn $test() {
+                for _ in 0..50 {
+                    let mut out = BytesSerializer::default();
+                    <$spec>::test_gen_random().mc_serialize(&mut out).unwrap();
+                    let bytes = out.into_bytes();
+
+                    let Deserialized { value, data } = <$derived>::mc_deserialize(&bytes).unwrap();
+                    assert!(data.is_empty());
+                    let mut out = BytesSerializer::default();
+                    value.mc_serialize(&mut out).unwrap();
+                    assert_eq!(out.into_bytes(), bytes);
+                }
+            }
+        };
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    #[mc(tag = u8)]
+    enum DerivedHandshakeIntent {
+        Status = 0x01,
+        Login = 0x02,
+        #[mc(id = 0x03)]
+        Transfer,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedHandshake {
+        #[mc(varint)]
+        protocol_version: i32,
+        #[mc(max_length = 255)]
+        server_address: String,
+        server_port: u16,
+        intent: DerivedHandshakeIntent,
     }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedLoginEncryptionResponse(#[mc(count = VarInt)] Vec<u8>, #[mc(count = VarInt)] Vec<u8>);
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedLoginSuccessProperty {
+        name: String,
+        value: String,
+        #[mc(optional)]
+        signature: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedLoginSuccess {
+        uuid: UUID4,
+        username: String,
+        #[mc(count = VarInt)]
+        properties: Vec<DerivedLoginSuccessProperty>,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedClientInformation {
+        #[mc(max_length = 16)]
+        locale: String,
+        view_distance: u8,
+        chat_mode: ChatMode,
+        chat_colours: bool,
+        display_skin_parts: u8,
+        main_hand: MainHand,
+        text_filtering: bool,
+        allow_list_players: bool,
+        particle_status: ParticleStatus,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedPluginMessage {
+        id: String,
+        #[mc(max_length = 32767)]
+        data: RemainingBytes,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    enum DerivedPlayerActionStatus {
+        StartedDigging = 0x00,
+        CancelledDigging = 0x01,
+        FinishedDigging = 0x02,
+        DropItemStack = 0x03,
+        DropItem = 0x04,
+        ShootArrowOrFinishEating = 0x05,
+        SwapItemInHand = 0x06,
+    }
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    struct DerivedPlayerAction {
+        status: DerivedPlayerActionStatus,
+        location: IntPosition,
+        face: BlockFace,
+        #[mc(varint)]
+        sequence: i32,
+    }
+
+    derive_matches_macro!(test_derive_handshake, HandshakeSpec, DerivedHandshake);
+    derive_matches_macro!(test_derive_login_encryption_response, LoginEncryptionResponseSpec, DerivedLoginEncryptionResponse);
+    derive_matches_macro!(test_derive_login_success, LoginSuccessSpec, DerivedLoginSuccess);
+    derive_matches_macro!(test_derive_client_information, ConfigurationClientInformationSpec, DerivedClientInformation);
+    derive_matches_macro!(test_derive_plugin_message, ServerBoundPluginMessageSpec, DerivedPluginMessage);
+    derive_matches_macro!(test_derive_player_action, PlayPlayerActionSpec, DerivedPlayerAction);
+
+    #[derive(Debug, PartialEq, McSerialize, McDeserialize)]
+    #[mc(tag = u8)]
+    enum DerivedEvent {
+        #[mc(id = 0x00)]
+        Empty,
+        #[mc(id = 0x01)]
+        Counted(#[mc(optional, varint)] Option<i32>),
+        #[mc(id = 0x02)]
+        Named {
+            #[mc(optional, count = VarInt, max_length = 2)]
+            values: Option<Vec<u16>>,
+        },
+    }
+
+    #[test]
+    fn test_derive_enum_bodies() {
+        let cases = [
+            (DerivedEvent::Empty, vec![0x00]),
+            (DerivedEvent::Counted(None), vec![0x01, 0x00]),
+            (DerivedEvent::Counted(Some(300)), vec![0x01, 0x01, 0xAC, 0x02]),
+            (DerivedEvent::Named { values: Some(vec![1, 2]) }, vec![0x02, 0x01, 0x02, 0x00, 0x01, 0x00, 0x02]),
+        ];
+        for (event, bytes) in cases {
+            let mut out = BytesSerializer::default();
+            event.mc_serialize(&mut out).unwrap();
+            assert_eq!(out.into_bytes(), bytes);
+            assert_eq!(DerivedEvent::mc_deserialize(&bytes).unwrap().value, event);
+        }
+
+        let too_many = [0x02, 0x01, 0x03, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03];
+        let err = DerivedEvent::mc_deserialize(&too_many).err().unwrap();
+        assert!(matches!(err.root_cause(), DeserializeErr::TooLong { length: 3, max: 2 }));
+
+        match DerivedEvent::mc_deserialize(&[0x07]) {
+            Err(DeserializeErr::CannotUnderstandValue(msg)) => assert_eq!(msg, "invalid DerivedEvent 7"),
+            other => panic!("expected an invalid id, got {:?}", other.map(|d| d.value)),
+        }
+    }
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, Handshake, HandshakeSpec,
+        test_handshake_borrowed, bench_read_handshake_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, LoginStart, LoginStartSpec,
+        test_login_start_borrowed, bench_read_login_start_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, LoginEncryptionRequest, LoginEncryptionRequestSpec,
+        test_login_encryption_request_borrowed, bench_read_login_encryption_request_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, LoginEncryptionResponse, LoginEncryptionResponseSpec,
+        test_login_encryption_response_borrowed, bench_read_login_encryption_response_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, LoginSuccess, LoginSuccessSpec,
+        test_login_success_borrowed, bench_read_login_success_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, ConfigurationClientInformation, ConfigurationClientInformationSpec,
+        test_configuration_client_information_borrowed, bench_read_configuration_client_information_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, ServerBoundPluginMessage, ServerBoundPluginMessageSpec,
+        test_server_bound_plugin_message_borrowed, bench_read_server_bound_plugin_message_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, PlayChatCommand, PlayChatCommandSpec,
+        test_play_chat_command_borrowed, bench_read_play_chat_command_borrowed);
+
+    borrowed_packet_test_cases!(RawPacket772, Packet772, PlayPlayerAction, PlayPlayerActionSpec,
+        test_play_player_action_borrowed, bench_read_play_player_action_borrowed);
 }
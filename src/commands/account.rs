@@ -0,0 +1,101 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+use embassy_time::Instant;
+use log::{info, warn};
+
+use crate::{
+    packets::PlayerContext,
+    server::ServerState,
+    storage::accounts::{AccountError, LoginResult, SALT_LENGTH, hash_password},
+};
+
+const MIN_PASSWORD_LENGTH: usize = 5;
+
+/// What a player who hasn't logged in yet is told to do
+pub async fn prompt(context: &PlayerContext, server: &ServerState) -> Vec<String> {
+    let Some(username) = context.username() else {
+        return Vec::new();
+    };
+    if server.accounts.lock().await.is_registered(username) {
+        vec!["§eplease log in with §f/login <password>".to_owned()]
+    } else {
+        vec!["§eplease pick a password with §f/register <password>".to_owned()]
+    }
+}
+
+/// `/register <password>`, claims the player's name for offline mode
+pub async fn register<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Vec<String> {
+    if context.logged_in {
+        return vec!["§cyou're already logged in".to_owned()];
+    }
+    let Some(password) = args.next() else {
+        return vec!["§c/register <password>".to_owned()];
+    };
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return vec![format!("§cpasswords need at least {MIN_PASSWORD_LENGTH} characters")];
+    }
+    let Some(username) = context.username() else {
+        return Vec::new();
+    };
+
+    let salt: [u8; SALT_LENGTH] = server.encryption.random_data().await[..SALT_LENGTH]
+        .try_into()
+        .expect("not enough random data for a salt");
+    // Hashed before taking the accounts, it's slow enough to hold up everyone else's logins
+    let hash = hash_password(password, &salt);
+    match server.accounts.lock().await.register(username, salt, hash) {
+        Ok(()) => {
+            info!("{} registered", username);
+            context.logged_in = true;
+            vec!["§aregistered, you're logged in".to_owned()]
+        }
+        Err(AccountError::AlreadyRegistered) => vec!["§cthis name is taken, use /login <password>".to_owned()],
+        Err(AccountError::Record(err)) => {
+            warn!("failed to store account: {:?}", err);
+            vec!["§cfailed to save your account, try again later".to_owned()]
+        }
+    }
+}
+
+/// `/login <password>`
+pub async fn login<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Vec<String> {
+    if context.logged_in {
+        return vec!["§cyou're already logged in".to_owned()];
+    }
+    let Some(password) = args.next() else {
+        return vec!["§c/login <password>".to_owned()];
+    };
+    let Some(username) = context.username() else {
+        return Vec::new();
+    };
+
+    let salt = server.accounts.lock().await.login_salt(username, Instant::now().as_secs());
+    let result = match salt {
+        // Hashed without holding the accounts, like registering
+        Ok(salt) => {
+            let hash = hash_password(password, &salt);
+            server.accounts.lock().await.login(username, &hash, Instant::now().as_secs())
+        }
+        Err(result) => result,
+    };
+    match result {
+        LoginResult::Success => {
+            info!("{} logged in", username);
+            context.logged_in = true;
+            vec!["§alogged in".to_owned()]
+        }
+        LoginResult::WrongPassword => {
+            warn!("wrong password for {}", username);
+            vec!["§cwrong password".to_owned()]
+        }
+        LoginResult::NotRegistered => vec!["§cnot registered yet, use /register <password>".to_owned()],
+        LoginResult::Locked(seconds) => vec![format!("§ctoo many wrong passwords, try again in {seconds}s")],
+    }
+}
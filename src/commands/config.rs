@@ -0,0 +1,208 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+use core::mem;
+use log::{info, warn};
+
+use crate::{
+    server::ServerState,
+    storage::config::{ConfigError, ForwardingMode, MAX_FAVICON_LENGTH, ServerConfig},
+};
+
+const KEYS: [&str; 16] = [
+    "ssid",
+    "password",
+    "motd",
+    "max-players",
+    "port",
+    "favicon",
+    "discovery-motd",
+    "compression-threshold",
+    "admins",
+    "rcon-password",
+    "session-server",
+    "forwarding",
+    "forwarding-secret",
+    "transfer-secret",
+    "proxy-protocol",
+    "reset-world",
+];
+// Base64 of the largest favicon the config takes
+const MAX_FAVICON_UPLOAD: usize = MAX_FAVICON_LENGTH.div_ceil(3) * 4;
+
+/// Never shown, only whether there is one
+fn show_secret(secret: &str) -> String {
+    if secret.is_empty() {
+        "none".to_owned()
+    } else {
+        "set".to_owned()
+    }
+}
+
+fn show_switch(on: bool) -> String {
+    if on {
+        "on".to_owned()
+    } else {
+        "off".to_owned()
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool, &'static str> {
+    match value {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err("that has to be on or off"),
+    }
+}
+
+/// `none` clears a setting that's empty when off
+fn or_none(value: String) -> String {
+    if value == "none" {
+        String::new()
+    } else {
+        value
+    }
+}
+
+fn show(config: &ServerConfig, key: &str) -> String {
+    let value = match key {
+        "ssid" => config.ssid.clone(),
+        // Never shown, only whether there is one
+        "password" if config.password.is_empty() => "none, the network is open".to_owned(),
+        "password" => "set".to_owned(),
+        "motd" => config.motd.clone(),
+        "max-players" => format!("{}", config.max_players),
+        "port" => format!("{}", config.port),
+        "favicon" => match &config.favicon {
+            Some(favicon) => format!("{} bytes", favicon.len()),
+            None => "built in".to_owned(),
+        },
+        "discovery-motd" => config.discovery_motd.clone(),
+        "compression-threshold" => format!("{}", config.compression_threshold),
+        "admins" if config.admins.is_empty() => "none".to_owned(),
+        "admins" => config.admins.join(", "),
+        "rcon-password" => show_secret(&config.rcon_password),
+        "session-server" if config.session_server.is_empty() => "none, offline mode".to_owned(),
+        "session-server" => config.session_server.clone(),
+        "forwarding" => match config.forwarding {
+            None => "none".to_owned(),
+            Some(ForwardingMode::Velocity) => "velocity".to_owned(),
+            Some(ForwardingMode::BungeeCord) => "bungeecord".to_owned(),
+        },
+        "forwarding-secret" => show_secret(&config.forwarding_secret),
+        "transfer-secret" => show_secret(&config.transfer_secret),
+        "proxy-protocol" => show_switch(config.proxy_protocol),
+        "reset-world" => show_switch(config.reset_world),
+        _ => unreachable!("not a config key"),
+    };
+    format!("§7{key}: §f{value}")
+}
+
+/// Applies `value` to `key`, or says why it can't be
+fn set(config: &mut ServerConfig, key: &str, value: String) -> Result<(), &'static str> {
+    match key {
+        "ssid" => config.ssid = value,
+        // Wifi passwords are at least 8 characters, so this can't be one
+        "password" if value == "none" => config.password = String::new(),
+        "password" => config.password = value,
+        "motd" => config.motd = value,
+        "max-players" => config.max_players = value.parse().map_err(|_| "max players has to be a number")?,
+        "port" => config.port = value.parse().map_err(|_| "that isn't a port")?,
+        "favicon" if value == "default" => config.favicon = None,
+        "favicon" => config.favicon = Some(base64::decode(&value).map_err(|_| "favicon has to be base64")?),
+        "discovery-motd" => config.discovery_motd = value,
+        "compression-threshold" => {
+            config.compression_threshold = value.parse().map_err(|_| "compression threshold has to be a number")?
+        }
+        "admins" if value == "none" => config.admins = Vec::new(),
+        "admins" => {
+            config.admins = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|admin| !admin.is_empty())
+                .map(str::to_owned)
+                .collect()
+        }
+        "rcon-password" => config.rcon_password = or_none(value),
+        "session-server" => config.session_server = or_none(value),
+        "forwarding" => {
+            config.forwarding = match value.as_str() {
+                "none" => None,
+                "velocity" => Some(ForwardingMode::Velocity),
+                "bungeecord" => Some(ForwardingMode::BungeeCord),
+                _ => return Err("forwarding is none, velocity or bungeecord"),
+            }
+        }
+        "forwarding-secret" => config.forwarding_secret = or_none(value),
+        "transfer-secret" => config.transfer_secret = or_none(value),
+        "proxy-protocol" => config.proxy_protocol = parse_switch(&value)?,
+        "reset-world" => config.reset_world = parse_switch(&value)?,
+        _ => unreachable!("not a config key"),
+    }
+    Ok(())
+}
+
+/// `/config [key] [value]`, shows or changes the settings kept in nvs. Everything after the key
+/// is the value, so motds can have spaces. A favicon is sent with `/config favicon append <base64>`
+/// as many times as it takes, then `/config favicon done`
+pub async fn config<'a>(mut args: impl Iterator<Item = &'a str>, server: &ServerState) -> Vec<String> {
+    let mut store = server.config.lock().await;
+    let Some(key) = args.next() else {
+        return KEYS.iter().map(|key| show(store.config(), key)).collect();
+    };
+    if !KEYS.contains(&key) {
+        return vec![format!("§cunknown setting, pick one of {}", KEYS.join(", "))];
+    }
+    let mut value = args.collect::<Vec<_>>().join(" ");
+    if value.is_empty() {
+        return vec![show(store.config(), key)];
+    }
+
+    // A favicon is far longer than a chat message, so it's sent in pieces and set once it's all in
+    if key == "favicon" {
+        let mut upload = server.favicon_upload.lock().await;
+        if let Some(piece) = value.strip_prefix("append ") {
+            let piece = piece.trim();
+            if upload.len() + piece.len() > MAX_FAVICON_UPLOAD {
+                upload.clear();
+                return vec!["§cthat favicon is larger than 3 KiB, start again".to_owned()];
+            }
+            upload.push_str(piece);
+            return vec![format!(
+                "§7{} characters so far, finish with /config favicon done",
+                upload.len()
+            )];
+        }
+        if value == "done" {
+            if upload.is_empty() {
+                return vec!["§cnothing to set, send it with /config favicon append first".to_owned()];
+            }
+            value = mem::take(&mut *upload);
+        }
+    }
+
+    let mut config = store.config().clone();
+    if let Err(reason) = set(&mut config, key, value) {
+        return vec![format!("§c{reason}")];
+    }
+    match store.update(|current| *current = config) {
+        Err(ConfigError::Invalid(reason)) => vec![format!("§c{reason}")],
+        Err(ConfigError::Record(err)) => {
+            warn!("failed to store config: {:?}", err);
+            vec!["§cfailed to save the config, try again later".to_owned()]
+        }
+        Ok(()) => {
+            info!("changed {}", key);
+            let mut lines = vec![format!("§aset {key}")];
+            match key {
+                "reset-world" if store.config().reset_world => {
+                    lines.push("§cthe world is wiped on the next restart".to_owned())
+                }
+                "ssid" | "password" | "session-server" | "forwarding" | "forwarding-secret" | "transfer-secret" => {
+                    lines.push("§7takes effect after a restart".to_owned())
+                }
+                "rcon-password" => lines.push("§7turning rcon on or off takes a restart".to_owned()),
+                "port" => lines.push("§7the game moves after the current connection, query after a restart".to_owned()),
+                _ => (),
+            }
+            lines
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+
+use crate::packets::PlayerContext;
+
+/// `/gamemode <survival|creative>`, the client is told once the command's output is out
+pub fn gamemode<'a>(mut args: impl Iterator<Item = &'a str>, context: &mut PlayerContext) -> Vec<String> {
+    context.creative = match args.next() {
+        Some("survival" | "s" | "0") => false,
+        Some("creative" | "c" | "1") => true,
+        _ => return vec!["§c/gamemode <survival|creative>".to_owned()],
+    };
+    let name = if context.creative { "creative" } else { "survival" };
+    vec![format!("§7you're now in {name}")]
+}
@@ -0,0 +1,53 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+
+use crate::{
+    packets::PlayerContext,
+    storage::Partition,
+    world::{
+        World,
+        block::{BlockUpdate, PlayerIndex},
+    },
+};
+
+/// How many updates to show when a block is punched
+const INSPECT_HISTORY_LENGTH: usize = 5;
+
+pub fn toggle(context: &mut PlayerContext) -> Vec<String> {
+    context.inspecting = !context.inspecting;
+    if context.inspecting {
+        vec!["§7inspect mode §aon§7, punch a block to see its history".to_owned()]
+    } else {
+        vec!["§7inspect mode §coff".to_owned()]
+    }
+}
+
+pub fn player_label(world: &mut World<Partition>, player: PlayerIndex) -> String {
+    match player {
+        PlayerIndex::SERVER => "server".to_owned(),
+        PlayerIndex::UNKNOWN => "unknown".to_owned(),
+        PlayerIndex::ROLLBACK => "rollback".to_owned(),
+        index => world.player_name(index).unwrap_or_else(|| format!("#{}", index.0)),
+    }
+}
+
+/// Chat lines describing the newest updates to a block, read from its chunk's chain
+pub fn describe_history(world: &mut World<Partition>, x: i32, y: i32, z: i32) -> (Vec<String>, Option<BlockUpdate>) {
+    let history = world.block_history(x, y, z, INSPECT_HISTORY_LENGTH);
+    if history.is_empty() {
+        return (vec![format!("§7no recorded changes at {x} {y} {z}")], None);
+    }
+
+    let now = world.now();
+    let mut lines = vec![format!("§7-- history of {x} {y} {z} --")];
+    for update in &history {
+        let ago = now.saturating_sub(update.timestamp);
+        lines.push(format!(
+            "§e{} §7set §f{} §7{}s ago",
+            player_label(world, update.player),
+            update.block.name(),
+            ago
+        ));
+    }
+
+    (lines, history.first().copied())
+}
@@ -0,0 +1,16 @@
+use alloc::{borrow::ToOwned as _, string::String, vec, vec::Vec};
+use log::{info, warn};
+
+use crate::server::ServerState;
+
+/// `/rotatekey`, generating takes a few seconds and nothing else runs meanwhile
+pub async fn rotate(server: &ServerState) -> Vec<String> {
+    info!("rotating server key");
+    match server.encryption.rotate_key().await {
+        Ok(()) => vec!["§aserver key rotated".to_owned()],
+        Err(err) => {
+            warn!("failed to store rotated key: {:?}", err);
+            vec!["§cfailed to store the new key, keeping the old one".to_owned()]
+        }
+    }
+}
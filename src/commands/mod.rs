@@ -0,0 +1,75 @@
+use alloc::{borrow::ToOwned as _, string::String, vec, vec::Vec};
+use log::info;
+
+use crate::{packets::PlayerContext, server::ServerState};
+
+pub mod account;
+pub mod config;
+pub mod gamemode;
+pub mod inspect;
+pub mod keys;
+pub mod replay;
+pub mod rollback;
+pub mod transfer;
+
+/// Who ran a command
+pub enum CommandSource<'a> {
+    Player(&'a mut PlayerContext),
+    /// Remote console, always an admin but has no player to act on
+    Console,
+}
+
+/// Runs a command (without the leading slash), returning the lines to show whoever ran it
+pub async fn dispatch(command: &str, source: CommandSource<'_>, server: &ServerState) -> Vec<String> {
+    let mut args = command.split_whitespace();
+    let Some(name) = args.next() else {
+        return Vec::new();
+    };
+    // Never log passwords
+    match (name, command.split_whitespace().nth(1)) {
+        ("login" | "l" | "register" | "reg", _) => info!("running command: {}", name),
+        ("config", Some(key @ ("password" | "rcon-password" | "forwarding-secret" | "transfer-secret"))) => {
+            info!("running command: config {}", key)
+        }
+        _ => info!("running command: {}", command),
+    }
+
+    // Until they log in, a player could be anyone using their name
+    let admin = match &source {
+        CommandSource::Player(context) => {
+            let config = server.config.lock().await;
+            context.logged_in && context.username().is_some_and(|username| config.config().is_admin(username))
+        }
+        CommandSource::Console => true,
+    };
+
+    match (name, source) {
+        ("login" | "l", CommandSource::Player(context)) => account::login(args, context, server).await,
+        ("register" | "reg", CommandSource::Player(context)) => account::register(args, context, server).await,
+        (_, CommandSource::Player(context)) if !context.logged_in => account::prompt(context, server).await,
+        ("inspect" | "i", CommandSource::Player(context)) => inspect::toggle(context),
+        ("gamemode" | "gm", CommandSource::Player(context)) if admin => gamemode::gamemode(args, context),
+        ("rollback" | "rb", _) if admin => rollback::rollback(args, server).await,
+        ("restore", _) if admin => rollback::restore(args, server).await,
+        ("replay", CommandSource::Player(context)) => replay::replay(args, context),
+        ("rotatekey", _) if admin => keys::rotate(server).await,
+        ("config", _) if admin => config::config(args, server).await,
+        ("transfer", CommandSource::Player(context)) => transfer::transfer(args, context),
+        ("inspect" | "i" | "gamemode" | "gm" | "replay" | "transfer" | "login" | "l" | "register" | "reg", CommandSource::Console) => {
+            vec!["§conly players can do that".to_owned()]
+        }
+        ("help", _) => vec![
+            "§7/login §f- log in with your password".to_owned(),
+            "§7/register §f- pick a password for your name".to_owned(),
+            "§7/inspect §f- punch blocks to see who changed them".to_owned(),
+            "§7/gamemode §f- switch between survival and creative (admin)".to_owned(),
+            "§7/rollback §f- undo changes in an area (admin)".to_owned(),
+            "§7/restore §f- undo a recent rollback (admin)".to_owned(),
+            "§7/replay §f- watch the world get built".to_owned(),
+            "§7/rotatekey §f- generate a new server key (admin)".to_owned(),
+            "§7/config §f- show or change the server's settings (admin)".to_owned(),
+            "§7/transfer §f- go to another server".to_owned(),
+        ],
+        _ => vec!["§cunknown command, try /help".to_owned()],
+    }
+}
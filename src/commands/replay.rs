@@ -0,0 +1,31 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+
+use crate::{
+    packets::PlayerContext,
+    world::replay::{DEFAULT_REPLAY_SPEED, Replay},
+};
+
+const REPLAY_USAGE: &str = "§c/replay [updates per second|stop]";
+
+/// Starts a time-lapse of the world being built, or skips to the end of the running one
+pub fn replay<'a>(mut args: impl Iterator<Item = &'a str>, context: &mut PlayerContext) -> Vec<String> {
+    let per_second = match args.next() {
+        Some("stop") => {
+            return match &mut context.replay {
+                Some(replay) => {
+                    replay.fast_forward();
+                    vec!["§7skipping to the end of the replay".to_owned()]
+                }
+                None => vec!["§cno replay running".to_owned()],
+            };
+        }
+        Some(speed) => match speed.parse::<u32>() {
+            Ok(speed) if speed > 0 => speed,
+            _ => return vec![REPLAY_USAGE.to_owned()],
+        },
+        None => DEFAULT_REPLAY_SPEED,
+    };
+
+    context.replay = Some(Replay::new(per_second));
+    vec![format!("§7replaying the world at §f{per_second}§7 updates a second")]
+}
@@ -0,0 +1,69 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+
+use crate::{
+    server::ServerState,
+    world::rollback::{Region, RollbackFilter},
+};
+
+const ROLLBACK_USAGE: &str = "§c/rollback <x1> <y1> <z1> <x2> <y2> <z2> [player|*] [seconds] [until seconds]";
+const RESTORE_USAGE: &str = "§c/restore <x1> <y1> <z1> <x2> <y2> <z2> <seconds>";
+
+fn parse_region<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<Region> {
+    let mut coordinates = [0i32; 6];
+    for coordinate in &mut coordinates {
+        *coordinate = args.next()?.parse().ok()?;
+    }
+    let [x1, y1, z1, x2, y2, z2] = coordinates;
+    Some(Region::new((x1, y1, z1), (x2, y2, z2)))
+}
+
+/// Undoes updates in a box, optionally only by one player or from a window of the last few seconds
+pub async fn rollback<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    server: &ServerState,
+) -> Vec<String> {
+    let Some(region) = parse_region(&mut args) else {
+        return vec![ROLLBACK_USAGE.to_owned()];
+    };
+
+    let mut filter = RollbackFilter::default();
+    match args.next() {
+        None | Some("*") => (),
+        Some(name) => match server.world.lock().await.find_player(name) {
+            Some(player) => filter.player = Some(player),
+            None => return vec![format!("§cunknown player {name}")],
+        },
+    }
+    // Both ends of the window are in seconds ago
+    let now = server.world.lock().await.now();
+    for bound in [&mut filter.since, &mut filter.until] {
+        if let Some(seconds) = args.next() {
+            let Ok(seconds) = seconds.parse::<u32>() else {
+                return vec![ROLLBACK_USAGE.to_owned()];
+            };
+            *bound = Some(now.saturating_sub(seconds));
+        }
+    }
+
+    let changes = server.world.lock().await.rollback(&region, &filter);
+    server.block_changes.publish(&changes);
+    vec![format!("§7rolled back §f{}§7 blocks", changes.len())]
+}
+
+/// Undoes rollbacks made in the last few seconds, leaving blocks changed since alone
+pub async fn restore<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    server: &ServerState,
+) -> Vec<String> {
+    let Some(region) = parse_region(&mut args) else {
+        return vec![RESTORE_USAGE.to_owned()];
+    };
+    let Some(Ok(seconds)) = args.next().map(str::parse::<u32>) else {
+        return vec![RESTORE_USAGE.to_owned()];
+    };
+
+    let since = server.world.lock().await.now().saturating_sub(seconds);
+    let changes = server.world.lock().await.restore(&region, Some(since), None);
+    server.block_changes.publish(&changes);
+    vec![format!("§7restored §f{}§7 blocks", changes.len())]
+}
@@ -0,0 +1,19 @@
+use alloc::{borrow::ToOwned as _, format, string::String, vec, vec::Vec};
+
+use crate::{packets::PlayerContext, storage::config::DEFAULT_PORT};
+
+/// `/transfer <host> [port]`, sends the player to another server. If it sends them back, their
+/// login comes with them
+pub fn transfer<'a>(mut args: impl Iterator<Item = &'a str>, context: &mut PlayerContext) -> Vec<String> {
+    let Some(host) = args.next() else {
+        return vec!["§c/transfer <host> [port]".to_owned()];
+    };
+    let port = match args.next().map(str::parse) {
+        None => DEFAULT_PORT,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => return vec!["§cthat isn't a port".to_owned()],
+    };
+
+    context.transfer = Some((host.into(), port));
+    vec![format!("§7sending you to {host}:{port}")]
+}
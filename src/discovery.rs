@@ -1,13 +1,21 @@
 use core::{net::Ipv4Addr, ptr::addr_of_mut};
 
+use alloc::{format, vec::Vec};
 use embassy_net::{
     IpAddress,
     udp::{PacketMetadata, UdpSocket},
 };
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::rng::Rng;
 use esp_hal::spi::master::Address;
 use esp_println::println;
-use log::info;
+use log::{info, warn};
+use mcproto_rs::{
+    query::{ChallengeTokens, QueryRequest, QueryStatus, encode_handshake},
+    v1_21::ProtocolVersion,
+};
+
+use crate::{packets::server_status, server::ServerState, storage::config::ServerConfig};
 
 const RX_BUFFER_SIZE: usize = 4096;
 const TX_BUFFER_SIZE: usize = 512;
@@ -16,8 +24,16 @@ static mut RX_BUFFER: [u8; RX_BUFFER_SIZE] = [0; RX_BUFFER_SIZE];
 static mut TX_META_BUFFER: [PacketMetadata; 2] = [PacketMetadata::EMPTY; _];
 static mut TX_BUFFER: [u8; TX_BUFFER_SIZE] = [0; TX_BUFFER_SIZE];
 
+// How long a challenge token is handed out for, it's accepted for twice as long
+const TOKEN_ROTATION: Duration = Duration::from_secs(30);
+const QUERY_BUFFER_SIZE: usize = 1024;
+static mut QUERY_RX_META_BUFFER: [PacketMetadata; 4] = [PacketMetadata::EMPTY; _];
+static mut QUERY_RX_BUFFER: [u8; QUERY_BUFFER_SIZE] = [0; QUERY_BUFFER_SIZE];
+static mut QUERY_TX_META_BUFFER: [PacketMetadata; 4] = [PacketMetadata::EMPTY; _];
+static mut QUERY_TX_BUFFER: [u8; QUERY_BUFFER_SIZE] = [0; QUERY_BUFFER_SIZE];
+
 #[embassy_executor::task]
-pub async fn start_discovery_server(stack: embassy_net::Stack<'static>) {
+pub async fn start_discovery_server(stack: embassy_net::Stack<'static>, state: &'static ServerState) {
     let mut socket = UdpSocket::new(
         stack,
         unsafe { &mut *addr_of_mut!(RX_META_BUFFER) },
@@ -37,12 +53,105 @@ pub async fn start_discovery_server(stack: embassy_net::Stack<'static>) {
         .bind((stack.config_v4().unwrap().address.address(), 4445))
         .expect("failed to bind to minecraft multicast");
 
-    let message = "[MOTD]block-chain - a block game on your keychain![/MOTD][AD]25565[/AD]".as_bytes();
-
     socket.wait_send_ready().await;
 
     loop {
-        let _ = socket.send_to(message, endpoint).await;
+        // Built for every broadcast so config changes show up without a restart
+        let message = {
+            let config = state.config.lock().await;
+            let config = config.config();
+            format!("[MOTD]{}[/MOTD][AD]{}[/AD]", config.discovery_motd, config.port)
+        };
+        let _ = socket.send_to(message.as_bytes(), endpoint).await;
         Timer::after(Duration::from_millis(5_000)).await;
     }
 }
+
+/// Answers the UDP Query protocol used by monitoring bots and server list sites. Like vanilla's
+/// default query.port it uses the game's port number, it's UDP so the two don't clash
+#[embassy_executor::task]
+pub async fn start_query_server(stack: embassy_net::Stack<'static>, state: &'static ServerState, mut rng: Rng) {
+    let mut socket = UdpSocket::new(
+        stack,
+        unsafe { &mut *addr_of_mut!(QUERY_RX_META_BUFFER) },
+        unsafe { &mut *addr_of_mut!(QUERY_RX_BUFFER) },
+        unsafe { &mut *addr_of_mut!(QUERY_TX_META_BUFFER) },
+        unsafe { &mut *addr_of_mut!(QUERY_TX_BUFFER) },
+    );
+
+    let port = state.config.lock().await.config().port;
+    socket.bind(port).expect("failed to bind query socket");
+    info!("answering queries on udp port {}", port);
+
+    let mut tokens = ChallengeTokens::new(rng.random());
+    let mut rotated_at = Instant::now();
+    let mut request = [0u8; 16];
+
+    loop {
+        let Ok((length, meta)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        let Some(query) = QueryRequest::parse(&request[..length]) else {
+            continue;
+        };
+
+        if rotated_at.elapsed() >= TOKEN_ROTATION {
+            tokens.rotate(rng.random());
+            rotated_at = Instant::now();
+        }
+
+        // Only IPv4 is enabled on the stack
+        let IpAddress::Ipv4(address) = meta.endpoint.addr;
+        let client = address.to_bits();
+        let response = match query {
+            QueryRequest::Handshake { session } => encode_handshake(session, tokens.issue(client)),
+            QueryRequest::BasicStat { token, .. } | QueryRequest::FullStat { token, .. }
+                if !tokens.is_valid(client, token) =>
+            {
+                continue;
+            }
+            QueryRequest::BasicStat { session, .. } => {
+                query_status(stack, state.config.lock().await.config(), |status| status.encode_basic(session))
+            }
+            QueryRequest::FullStat { session, .. } => {
+                query_status(stack, state.config.lock().await.config(), |status| status.encode_full(session))
+            }
+        };
+
+        if let Err(err) = socket.send_to(&response, meta.endpoint).await {
+            warn!("failed to answer query: {:?}", err);
+        }
+    }
+}
+
+/// Fills in a query status from the same data as the server list ping
+fn query_status(
+    stack: embassy_net::Stack<'_>,
+    config: &ServerConfig,
+    encode: impl FnOnce(&QueryStatus) -> Vec<u8>,
+) -> Vec<u8> {
+    let status = server_status(ProtocolVersion::LATEST, config);
+    let motd = status.description.to_traditional().unwrap_or_default();
+    let host_ip = stack
+        .config_v4()
+        .map(|network| format!("{}", network.address.address()))
+        .unwrap_or_default();
+    let players = status
+        .players
+        .sample
+        .iter()
+        .map(|player| player.name.as_str())
+        .collect::<Vec<_>>();
+
+    encode(&QueryStatus {
+        motd: &motd,
+        game_type: "SMP",
+        map: "world",
+        version: status.version.as_ref().map_or("", |version| version.name.as_str()),
+        online: status.players.online,
+        max: status.players.max,
+        host_port: config.port,
+        host_ip: &host_ip,
+        players: &players,
+    })
+}
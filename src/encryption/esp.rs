@@ -0,0 +1,78 @@
+use core::cell::RefCell;
+
+use alloc::vec::Vec;
+use blockchain_core::encryption::accelerator::{
+    self, KEY_BYTES as HARDWARE_KEY_BYTES, KEY_WORDS as HARDWARE_KEY_WORDS, m_prime, r_squared,
+    to_operand,
+};
+use embassy_sync::{blocking_mutex, blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use esp_hal::{
+    Async,
+    rng::Rng,
+    rsa::{Rsa, RsaModularExponentiation, operand_sizes::Op1024},
+};
+use rsa::{
+    Pkcs1v15Encrypt, RsaPrivateKey,
+    traits::{PrivateKeyParts as _, PublicKeyParts as _},
+};
+
+use crate::encryption::{BackendRng, CryptoBackend, stream::Cfb8Stream};
+
+/// Uses the RSA accelerator for the modular exponentiation in 1024 bit decryption and the
+/// hardware RNG. Other key sizes fall back to software
+pub struct EspBackend<'d> {
+    rsa: Mutex<NoopRawMutex, Rsa<'d, Async>>,
+    rng: blocking_mutex::Mutex<NoopRawMutex, RefCell<Rng>>,
+}
+
+impl<'d> EspBackend<'d> {
+    pub fn new(rsa: Rsa<'d, Async>, rng: Rng) -> Self {
+        Self {
+            rsa: Mutex::new(rsa),
+            rng: blocking_mutex::Mutex::new(RefCell::new(rng)),
+        }
+    }
+}
+
+impl<'d> CryptoBackend for EspBackend<'d> {
+    type Stream = Cfb8Stream;
+
+    async fn rsa_decrypt(&self, key: &RsaPrivateKey, ciphertext: &[u8]) -> Result<Vec<u8>, rsa::Error> {
+        if key.size() != HARDWARE_KEY_BYTES {
+            return key.decrypt(Pkcs1v15Encrypt, ciphertext);
+        }
+        if ciphertext.len() != HARDWARE_KEY_BYTES {
+            return Err(rsa::Error::Decryption);
+        }
+
+        let modulus = to_operand(&key.n().to_be_bytes());
+        let exponent = to_operand(&key.d().to_be_bytes());
+        // The accelerator exponentiates with the raw private exponent, so it only ever gets a
+        // blinded base
+        let blinded = accelerator::blind(&mut BackendRng(self), key, ciphertext)?;
+
+        let mut result = [0u32; HARDWARE_KEY_WORDS];
+        {
+            let mut rsa = self.rsa.lock().await;
+            let mut exponentiation = RsaModularExponentiation::<Op1024, Async>::new(
+                &mut rsa,
+                &exponent,
+                &modulus,
+                m_prime(modulus[0]),
+            );
+            exponentiation
+                .exponentiation(&blinded.base, &r_squared(&modulus), &mut result)
+                .await;
+        }
+
+        accelerator::unpad(&blinded.unblind(key, &result))
+    }
+
+    fn random_bytes(&self, out: &mut [u8]) {
+        self.rng.lock(|rng| rng.borrow_mut().read(out));
+    }
+
+    fn cfb8_stream(&self, shared_secret: &[u8; 16]) -> Self::Stream {
+        Cfb8Stream::new(shared_secret)
+    }
+}
@@ -0,0 +1,86 @@
+use alloc::vec::Vec;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey, pkcs8::der::Encode as _};
+
+use crate::storage::{
+    Nvs, PartitionRecordError,
+    key::{generate_server_key, load_server_key, store_server_key},
+    record::RecordStore,
+};
+
+pub use blockchain_core::encryption::{BackendRng, CipherStream, CryptoBackend, stream};
+
+pub mod esp;
+
+/// The backend the server is built with
+pub type PlatformBackend = esp::EspBackend<'static>;
+pub type PlatformStream = <PlatformBackend as CryptoBackend>::Stream;
+
+pub struct ServerEncryption<B> {
+    backend: B,
+    keys: Mutex<NoopRawMutex, ServerKeys>,
+    key_store: Mutex<NoopRawMutex, RecordStore<Nvs>>,
+}
+
+struct ServerKeys {
+    private: RsaPrivateKey,
+    public: RsaPublicKey,
+}
+
+impl ServerKeys {
+    fn new(private: RsaPrivateKey) -> Self {
+        let public = private.to_public_key();
+        Self { private, public }
+    }
+}
+
+impl<B: CryptoBackend> ServerEncryption<B> {
+    /// Loads the server key from `key_store`, only generating one if none is stored (or it's
+    /// unreadable)
+    pub fn new(backend: B, mut key_store: RecordStore<Nvs>) -> Self {
+        let private = load_server_key(&mut key_store, &mut BackendRng(&backend));
+        Self {
+            backend,
+            keys: Mutex::new(ServerKeys::new(private)),
+            key_store: Mutex::new(key_store),
+        }
+    }
+
+    /// Replaces the server key with a freshly generated one. Players mid-login will fail to
+    /// decrypt and have to reconnect, everyone already in keeps their session key
+    pub async fn rotate_key(&self) -> Result<(), PartitionRecordError> {
+        let private = generate_server_key(&mut BackendRng(&self.backend));
+        store_server_key(&mut *self.key_store.lock().await, &private)?;
+        *self.keys.lock().await = ServerKeys::new(private);
+        Ok(())
+    }
+
+    /// The public key as a DER encoded SubjectPublicKeyInfo, which is what the client expects
+    pub async fn public_key_der(&self) -> Result<Vec<u8>, rsa::pkcs8::spki::Error> {
+        let keys = self.keys.lock().await;
+        let spki = rsa::pkcs8::SubjectPublicKeyInfo::from_key(&keys.public)?;
+        Ok(spki.to_der().expect("failed to serialize to der"))
+    }
+
+    pub async fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, rsa::Error> {
+        let public = self.keys.lock().await.public.clone();
+        let enc_data = public.encrypt(&mut BackendRng(&self.backend), Pkcs1v15Encrypt, data)?;
+
+        Ok(enc_data)
+    }
+
+    pub async fn decrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, rsa::Error > {
+        let keys = self.keys.lock().await;
+        self.backend.rsa_decrypt(&keys.private, data).await
+    }
+
+    pub async fn random_data(&self) -> Vec<u8> {
+        let mut random_buffer = [0u8; 64];
+        self.backend.random_bytes(&mut random_buffer);
+        random_buffer.to_vec()
+    }
+
+    pub fn cfb8_stream(&self, shared_secret: &[u8; 16]) -> B::Stream {
+        self.backend.cfb8_stream(shared_secret)
+    }
+}
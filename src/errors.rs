@@ -1,4 +1,10 @@
-use mcproto_rs::{protocol::PacketErr, DeserializeErr, SerializeErr};
+use alloc::{format, string::String};
+use mcproto_rs::{
+    auth::SessionErr, forwarding::ForwardingErr, frame::FrameErr, protocol::PacketErr, proxy_protocol::ProxyErr,
+    rcon::RconErr, v1_21::ProtocolVersion, DeserializeErr, SerializeErr,
+};
+
+use crate::session::HttpError;
 
 #[derive(Debug)]
 pub enum MinecraftError {
@@ -8,8 +14,52 @@ pub enum MinecraftError {
     EncryptionError(rsa::Error),
     CertificateParsingError(rsa::pkcs8::spki::Error),
     PacketErr(PacketErr),
-    InvalidPacketHeader,
+    FrameError(FrameErr),
+    RconError(RconErr),
+    SessionError(SessionErr<HttpError>),
+    /// Forwarding is on and the player's info is missing, forged or unreadable
+    ForwardingError(ForwardingErr),
+    /// The PROXY header is missing or unreadable, when one is expected
+    ProxyError(ProxyErr),
     Unauthorized,
+    /// A login with a name no account could have
+    InvalidUsername,
+    /// A login from a protocol version outside of `ProtocolVersion::ALL`
+    UnsupportedVersion(i32),
+}
+
+impl MinecraftError {
+    /// What the player is told when this ends their connection, nothing if the socket is gone
+    pub fn disconnect_reason(&self) -> Option<String> {
+        let latest = ProtocolVersion::LATEST.name();
+        Some(match self {
+            MinecraftError::ConnectionError(_) => return None,
+            MinecraftError::UnsupportedVersion(protocol) if *protocol < ProtocolVersion::ALL[0].protocol() => {
+                format!("Outdated client! Please use {}", latest)
+            }
+            MinecraftError::UnsupportedVersion(_) => format!("Outdated server! I'm still on {}", latest),
+            MinecraftError::SerializeError(err) => format!("Server failed to write a packet: {}", err),
+            MinecraftError::DeserializeError(err) => format!("Could not read your packet: {}", err),
+            MinecraftError::PacketErr(err) => format!("Could not read your packet: {}", err),
+            MinecraftError::FrameError(err) => format!("Could not read your packet: {}", err),
+            MinecraftError::RconError(err) => format!("Could not read your packet: {}", err),
+            MinecraftError::EncryptionError(_) | MinecraftError::CertificateParsingError(_) => {
+                "Failed to set up encryption".into()
+            }
+            MinecraftError::SessionError(_) => {
+                "Authentication servers are down. Please try again later, sorry!".into()
+            }
+            // Same as spigot's, it's usually a direct connection or a proxy left without forwarding
+            MinecraftError::ForwardingError(ForwardingErr::NotForwarded) => {
+                "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!".into()
+            }
+            MinecraftError::ForwardingError(ForwardingErr::BadSignature) => "Unable to verify player details".into(),
+            MinecraftError::ForwardingError(err) => format!("Could not read your forwarded info: {}", err),
+            MinecraftError::ProxyError(err) => format!("Could not read your connection: {}", err),
+            MinecraftError::Unauthorized => "Failed to verify your login".into(),
+            MinecraftError::InvalidUsername => "Invalid characters in username".into(),
+        })
+    }
 }
 
 impl From<embassy_net::tcp::Error> for MinecraftError {
@@ -42,8 +92,37 @@ impl From<DeserializeErr> for MinecraftError {
     }
 }
 
+impl From<FrameErr> for MinecraftError {
+    fn from(value: FrameErr) -> Self {
+        MinecraftError::FrameError(value)
+    }
+}
+
 impl From<PacketErr> for MinecraftError {
     fn from(value: PacketErr) -> Self {
         MinecraftError::PacketErr(value)
     }
-}
\ No newline at end of file
+}
+impl From<RconErr> for MinecraftError {
+    fn from(value: RconErr) -> Self {
+        MinecraftError::RconError(value)
+    }
+}
+
+impl From<SessionErr<HttpError>> for MinecraftError {
+    fn from(value: SessionErr<HttpError>) -> Self {
+        MinecraftError::SessionError(value)
+    }
+}
+
+impl From<ForwardingErr> for MinecraftError {
+    fn from(value: ForwardingErr) -> Self {
+        MinecraftError::ForwardingError(value)
+    }
+}
+
+impl From<ProxyErr> for MinecraftError {
+    fn from(value: ProxyErr) -> Self {
+        MinecraftError::ProxyError(value)
+    }
+}
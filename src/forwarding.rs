@@ -0,0 +1,21 @@
+use alloc::vec::Vec;
+
+use crate::storage::config::{ForwardingMode, ServerConfig};
+
+/// How a proxy in front of us tells us who its players really are
+pub enum Forwarding {
+    /// Modern forwarding, signed with the shared secret
+    Velocity(Vec<u8>),
+    /// Legacy forwarding through the handshake, only safe if nothing but the proxy can reach us
+    BungeeCord,
+}
+
+impl Forwarding {
+    /// From the config, which won't hold velocity without a secret
+    pub fn from_config(config: &ServerConfig) -> Option<Self> {
+        match config.forwarding? {
+            ForwardingMode::Velocity => Some(Forwarding::Velocity(config.forwarding_secret.as_bytes().to_vec())),
+            ForwardingMode::BungeeCord => Some(Forwarding::BungeeCord),
+        }
+    }
+}
@@ -5,19 +5,33 @@
 #![feature(generic_const_exprs)]
 #![feature(int_roundings)]
 
+mod commands;
 mod discovery;
 mod encryption;
 mod errors;
+mod forwarding;
 mod packets;
+mod rcon;
 mod server;
+mod session;
+mod storage;
+mod transfer;
 mod utils;
 mod wifi;
-mod world;
 
 extern crate alloc;
 
+use core::cell::RefCell;
+
+use alloc::string::String;
+
+use blockchain_core::world;
 use embassy_executor::Spawner;
 use embassy_net::StackResources;
+use embassy_sync::{
+    blocking_mutex::{self, raw::NoopRawMutex},
+    mutex::Mutex,
+};
 use embassy_time::{Duration, Timer};
 use esp_backtrace as _;
 use esp_hal::{
@@ -31,17 +45,74 @@ use esp_wifi::EspWifiController;
 use log::{info, warn};
 
 use crate::{
-    discovery::start_discovery_server,
-    encryption::ServerEncryption,
-    server::start_tcp_server,
-    wifi::{maintain_wifi_connection, net_task},
-    world::{
-        block::{BlockType, BlockUpdate, BlockUpdatePointer, PackedChunkPosition}, World
+    discovery::{start_discovery_server, start_query_server},
+    encryption::{PlatformBackend, ServerEncryption, esp::EspBackend},
+    forwarding::Forwarding,
+    rcon::{DEFAULT_RCON_PORT, start_rcon_server},
+    server::{ServerState, start_tcp_server},
+    session::HttpTransport,
+    transfer::TransferTickets,
+    storage::{
+        ACCOUNTS_RECORD, CONFIG_RECORD, Nvs, Partition, Partitions, RECORD_SIZE, SERVER_KEY_RECORD,
+        SharedFlash,
+        accounts::AccountStore,
+        config::{ConfigStore, ForwardingMode, ServerConfig},
+        record::RecordStore,
     },
+    wifi::{maintain_wifi_connection, net_task},
+    world::{World, events::BlockChanges},
 };
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// Only used the first time a device boots, or for settings its stored config is too old to
+/// have. After that the stored config wins
+const BUILD_SSID: Option<&str> = option_env!("SSID");
+const BUILD_PASSWORD: Option<&str> = option_env!("PASSWORD");
+/// Comma separated usernames allowed to run admin commands
+const BUILD_ADMINS: Option<&str> = option_env!("ADMINS");
+const BUILD_RCON_PASSWORD: Option<&str> = option_env!("RCON_PASSWORD");
+const BUILD_SESSION_SERVER: Option<&str> = option_env!("SESSION_SERVER");
+/// `velocity` or `bungeecord`
+const BUILD_FORWARDING: Option<&str> = option_env!("FORWARDING");
+const BUILD_FORWARDING_SECRET: Option<&str> = option_env!("FORWARDING_SECRET");
+const BUILD_TRANSFER_SECRET: Option<&str> = option_env!("TRANSFER_SECRET");
+const BUILD_PROXY_PROTOCOL: Option<&str> = option_env!("PROXY_PROTOCOL");
+const BUILD_RESET_WORLD: Option<&str> = option_env!("RESET_WORLD");
+
+/// The config a device starts with, from the build's environment. Panics on settings the
+/// config wouldn't take, they'd never work
+fn build_defaults() -> ServerConfig {
+    let defaults = ServerConfig {
+        ssid: BUILD_SSID.unwrap_or_default().into(),
+        password: BUILD_PASSWORD.unwrap_or_default().into(),
+        admins: BUILD_ADMINS
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|admin| !admin.is_empty())
+            .map(Into::into)
+            .collect(),
+        rcon_password: BUILD_RCON_PASSWORD.unwrap_or_default().into(),
+        session_server: BUILD_SESSION_SERVER.unwrap_or_default().into(),
+        forwarding: match BUILD_FORWARDING {
+            None => None,
+            Some("velocity") => Some(ForwardingMode::Velocity),
+            Some("bungeecord") => Some(ForwardingMode::BungeeCord),
+            Some(other) => panic!("unknown FORWARDING mode {}, expected velocity or bungeecord", other),
+        },
+        forwarding_secret: BUILD_FORWARDING_SECRET.unwrap_or_default().into(),
+        transfer_secret: BUILD_TRANSFER_SECRET.unwrap_or_default().into(),
+        proxy_protocol: BUILD_PROXY_PROTOCOL.is_some(),
+        reset_world: BUILD_RESET_WORLD.is_some(),
+        ..Default::default()
+    };
+    if let Err(reason) = defaults.validate() {
+        panic!("bad build settings: {}", reason);
+    }
+    defaults
+}
+
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -71,7 +142,29 @@ async fn main(spawner: Spawner) {
     let mut rng = Rng::new(peripherals.RNG);
     let rsa = Rsa::new(peripherals.RSA).into_async();
 
-    let encryption = mk_static!(ServerEncryption<'static>, ServerEncryption::new(rsa, rng));
+    let mut partitions = Partitions::read().open_all();
+    let nvs = SharedFlash::new(&*mk_static!(
+        blocking_mutex::Mutex<NoopRawMutex, RefCell<Partition>>,
+        blocking_mutex::Mutex::new(RefCell::new(partitions.nvs))
+    ));
+    let mut server_config = ConfigStore::load(RecordStore::new(nvs.clone(), CONFIG_RECORD, RECORD_SIZE), build_defaults());
+    // Everything but the settings `/config` says apply straight away is read once, here. The
+    // favicon is always read from the store, no need to keep a second copy around
+    let boot_config = ServerConfig {
+        favicon: None,
+        ..server_config.config().clone()
+    };
+    let (ssid, password) = (boot_config.ssid.clone(), boot_config.password.clone());
+    if ssid.is_empty() {
+        warn!("no wifi network configured, build with SSID and PASSWORD set");
+    }
+
+    let key_store = RecordStore::new(nvs.clone(), SERVER_KEY_RECORD, RECORD_SIZE);
+
+    let encryption = mk_static!(
+        ServerEncryption<PlatformBackend>,
+        ServerEncryption::new(EspBackend::new(rsa, rng), key_store)
+    );
 
     let esp_radio_ctrl = &*mk_static!(
         EspWifiController<'static>,
@@ -83,20 +176,24 @@ async fn main(spawner: Spawner) {
 
     let wifi_interface = interfaces.sta;
 
-    let mut world = World::new();
-    let pointer = world.find_free_space();
-    world.write_block_update(
-        pointer,
-        BlockUpdate {
-            pos: PackedChunkPosition::new(0, 0, 0),
-            block: BlockType::DIRT,
-            next: BlockUpdatePointer::from_u32(0),
-            chunk_x: 0,
-            chunk_z: 0,
-        },
+    if boot_config.reset_world {
+        warn!("wiping the world");
+        World::clear(&mut partitions.world, partitions.world_size);
+        // Once, rather than on every boot until someone turns it off
+        if let Err(err) = server_config.update(|config| config.reset_world = false) {
+            warn!("failed to turn reset-world off: {:?}", err);
+        }
+    }
+    let server_config = mk_static!(Mutex<NoopRawMutex, ConfigStore<Nvs>>, Mutex::new(server_config));
+    let world = mk_static!(
+        Mutex<NoopRawMutex, World<Partition>>,
+        Mutex::new(World::new(partitions.world, partitions.world_size))
+    );
+
+    let accounts = mk_static!(
+        Mutex<NoopRawMutex, AccountStore<Nvs>>,
+        Mutex::new(AccountStore::load(RecordStore::new(nvs, ACCOUNTS_RECORD, RECORD_SIZE)))
     );
-    let next = world.find_free_space();
-    info!("next: {}", next.to_u32());
 
     controller
         .set_power_saving(esp_wifi::config::PowerSaveMode::None)
@@ -112,12 +209,52 @@ async fn main(spawner: Spawner) {
     let (stack, runner) = embassy_net::new(
         wifi_interface,
         config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        // dhcp, the game's tcp socket, discovery, query, rcon and session requests
+        mk_static!(StackResources<6>, StackResources::<6>::new()),
         seed,
     );
 
+    // The config only holds an ip:port or nothing
+    let session = HttpTransport::new(stack, &boot_config.session_server);
+    match &session {
+        Some(_) => info!("verifying logins with {}", boot_config.session_server),
+        None => info!("offline mode, set session-server to verify logins"),
+    }
+
+    let forwarding = Forwarding::from_config(&boot_config);
+    match &forwarding {
+        Some(Forwarding::Velocity(_)) => info!("only accepting players forwarded by velocity"),
+        Some(Forwarding::BungeeCord) => info!("only accepting players forwarded by bungeecord"),
+        None => (),
+    }
+
+    if boot_config.proxy_protocol {
+        info!("expecting a PROXY header on every connection");
+    }
+
+    let transfer_secret = Some(boot_config.transfer_secret.as_bytes().to_vec()).filter(|secret| !secret.is_empty());
+    if transfer_secret.is_none() {
+        info!("set transfer-secret to let logins come back with transferred players");
+    }
+
+    let state = &*mk_static!(
+        ServerState,
+        ServerState {
+            encryption,
+            world,
+            block_changes: BlockChanges::new(),
+            config: server_config,
+            accounts,
+            session,
+            forwarding,
+            transfer_secret,
+            transfer_tickets: TransferTickets::new(),
+            favicon_upload: Mutex::new(String::new()),
+        }
+    );
+
     spawner
-        .spawn(maintain_wifi_connection(controller))
+        .spawn(maintain_wifi_connection(controller, ssid, password))
         .expect("failed to spawn connection");
     spawner
         .spawn(net_task(runner))
@@ -139,13 +276,25 @@ async fn main(spawner: Spawner) {
     }
 
     spawner
-        .spawn(start_tcp_server(stack, encryption))
+        .spawn(start_tcp_server(stack, state))
         .expect("failed to start tcp server");
 
     spawner
-        .spawn(start_discovery_server(stack))
+        .spawn(start_discovery_server(stack, state))
         .expect("failed to start discovery server");
 
+    spawner
+        .spawn(start_query_server(stack, state, rng))
+        .expect("failed to start query server");
+
+    if boot_config.rcon_password.is_empty() {
+        info!("rcon disabled, set rcon-password to enable it");
+    } else {
+        spawner
+            .spawn(start_rcon_server(stack, DEFAULT_RCON_PORT, state))
+            .expect("failed to start rcon server");
+    }
+
     loop {
         Timer::after(Duration::from_millis(5_000)).await;
     }
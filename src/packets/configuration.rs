@@ -1,28 +1,35 @@
 use alloc::string::String;
 use embassy_net::tcp::TcpSocket;
 use log::info;
-use mcproto_rs::{protocol::State, v1_21_8::{ConfigurationFinishSpec, Packet772}};
+use mcproto_rs::{
+    DeserializeBorrowed as _,
+    protocol::State,
+    v1_21_8::{ConfigurationFinishSpec, Packet772, borrowed::ServerBoundPluginMessageSpec},
+};
 
-use crate::{encryption::ServerEncryption, errors::MinecraftError, packets::{write_packet, PlayerContext}};
+use crate::{
+    commands::account::prompt,
+    errors::MinecraftError,
+    packets::{play::send_lines, write_packet, PlayerContext},
+    server::ServerState,
+};
+
+/// Plugin messages are only looked at, so they're read in place rather than copied out
+pub fn handle_plugin_message(message: &ServerBoundPluginMessageSpec<'_>) {
+    if message.id == "minecraft:brand" {
+        if let Ok(brand) = String::mc_deserialize_borrowed(message.data) {
+            info!("OMG BRAND: {}", brand.value);
+        }
+    }
+}
 
 pub async fn handle_configuration_packet(
     packet: Packet772,
     context: &mut PlayerContext,
     socket: &mut TcpSocket<'_>,
-    encryption: &ServerEncryption<'static>,
+    server: &ServerState,
 ) -> Result<(Option<Packet772>, bool), MinecraftError> {
     match packet {
-        Packet772::ServerBoundPluginMessage(spec) => {
-            match spec.id.as_str() {
-                "minecraft:brand" => {
-                    let brand = String::from_utf8(spec.data.to_vec()).unwrap();
-                    info!("OMG BRAND: {}", brand);
-                },
-                _ => (),
-            }
-
-            return Ok((None, true));
-        },
         Packet772::ConfigurationClientInformation(spec) => {
             let response = Packet772::ConfigurationFinish(ConfigurationFinishSpec {});
             write_packet(socket, context, response).await?;
@@ -32,6 +39,11 @@ pub async fn handle_configuration_packet(
         Packet772::ConfigurationFinishAck(_) => {
             context.state = State::Play;
 
+            if !context.logged_in {
+                let lines = prompt(context, server).await;
+                send_lines(socket, context, &lines).await?;
+            }
+
             return Ok((None, true));
         },
         _ => Ok((Some(packet), true)),
@@ -1,52 +1,108 @@
-use alloc::vec::Vec;
+use alloc::{borrow::ToOwned as _, vec, vec::Vec};
 use embassy_net::tcp::TcpSocket;
 use log::{info, warn};
 use mcproto_rs::{
+    auth::{has_joined, is_valid_username, server_hash},
+    compression::CompressionCodec,
+    forwarding::{parse_velocity, ForwardedPlayer, ForwardingErr, VELOCITY_CHANNEL, VELOCITY_FORWARDING_VERSION},
     protocol::State,
-    types::CountedArray,
-    v1_21_8::{LoginEncryptionRequestSpec, LoginSuccessSpec, Packet772},
+    types::{CountedArray, VarInt},
+    uuid::UUID4,
+    v1_21_8::{
+        LoginCookieRequestSpec, LoginEncryptionRequestSpec, LoginPluginRequestSpec, LoginSetCompressionSpec,
+        LoginSuccessSpec, Packet772,
+    },
 };
-use rsa::pkcs8::der::Encode as _;
 
 use crate::{
-    encryption::ServerEncryption,
     errors::MinecraftError,
+    forwarding::Forwarding,
     packets::{write_packet, PlayerContext, PlayerEncryptionContext, PlayerLoginContext, EMPTY_STRING},
+    server::{MAX_PACKET_LENGTH, ServerState},
+    transfer::{TransferState, TRANSFER_COOKIE},
 };
 
+/// Only one plugin request is ever sent, so its id is fixed
+const VELOCITY_MESSAGE_ID: i32 = 0;
+
 pub async fn handle_login_packets(
     packet: Packet772,
     context: &mut PlayerContext,
     socket: &mut TcpSocket<'_>,
-    encryption: &ServerEncryption<'static>,
+    server: &ServerState,
 ) -> Result<(Option<Packet772>, bool), MinecraftError> {
     match packet {
         Packet772::LoginStart(spec) => {
             info!("{} is connecting...", spec.name);
+            if !is_valid_username(&spec.name) {
+                return Err(MinecraftError::InvalidUsername);
+            }
+
+            let mut login_context = PlayerLoginContext {
+                verify_token: None,
+                // Never the client's claimed uuid, anyone could send someone else's. Online mode
+                // and forwarding replace this with the real one
+                uuid: UUID4::offline_player(&spec.name),
+                username: spec.name,
+                properties: Vec::new(),
+            };
 
-            let spki = rsa::pkcs8::SubjectPublicKeyInfo::from_key(&encryption.public)?
-                .to_der()
-                .expect("failed to serialize to der");
+            // Behind a proxy, which already did encryption and authentication with the player
+            match &server.forwarding {
+                Some(Forwarding::Velocity(_)) => {
+                    context.login_context = Some(login_context);
+                    let request = Packet772::LoginPluginRequest(LoginPluginRequestSpec {
+                        message_id: VarInt(VELOCITY_MESSAGE_ID),
+                        channel: VELOCITY_CHANNEL.to_owned(),
+                        data: vec![VELOCITY_FORWARDING_VERSION].into(),
+                    });
+                    write_packet(socket, context, request).await?;
+                    return Ok((None, true));
+                }
+                Some(Forwarding::BungeeCord) => {
+                    let player = context.forwarded.take().ok_or(ForwardingErr::NotForwarded)?;
+                    context.login_context = Some(login_context);
+                    use_forwarded(context, player);
+                    finish_login(socket, context, server).await?;
+                    return Ok((None, true));
+                }
+                None => (),
+            }
+
+            let spki = server.encryption.public_key_der().await?;
 
-            let random = encryption.random_data().await;
+            let random = server.encryption.random_data().await;
 
             let encryption_request =
                 Packet772::LoginEncryptionRequest(LoginEncryptionRequestSpec {
                     server_id: EMPTY_STRING,
                     public_key: CountedArray::from(spki),
                     verify_token: CountedArray::from(random.clone()),
-                    should_authenticate: false,
+                    should_authenticate: server.session.is_some(),
                 });
 
-            context.login_context = Some(PlayerLoginContext {
-                verify_token: Some(random),
-                uuid: spec.uuid,
-                username: spec.name,
-            });
+            login_context.verify_token = Some(random);
+            context.login_context = Some(login_context);
 
             write_packet(socket, context, encryption_request).await?;
             return Ok((None, true));
         }
+        Packet772::LoginPluginResponse(spec) => {
+            let Some(Forwarding::Velocity(secret)) = &server.forwarding else {
+                return Ok((None, true));
+            };
+            // Only answered once, and only to the request sent at Login Start
+            if spec.message_id.0 != VELOCITY_MESSAGE_ID || context.login_context.is_none() || context.logged_in {
+                return Err(MinecraftError::Unauthorized);
+            }
+
+            // Clients that connected straight to us don't know the channel and answer without data
+            let data = spec.data.ok_or(ForwardingErr::NotForwarded)?;
+            let player = parse_velocity(&data.data, secret)?;
+            use_forwarded(context, player);
+            finish_login(socket, context, server).await?;
+            return Ok((None, true));
+        }
         Packet772::LoginEncryptionResponse(spec) => {
             let login_context = if let Some(login_context) = &mut context.login_context {
                 login_context
@@ -59,23 +115,66 @@ pub async fn handle_login_packets(
             } else {
                 return Err(MinecraftError::Unauthorized);
             };
-            let decrypted_verify = encryption.decrypt_data(&spec.verify_token).await?;
+            let decrypted_verify = server.encryption.decrypt_data(&spec.verify_token).await?;
 
             if !decrypted_verify.eq(&verify_token) {
                 return Err(MinecraftError::Unauthorized);
             }
 
-            let decrypted_secret = encryption.decrypt_data(&spec.shared_secret).await?;
-            context.encryption_context = Some(PlayerEncryptionContext::new(decrypted_secret));
+            let decrypted_secret = server.encryption.decrypt_data(&spec.shared_secret).await?;
+            let hash = match &server.session {
+                Some(_) => {
+                    let spki = server.encryption.public_key_der().await?;
+                    Some(server_hash("", &decrypted_secret, &spki))
+                }
+                None => None,
+            };
+            context.encryption_context = Some(PlayerEncryptionContext::new(decrypted_secret, server.encryption));
+
+            // Online mode: the client told the session server it's joining a server with this
+            // hash, only the owner of the account could have. The client is already encrypting,
+            // so a refusal has to come after the line above
+            if let (Some(session), Some(hash)) = (&server.session, hash) {
+                let profile = has_joined(session, &login_context.username, &hash, None)
+                    .await?
+                    .ok_or(MinecraftError::Unauthorized)?;
+
+                info!("{} authenticated as {}", login_context.username, profile.id);
+                login_context.uuid = profile.id;
+                login_context.username = profile.name;
+                login_context.properties = profile.properties.into_iter().map(Into::into).collect();
+                context.logged_in = true;
+            }
 
             // Encryption enabled now because of above
-            let login_success = Packet772::LoginSuccess(LoginSuccessSpec {
-                uuid: login_context.uuid,
-                username: login_context.username.clone(),
-                properties: CountedArray::from(Vec::new()),
-            });
+            finish_login(socket, context, server).await?;
+            return Ok((None, true));
+        }
+        Packet772::LoginCookieResponse(spec) => {
+            // Only asked for once the login is settled, the uuid can't change after that
+            let (Some(secret), Some(login_context)) = (&server.transfer_secret, &context.login_context) else {
+                return Ok((None, true));
+            };
+            if !context.transferred || context.player.is_none() || spec.key != TRANSFER_COOKIE {
+                return Ok((None, true));
+            }
 
-            write_packet(socket, context, login_success).await?;
+            let state = spec
+                .payload
+                .and_then(|payload| TransferState::from_cookie(&payload, secret));
+            let now = server.world.lock().await.now();
+            match state {
+                Some(state) if state.uuid != login_context.uuid => {
+                    warn!("{} brought back someone else's cookie", login_context.username)
+                }
+                // Only the first time it comes back, and only soon after we handed it out
+                Some(state) if server.transfer_tickets.redeem(&state, now) => {
+                    info!("{} came back, logged in: {}", login_context.username, state.logged_in);
+                    context.logged_in |= state.logged_in;
+                }
+                Some(_) => warn!("{} brought back a stale or reused cookie", login_context.username),
+                None => info!("{} was transferred without a cookie from us", login_context.username),
+            }
             return Ok((None, true));
         }
         Packet772::LoginAcknowledged(_) => {
@@ -87,3 +186,60 @@ pub async fn handle_login_packets(
         _ => Ok((Some(packet), true)),
     }
 }
+
+/// Takes who the proxy says the player is over what we'd have worked out ourselves
+fn use_forwarded(context: &mut PlayerContext, player: ForwardedPlayer) {
+    match player.address.parse() {
+        Ok(address) => context.address = Some(address),
+        Err(_) => warn!("proxy forwarded an unreadable address {}", player.address),
+    }
+    if let Some(login_context) = &mut context.login_context {
+        info!("{} forwarded as {} from {}", login_context.username, player.uuid, player.address);
+        login_context.uuid = player.uuid;
+        if let Some(username) = player.username {
+            login_context.username = username;
+        }
+        login_context.properties = player.properties;
+    }
+    // The proxy is trusted with who they are, like the session server would be
+    context.logged_in = true;
+}
+
+/// Set Compression and Login Success, the same however the player got here
+async fn finish_login(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    // Set Compression is the last uncompressed packet. Like vanilla, it isn't sent at all when
+    // compression is off
+    let threshold = server.config.lock().await.config().compression_threshold;
+    if threshold >= 0 {
+        let set_compression = Packet772::LoginSetCompression(LoginSetCompressionSpec {
+            threshold: VarInt(threshold),
+        });
+        write_packet(socket, context, set_compression).await?;
+        context.compression =
+            Some(CompressionCodec::new(threshold).with_max_length(MAX_PACKET_LENGTH as usize));
+    }
+
+    // A player we sent away may be carrying their login back, the answer comes before Login Acknowledged
+    if context.transferred && server.transfer_secret.is_some() {
+        let request = Packet772::LoginCookieRequest(LoginCookieRequestSpec {
+            key: TRANSFER_COOKIE.to_owned(),
+        });
+        write_packet(socket, context, request).await?;
+    }
+
+    let login_context = context.login_context.as_ref().ok_or(MinecraftError::Unauthorized)?;
+    let login_success = Packet772::LoginSuccess(LoginSuccessSpec {
+        uuid: login_context.uuid,
+        username: login_context.username.clone(),
+        properties: CountedArray::from(login_context.properties.clone()),
+    });
+
+    context.player = Some(server.world.lock().await.player_index(&login_context.username));
+
+    write_packet(socket, context, login_success).await?;
+    Ok(())
+}
@@ -1,64 +1,77 @@
-use core::mem;
+use core::{mem, net::IpAddr};
 
-use aes::cipher::AsyncStreamCipher;
-use aes::cipher::BlockEncryptMut;
-use aes::cipher::KeyIvInit;
-use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+use alloc::{borrow::ToOwned as _, format, string::String, vec::Vec};
 use embassy_net::tcp::TcpSocket;
 use log::{info, warn};
 use mcproto_rs::{
-    Serialize,
-    protocol::{HasPacketBody, HasPacketId, State},
+    Serialize, SerializeErr, SerializedSize as _, Serializer as _,
+    compression::{CompressionCodec, ZlibEncoder},
+    forwarding::ForwardedPlayer,
+    protocol::{HasPacketId as _, State},
     status::{StatusPlayersSpec, StatusSpec, StatusVersionSpec},
-    types::{Chat, CountedArray, VarInt},
+    types::{BytesSerializer, Chat, CountedArray, NbtChat, VarInt},
     uuid::UUID4,
+    v1_21::{ProtocolVersion, VersionedPacket},
     v1_21_8::{
-        HandshakeIntent, LoginEncryptionRequestSpec, LoginSuccessSpec, Packet772, PingResponseSpec,
-        StatusResponseSpec,
+        ConfigurationDisconnectSpec, ConfigurationStoreCookieSpec, ConfigurationTransferSpec, HandshakeIntent,
+        LoginDisconnectSpec, LoginEncryptionRequestSpec, LoginSuccessProperty, LoginSuccessSpec, Packet772,
+        PingResponseSpec, PlayDisconnectSpec, PlayStoreCookieSpec, PlayTransferSpec, StatusResponseSpec,
     },
 };
 use rsa::pkcs8::der::Encode;
 
 use crate::packets::configuration::handle_configuration_packet;
 use crate::packets::login::handle_login_packets;
+use crate::packets::play::handle_play_packets;
 use crate::packets::status::handle_status_packets;
 use crate::{
-    encryption::ServerEncryption,
+    encryption::{CipherStream as _, PlatformBackend, PlatformStream, ServerEncryption},
     errors::MinecraftError,
+    server::ServerState,
+    transfer::{TransferState, TRANSFER_COOKIE},
     utils::{SliceSerializer, text},
+    world::{
+        block::{BlockType, PlayerIndex},
+        replay::Replay,
+    },
 };
 
 const PACKET_WRITE_BUFFER_SIZE: usize = 4096;
+// Larger packets are serialized onto the heap whole, this keeps that well inside it
+const MAX_WRITTEN_PACKET: usize = 16 * 1024;
 pub const VAR_INT_BUF_SIZE: usize = 5;
 pub const EMPTY_STRING: String = String::new();
 
 mod configuration;
 mod login;
+mod play;
 mod status;
 
+pub use configuration::handle_plugin_message;
+pub use play::{send_block_changes, send_replay};
+pub use status::{answer_legacy_ping, server_status};
+
 struct PlayerLoginContext {
     verify_token: Option<Vec<u8>>,
     uuid: UUID4,
     username: String,
+    /// Skin textures from the session server, empty in offline mode
+    properties: Vec<LoginSuccessProperty>,
 }
 
-type Aes128Cfb8Enc = cfb8::Encryptor<aes::Aes128>;
-type Aes128Cfb8Dec = cfb8::Decryptor<aes::Aes128>;
 pub struct PlayerEncryptionContext {
-    pub encrypter: Aes128Cfb8Enc,
-    pub decrypter: Aes128Cfb8Dec,
+    pub stream: PlatformStream,
     shared_token: [u8; 16],
 }
 
 impl PlayerEncryptionContext {
-    pub fn new(shared_token: Vec<u8>) -> Self {
+    pub fn new(shared_token: Vec<u8>, encryption: &ServerEncryption<PlatformBackend>) -> Self {
         info!("token length {}", shared_token.len() * 8);
         let shared_token: [u8; 16] = shared_token
             .try_into()
             .expect("shared token not long enough");
         Self {
-            encrypter: Aes128Cfb8Enc::new(&shared_token.into(), &shared_token.into()),
-            decrypter: Aes128Cfb8Dec::new(&shared_token.into(), &shared_token.into()),
+            stream: encryption.cfb8_stream(&shared_token),
             shared_token,
         }
     }
@@ -66,16 +79,66 @@ impl PlayerEncryptionContext {
 
 pub struct PlayerContext {
     pub state: State,
+    /// Where the player really connects from, the proxy's word for it if one forwards
+    pub address: Option<IpAddr>,
+    /// BungeeCord's info from the handshake, waiting for Login Start
+    forwarded: Option<ForwardedPlayer>,
+    /// Sent here by another server, which may have given the player a cookie from us
+    transferred: bool,
+    /// Taken from the handshake, decides the packet ids and layouts used from then on
+    pub version: ProtocolVersion,
     login_context: Option<PlayerLoginContext>,
     pub encryption_context: Option<PlayerEncryptionContext>,
+    /// Set once Set Compression has been sent, every later frame uses the compressed format
+    pub compression: Option<CompressionCodec>,
+    /// Set once the player has logged in, used to attribute their block updates
+    pub player: Option<PlayerIndex>,
+    /// Verified by the session server or with `/login`, nothing else is allowed until then
+    pub logged_in: bool,
+    /// Where the player first reported being, they're kept there until they log in
+    pub join_position: Option<(f64, f64, f64)>,
+    /// Punching a block shows its history instead of breaking it
+    pub inspecting: bool,
+    /// Set with `/gamemode`, creative clients break a block as soon as they punch it
+    pub creative: bool,
+    /// Hotbar slot the player has selected, 0 to 8
+    pub held_slot: usize,
+    /// What each hotbar slot places, filled in as a creative player picks blocks
+    pub hotbar: [Option<BlockType>; 9],
+    /// Time-lapse of the journal currently being streamed to this player
+    pub replay: Option<Replay>,
+    /// Host and port to send the player to once their command's output is out
+    pub transfer: Option<(String, u16)>,
+}
+
+impl PlayerContext {
+    pub fn username(&self) -> Option<&str> {
+        self.login_context
+            .as_ref()
+            .map(|login_context| login_context.username.as_str())
+    }
 }
 
 impl Default for PlayerContext {
     fn default() -> Self {
         Self {
             state: State::Handshaking,
+            address: None,
+            forwarded: None,
+            transferred: false,
+            version: ProtocolVersion::LATEST,
             login_context: None,
             encryption_context: None,
+            compression: None,
+            player: None,
+            logged_in: false,
+            join_position: None,
+            inspecting: false,
+            creative: false,
+            held_slot: 0,
+            hotbar: [None; 9],
+            replay: None,
+            transfer: None,
         }
     }
 }
@@ -87,9 +150,7 @@ async fn write_encryption_transparent<const T: usize>(
 ) -> Result<(), MinecraftError> {
     for slice in slices {
         if let Some(encryption) = &mut context.encryption_context {
-            for chunk in slice.chunks_mut(1) {
-                encryption.encrypter.encrypt_block_mut(chunk.into());
-            }
+            encryption.stream.encrypt(slice);
         }
 
         let mut written = 0;
@@ -107,12 +168,28 @@ async fn write_packet(
     context: &mut PlayerContext,
     packet: Packet772,
 ) -> Result<(), MinecraftError> {
+    let packet = VersionedPacket {
+        version: context.version,
+        packet: &packet,
+    };
+    let size = packet.serialized_size()?;
+    if size > PACKET_WRITE_BUFFER_SIZE {
+        return write_packet_large(socket, context, &packet, size).await;
+    }
+
     let mut serializer_backend = [0u8; PACKET_WRITE_BUFFER_SIZE];
     let mut serializer = SliceSerializer::create(&mut serializer_backend);
-    packet.id().mc_serialize(&mut serializer)?;
-    packet.mc_serialize_body(&mut serializer)?;
+    packet.mc_serialize(&mut serializer)?;
 
     let packet = serializer.finish();
+    let mut compressed;
+    let packet = match context.compression {
+        Some(codec) => {
+            compressed = codec.encode(packet);
+            compressed.as_mut_slice()
+        }
+        None => packet,
+    };
 
     let mut length_serializer_backend = [0u8; VAR_INT_BUF_SIZE];
     let mut length_serializer = SliceSerializer::create(&mut length_serializer_backend);
@@ -132,16 +209,145 @@ async fn write_packet(
     Ok(())
 }
 
+/// Writes a packet that doesn't fit in the write buffer. It's serialized once onto the heap, then
+/// compressed a piece at a time twice over: once to learn the frame length that has to go first,
+/// and again to send it, so the compressed copy is never held whole
+async fn write_packet_large<P: Serialize>(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    packet: &P,
+    size: usize,
+) -> Result<(), MinecraftError> {
+    if size > MAX_WRITTEN_PACKET {
+        return Err(SerializeErr::CannotSerialize(format!("{} byte packet is too large to send", size)).into());
+    }
+    let mut serializer = BytesSerializer::with_capacity(size);
+    packet.mc_serialize(&mut serializer)?;
+    let mut serialized = serializer.into_bytes();
+
+    let compressed = context
+        .compression
+        .is_some_and(|codec| codec.is_enabled() && size >= codec.threshold() as usize);
+
+    let mut header_backend = [0u8; VAR_INT_BUF_SIZE * 2];
+    let mut header = SliceSerializer::create(&mut header_backend);
+    match context.compression {
+        Some(_) if compressed => {
+            let mut piece = Vec::new();
+            let mut encoder = ZlibEncoder::new(&serialized);
+            let mut compressed_length = 0;
+            while !encoder.is_finished() {
+                piece.clear();
+                encoder.encode(PACKET_WRITE_BUFFER_SIZE, &mut piece);
+                compressed_length += piece.len();
+            }
+
+            let data_length = VarInt(size as i32);
+            VarInt((data_length.serialized_size()? + compressed_length) as i32).mc_serialize(&mut header)?;
+            data_length.mc_serialize(&mut header)?;
+            write_encryption_transparent(socket, context, [header.finish()]).await?;
+
+            let mut encoder = ZlibEncoder::new(&serialized);
+            while !encoder.is_finished() {
+                piece.clear();
+                encoder.encode(PACKET_WRITE_BUFFER_SIZE, &mut piece);
+                write_encryption_transparent(socket, context, [piece.as_mut_slice()]).await?;
+            }
+            return Ok(());
+        }
+        // Left uncompressed, behind a zero data length
+        Some(_) => {
+            VarInt(size as i32 + 1).mc_serialize(&mut header)?;
+            VarInt(0).mc_serialize(&mut header)?;
+        }
+        None => VarInt(size as i32).mc_serialize(&mut header)?,
+    }
+    write_encryption_transparent(socket, context, [header.finish()]).await?;
+    for piece in serialized.chunks_mut(PACKET_WRITE_BUFFER_SIZE) {
+        write_encryption_transparent(socket, context, [piece]).await?;
+    }
+
+    Ok(())
+}
+
+/// Sends the client to another server, with a signed cookie of its login for when it comes back
+pub async fn send_transfer(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+    host: &str,
+    port: u16,
+) -> Result<(), MinecraftError> {
+    let cookie = match (&server.transfer_secret, &context.login_context) {
+        (Some(secret), Some(login_context)) => {
+            let random = server.encryption.random_data().await;
+            let state = TransferState {
+                uuid: login_context.uuid,
+                logged_in: context.logged_in,
+                issued_at: server.world.lock().await.now(),
+                nonce: u64::from_be_bytes(random[..8].try_into().expect("random data is 64 bytes")),
+            };
+            server.transfer_tickets.issue(state);
+            Some(state.to_cookie(secret))
+        }
+        _ => None,
+    };
+    let key = TRANSFER_COOKIE.to_owned();
+    let (host, port) = (host.to_owned(), VarInt(port as i32));
+    let (store, transfer) = match context.state {
+        State::Configuration => (
+            cookie.map(|payload| {
+                Packet772::ConfigurationStoreCookie(ConfigurationStoreCookieSpec { key, payload: payload.into() })
+            }),
+            Packet772::ConfigurationTransfer(ConfigurationTransferSpec { host, port }),
+        ),
+        State::Play => (
+            cookie.map(|payload| Packet772::PlayStoreCookie(PlayStoreCookieSpec { key, payload: payload.into() })),
+            Packet772::PlayTransfer(PlayTransferSpec { host, port }),
+        ),
+        // The client can only be moved once it has logged in
+        State::Handshaking | State::Status | State::Login => return Ok(()),
+    };
+
+    if let Some(store) = store {
+        write_packet(socket, context, store).await?;
+    }
+    write_packet(socket, context, transfer).await?;
+    socket.flush().await?;
+    Ok(())
+}
+
+/// Tells the client why it's being disconnected, if its state has a packet for that
+pub async fn send_disconnect(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    reason: &str,
+) -> Result<(), MinecraftError> {
+    let reason = Chat::from_text(reason);
+    let packet = match context.state {
+        State::Handshaking | State::Status => return Ok(()),
+        State::Login => Packet772::LoginDisconnect(LoginDisconnectSpec { reason }),
+        State::Configuration => {
+            Packet772::ConfigurationDisconnect(ConfigurationDisconnectSpec { reason: NbtChat(reason) })
+        }
+        State::Play => Packet772::PlayDisconnect(PlayDisconnectSpec { reason: NbtChat(reason) }),
+    };
+    write_packet(socket, context, packet).await?;
+    // The socket is dropped right after, make sure the reason made it out first
+    socket.flush().await?;
+    Ok(())
+}
+
 pub async fn process_packet(
     packet: Packet772,
     context: &mut PlayerContext,
     socket: &mut TcpSocket<'_>,
-    encryption: &ServerEncryption<'static>,
+    server: &ServerState,
 ) -> Result<bool, MinecraftError> {
     let leftover = match context.state {
         State::Handshaking | State::Status => {
             let (packet, status) =
-                handle_status_packets(packet, context, socket, encryption).await?;
+                handle_status_packets(packet, context, socket, server).await?;
             let packet = if let Some(packet) = packet {
                 packet
             } else {
@@ -151,7 +357,7 @@ pub async fn process_packet(
         }
         State::Login => {
             let (packet, status) =
-                handle_login_packets(packet, context, socket, encryption).await?;
+                handle_login_packets(packet, context, socket, server).await?;
             let packet = if let Some(packet) = packet {
                 packet
             } else {
@@ -161,7 +367,16 @@ pub async fn process_packet(
         }
         State::Configuration => {
             let (packet, status) =
-                handle_configuration_packet(packet, context, socket, encryption).await?;
+                handle_configuration_packet(packet, context, socket, server).await?;
+            let packet = if let Some(packet) = packet {
+                packet
+            } else {
+                return Ok(status);
+            };
+            packet
+        }
+        State::Play => {
+            let (packet, status) = handle_play_packets(packet, context, socket, server).await?;
             let packet = if let Some(packet) = packet {
                 packet
             } else {
@@ -169,7 +384,6 @@ pub async fn process_packet(
             };
             packet
         }
-        _ => packet,
     };
 
     info!("no handler for type {:?}", leftover.id());
@@ -0,0 +1,368 @@
+use alloc::{string::String, vec::Vec};
+use log::info;
+use embassy_net::tcp::TcpSocket;
+use mcproto_rs::{
+    types::{Chat, IntPosition, NbtChat, VarInt},
+    v1_21_8::{
+        BlockFace, GameEvent, Hand, Packet772, PlayBlockUpdateSpec, PlayGameEventSpec, PlayPlayerActionSpec,
+        PlaySynchronizePlayerPositionSpec, PlaySystemChatMessageSpec, PlayUseItemOnSpec, PlayerActionStatus,
+        TELEPORT_RELATIVE_PITCH, TELEPORT_RELATIVE_YAW,
+    },
+};
+
+use crate::{
+    commands::{CommandSource, account::prompt, dispatch, inspect::describe_history},
+    errors::MinecraftError,
+    packets::{send_transfer, write_packet, PlayerContext},
+    server::ServerState,
+    world::{
+        block::{BlockType, PlayerIndex},
+        replay::ReplayPhase,
+    },
+};
+
+/// The hotbar's slots in the player's inventory window
+const HOTBAR_SLOTS: core::ops::Range<i16> = 36..45;
+
+pub async fn send_system_message(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    line: &str,
+) -> Result<(), MinecraftError> {
+    let message = Packet772::PlaySystemChatMessage(PlaySystemChatMessageSpec {
+        content: NbtChat(Chat::from_traditional(line, false)),
+        overlay: false,
+    });
+    write_packet(socket, context, message).await
+}
+
+pub async fn send_lines(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    lines: &[String],
+) -> Result<(), MinecraftError> {
+    for line in lines {
+        send_system_message(socket, context, line).await?;
+    }
+    Ok(())
+}
+
+async fn send_block(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    (x, y, z): (i32, i32, i32),
+    block: BlockType,
+) -> Result<(), MinecraftError> {
+    let update = Packet772::PlayBlockUpdate(PlayBlockUpdateSpec {
+        location: IntPosition { x, y: y as i16, z },
+        block_id: VarInt(block.state_id()),
+    });
+    write_packet(socket, context, update).await
+}
+
+/// Sends the client every block change queued up by something other than itself
+pub async fn send_block_changes(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    let pending = server.block_changes.take();
+    for change in pending.changes {
+        send_block(socket, context, (change.x, change.y, change.z), change.block).await?;
+    }
+    for (chunk_x, chunk_z) in pending.chunks {
+        let latest = server.world.lock().await.latest_blocks(chunk_x, chunk_z);
+        for change in latest {
+            send_block(socket, context, (change.x, change.y, change.z), change.block).await?;
+        }
+    }
+    if pending.overflowed {
+        send_system_message(socket, context, "§7too many blocks changed at once, rejoin to see them all").await?;
+    }
+    Ok(())
+}
+
+/// Streams the next part of a running replay, if it's due
+pub async fn send_replay(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    let Some(replay) = &context.replay else {
+        return Ok(());
+    };
+
+    if replay.phase == ReplayPhase::Clearing && replay.cursor == 0 {
+        // Spectators can fly through the build without breaking anything
+        send_game_mode(socket, context, 3.0).await?;
+    }
+    let Some(replay) = &mut context.replay else {
+        return Ok(());
+    };
+
+    let mut blocks = Vec::new();
+    let mut finished = false;
+    {
+        let mut world = server.world.lock().await;
+        for _ in 0..replay.budget() {
+            match world.journal_entry(replay.cursor) {
+                Some(update) => {
+                    let block = match replay.phase {
+                        ReplayPhase::Clearing => BlockType::AIR,
+                        ReplayPhase::Building => update.block,
+                    };
+                    blocks.push((update.position(), block));
+                    replay.cursor += 1;
+                }
+                None => {
+                    finished = replay.finish_phase();
+                    break;
+                }
+            }
+        }
+    }
+
+    for (position, block) in blocks {
+        send_block(socket, context, position, block).await?;
+    }
+
+    if finished {
+        info!("replay finished");
+        context.replay = None;
+        // The build has caught up with the world, so they can carry on from here
+        send_game_mode(socket, context, own_game_mode(context)).await?;
+        send_system_message(socket, context, "§7replay finished").await?;
+    }
+    Ok(())
+}
+
+/// Puts back whatever the client shows at a block with the newest value from the journal
+async fn resend_block(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+    (x, y, z): (i32, i32, i32),
+) -> Result<(), MinecraftError> {
+    let latest = server.world.lock().await.block_history(x, y, z, 1).first().copied();
+    if let Some(latest) = latest {
+        send_block(socket, context, (x, y, z), latest.block).await?;
+    }
+    Ok(())
+}
+
+/// The game mode the player is in outside a replay
+fn own_game_mode(context: &PlayerContext) -> f32 {
+    if context.creative { 1.0 } else { 0.0 }
+}
+
+async fn send_game_mode(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    mode: f32,
+) -> Result<(), MinecraftError> {
+    let change = Packet772::PlayGameEvent(PlayGameEventSpec {
+        event: GameEvent::ChangeGameMode,
+        value: mode,
+    });
+    write_packet(socket, context, change).await
+}
+
+async fn handle_player_action(
+    spec: PlayPlayerActionSpec,
+    context: &mut PlayerContext,
+    socket: &mut TcpSocket<'_>,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    let IntPosition { x, y, z } = spec.location;
+    let y = y as i32;
+
+    match spec.status {
+        // Players who haven't logged in can't change anything, put back what their client broke
+        PlayerActionStatus::StartedDigging if !context.logged_in => {
+            let lines = prompt(context, server).await;
+            send_lines(socket, context, &lines).await?;
+            resend_block(socket, context, server, (x, y, z)).await?;
+        }
+        PlayerActionStatus::FinishedDigging if !context.logged_in => {
+            resend_block(socket, context, server, (x, y, z)).await?;
+        }
+        PlayerActionStatus::StartedDigging if context.inspecting => {
+            let (lines, latest) = describe_history(&mut *server.world.lock().await, x, y, z);
+            send_lines(socket, context, &lines).await?;
+
+            // Creative players break blocks client side as soon as they punch, put it back
+            if let Some(latest) = latest {
+                send_block(socket, context, (x, y, z), latest.block).await?;
+            }
+        }
+        // Survival players break it once they're done digging, the history was shown when they
+        // started
+        PlayerActionStatus::FinishedDigging if context.inspecting => {
+            resend_block(socket, context, server, (x, y, z)).await?;
+        }
+        // Creative clients never finish digging, the block is gone as soon as they punch it
+        PlayerActionStatus::StartedDigging if context.creative => {
+            change_block(context, server, (x, y, z), BlockType::AIR).await;
+        }
+        PlayerActionStatus::FinishedDigging => {
+            change_block(context, server, (x, y, z), BlockType::AIR).await;
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+async fn handle_use_item_on(
+    spec: PlayUseItemOnSpec,
+    context: &mut PlayerContext,
+    socket: &mut TcpSocket<'_>,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    let position = placed_position(spec.location, spec.face);
+    let held = match spec.hand {
+        Hand::MainHand => context.hotbar[context.held_slot],
+        // Nothing ever fills in the off hand
+        Hand::OffHand => None,
+    };
+
+    match held {
+        // Players who haven't logged in can't change anything, put back what their client placed
+        _ if !context.logged_in => {
+            let lines = prompt(context, server).await;
+            send_lines(socket, context, &lines).await?;
+            resend_block(socket, context, server, position).await?;
+        }
+        Some(block) => change_block(context, server, position, block).await,
+        // Not a block the world can hold, don't leave the client showing it
+        None => resend_block(socket, context, server, position).await?,
+    }
+
+    Ok(())
+}
+
+/// Where a block placed against the clicked face ends up
+fn placed_position(IntPosition { x, y, z }: IntPosition, face: BlockFace) -> (i32, i32, i32) {
+    let y = y as i32;
+    match face {
+        BlockFace::Bottom => (x, y - 1, z),
+        BlockFace::Top => (x, y + 1, z),
+        BlockFace::North => (x, y, z - 1),
+        BlockFace::South => (x, y, z + 1),
+        BlockFace::West => (x - 1, y, z),
+        BlockFace::East => (x + 1, y, z),
+    }
+}
+
+/// Journals a change the player's client has already made
+async fn change_block(context: &PlayerContext, server: &ServerState, (x, y, z): (i32, i32, i32), block: BlockType) {
+    let player = context.player.unwrap_or(PlayerIndex::UNKNOWN);
+    server
+        .world
+        .lock()
+        .await
+        .append_block_update(x, y, z, block, player);
+}
+
+/// Players who haven't logged in stay where they joined, any move teleports them back there
+async fn hold_in_place(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    position: (f64, f64, f64),
+) -> Result<(), MinecraftError> {
+    if context.logged_in {
+        return Ok(());
+    }
+    let Some((x, y, z)) = context.join_position else {
+        context.join_position = Some(position);
+        return Ok(());
+    };
+    if (x, y, z) == position {
+        return Ok(());
+    }
+
+    // They can still look around
+    let teleport = Packet772::PlaySynchronizePlayerPosition(PlaySynchronizePlayerPositionSpec {
+        teleport_id: VarInt(0),
+        x,
+        y,
+        z,
+        velocity_x: 0.0,
+        velocity_y: 0.0,
+        velocity_z: 0.0,
+        yaw: 0.0,
+        pitch: 0.0,
+        flags: TELEPORT_RELATIVE_YAW | TELEPORT_RELATIVE_PITCH,
+    });
+    write_packet(socket, context, teleport).await
+}
+
+pub async fn handle_play_packets(
+    packet: Packet772,
+    context: &mut PlayerContext,
+    socket: &mut TcpSocket<'_>,
+    server: &ServerState,
+) -> Result<(Option<Packet772>, bool), MinecraftError> {
+    match packet {
+        Packet772::PlayChatCommand(spec) => {
+            let creative = context.creative;
+            let lines = dispatch(&spec.command, CommandSource::Player(context), server).await;
+            send_lines(socket, context, &lines).await?;
+
+            if context.creative != creative {
+                send_game_mode(socket, context, own_game_mode(context)).await?;
+            }
+
+            if let Some((host, port)) = context.transfer.take() {
+                info!("sending {:?} to {}:{}", context.username(), host, port);
+                send_transfer(socket, context, server, &host, port).await?;
+                // The client hangs up and connects to the other server
+                return Ok((None, false));
+            }
+
+            return Ok((None, true));
+        }
+        Packet772::PlayPlayerAction(spec) => {
+            handle_player_action(spec, context, socket, server).await?;
+
+            return Ok((None, true));
+        }
+        Packet772::PlayUseItemOn(spec) => {
+            handle_use_item_on(spec, context, socket, server).await?;
+
+            return Ok((None, true));
+        }
+        Packet772::PlaySetHeldItem(spec) => {
+            context.held_slot = spec.slot.clamp(0, 8) as usize;
+
+            return Ok((None, true));
+        }
+        // Creative clients pick their own blocks, survival ones only ever have what we give them
+        Packet772::PlaySetCreativeModeSlot(spec) if context.creative => {
+            if HOTBAR_SLOTS.contains(&spec.slot) {
+                context.hotbar[(spec.slot - HOTBAR_SLOTS.start) as usize] =
+                    spec.item_id().and_then(BlockType::from_item_id);
+            }
+
+            return Ok((None, true));
+        }
+        Packet772::PlaySetPlayerPosition(spec) => {
+            hold_in_place(socket, context, (spec.x, spec.feet_y, spec.z)).await?;
+
+            return Ok((None, true));
+        }
+        Packet772::PlaySetPlayerPositionAndRotation(spec) => {
+            hold_in_place(socket, context, (spec.x, spec.feet_y, spec.z)).await?;
+
+            return Ok((None, true));
+        }
+        // Nobody hears a player who hasn't logged in, they're told how to instead
+        Packet772::PlayChatMessage(_) if !context.logged_in => {
+            let lines = prompt(context, server).await;
+            send_lines(socket, context, &lines).await?;
+
+            return Ok((None, true));
+        }
+        _ => Ok((Some(packet), true)),
+    }
+}
@@ -1,19 +1,61 @@
 use alloc::{borrow::ToOwned as _, vec::Vec};
 use embassy_net::tcp::TcpSocket;
-use log::{info, warn};
-use mcproto_rs::{protocol::State, status::{StatusFaviconSpec, StatusPlayersSpec, StatusSpec, StatusVersionSpec}, types::Chat, v1_21_8::{HandshakeIntent, Packet772, PingResponseSpec, StatusResponseSpec}};
+use log::info;
+use mcproto_rs::{forwarding::parse_bungeecord, legacy::{LegacyStatus, LEGACY_PROTOCOL}, protocol::State, status::{StatusFaviconSpec, StatusPlayersSpec, StatusSpec, StatusVersionSpec}, types::Chat, v1_21::ProtocolVersion, v1_21_8::{HandshakeIntent, Packet772, PingResponseSpec, StatusResponseSpec}};
 
-use crate::{encryption::ServerEncryption, errors::MinecraftError, packets::{write_packet, PlayerContext}, utils::text};
+use crate::{errors::MinecraftError, forwarding::Forwarding, packets::{write_encryption_transparent, write_packet, PlayerContext}, server::ServerState, storage::config::ServerConfig};
 
-const FAVICON: &[u8; 2765] = include_bytes!("./favicon.png");
+/// Shown unless the config has its own
+const FAVICON: &[u8] = include_bytes!("./favicon.png");
+
+/// What both the server list ping and the legacy one report, less the favicon
+pub fn server_status(version: ProtocolVersion, config: &ServerConfig) -> StatusSpec {
+    StatusSpec {
+        // Unsupported clients see the latest version and are told they're outdated
+        version: Some(StatusVersionSpec {
+            name: version.name().to_owned(),
+            protocol: version.protocol(),
+        }),
+        players: StatusPlayersSpec {
+            max: config.max_players as i32,
+            online: 0,
+            sample: Vec::new(),
+        },
+        description: Chat::from_traditional(&config.motd, false),
+        favicon: None,
+        enforces_secure_chat: false,
+    }
+}
+
+/// Answers a pre-1.7 server list ping, which is a single unframed kick packet
+pub async fn answer_legacy_ping(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    server: &ServerState,
+) -> Result<(), MinecraftError> {
+    let status = server_status(ProtocolVersion::LATEST, server.config.lock().await.config());
+    let motd = status.description.to_traditional().unwrap_or_default();
+    let version = status.version.as_ref().map_or("", |version| version.name.as_str());
+    let mut response = LegacyStatus {
+        protocol: LEGACY_PROTOCOL,
+        version,
+        motd: &motd,
+        online: status.players.online,
+        max: status.players.max,
+    }
+    .encode();
+
+    write_encryption_transparent(socket, context, [&mut response]).await?;
+    socket.flush().await?;
+    Ok(())
+}
 
 pub async fn handle_status_packets(
     packet: Packet772,
     context: &mut PlayerContext,
     socket: &mut TcpSocket<'_>,
-    encryption: &ServerEncryption<'static>,
+    server: &ServerState,
 ) -> Result<(Option<Packet772>, bool), MinecraftError> {
-    let favicon_vec = FAVICON.to_vec();
     match packet {
         Packet772::PingRequest(v) => {
             let response = Packet772::PingResponse(PingResponseSpec { payload: v.payload });
@@ -23,43 +65,47 @@ pub async fn handle_status_packets(
         }
         Packet772::Handshake(v) => {
             info!("trying to connect with version: {}", v.protocol_version);
+            let version = ProtocolVersion::from_protocol(v.protocol_version.0);
+            if let Some(version) = version {
+                context.version = version;
+            }
 
             match v.intent {
                 HandshakeIntent::Status => {
                     context.state = State::Status;
                 }
-                HandshakeIntent::Login => {
+                // A transfer is a login that another server sent here
+                HandshakeIntent::Login | HandshakeIntent::Transfer => {
                     context.state = State::Login;
-                }
-                HandshakeIntent::Transfer => {
-                    warn!("transfer not supported")
+                    context.transferred = matches!(v.intent, HandshakeIntent::Transfer);
+                    // Status still answers so the client can show the mismatch, a login can't go on
+                    if version.is_none() {
+                        return Err(MinecraftError::UnsupportedVersion(v.protocol_version.0));
+                    }
+                    // BungeeCord puts the player's info after the address, nothing else vouches for it
+                    if let Some(Forwarding::BungeeCord) = server.forwarding {
+                        let (_, player) = parse_bungeecord(&v.server_address)?;
+                        context.forwarded = Some(player);
+                    }
                 }
             };
 
             return Ok((None, true));
         }
         Packet772::StatusRequest(_) => {
-            let response = Packet772::StatusResponse(StatusResponseSpec {
-                response: StatusSpec {
-                    version: Some(StatusVersionSpec {
-                        name: "1.21.8".to_owned(),
-                        protocol: 772,
-                    }),
-                    players: StatusPlayersSpec {
-                        max: 10,
-                        online: 0,
-                        sample: Vec::new(),
+            let response = {
+                let config = server.config.lock().await;
+                let config = config.config();
+                Packet772::StatusResponse(StatusResponseSpec {
+                    response: StatusSpec {
+                        favicon: Some(StatusFaviconSpec {
+                            content_type: "image/png".to_owned(),
+                            data: config.favicon.as_deref().unwrap_or(FAVICON).to_vec(),
+                        }),
+                        ..server_status(context.version, config)
                     },
-                    description: Chat::Text(text(
-                        "blockchain - instead of bitcoin, a block game for your key chain!",
-                    )),
-                    favicon: Some(StatusFaviconSpec {
-                        content_type: "image/png".to_owned(),
-                        data: favicon_vec,
-                    }),
-                    enforces_secure_chat: false,
-                },
-            });
+                })
+            };
 
             write_packet(socket, context, response).await?;
             return Ok((None, true));
@@ -0,0 +1,175 @@
+use core::ptr::addr_of_mut;
+
+use alloc::string::String;
+use embassy_net::tcp::TcpSocket;
+use embassy_time::{Duration, Timer};
+use log::{info, warn};
+use mcproto_rs::{
+    hash::{constant_time_eq, sha256},
+    rcon::{
+        AUTH_FAILED, MAX_REQUEST_LENGTH, RconPacket, TYPE_AUTH, TYPE_AUTH_RESPONSE, TYPE_COMMAND,
+        TYPE_RESPONSE, fragments,
+    },
+};
+
+use crate::{
+    commands::{CommandSource, dispatch},
+    errors::MinecraftError,
+    server::ServerState,
+};
+
+/// Vanilla's default rcon.port
+pub const DEFAULT_RCON_PORT: u16 = 25575;
+// Separate from the game socket's, so a console session never waits on a player
+const RX_BUFFER_SIZE: usize = 2048;
+const TX_BUFFER_SIZE: usize = 8192;
+// A wrong password ends the connection after this, so guesses come one a second at most
+const FAILED_AUTH_DELAY: Duration = Duration::from_secs(1);
+
+static mut RX_BUFFER: [u8; RX_BUFFER_SIZE] = [0; RX_BUFFER_SIZE];
+static mut TX_BUFFER: [u8; TX_BUFFER_SIZE] = [0; TX_BUFFER_SIZE];
+
+/// Runs commands sent over Source RCON as an admin, one console at a time
+#[embassy_executor::task]
+pub async fn start_rcon_server(
+    stack: embassy_net::Stack<'static>,
+    port: u16,
+    state: &'static ServerState,
+) {
+    info!("answering rcon on tcp port {}", port);
+
+    loop {
+        let mut socket = TcpSocket::new(stack, unsafe { &mut *addr_of_mut!(RX_BUFFER) }, unsafe {
+            &mut *addr_of_mut!(TX_BUFFER)
+        });
+
+        socket.accept(port).await.expect("failed to accept rcon socket");
+        info!("rcon connection from {:?}", socket.remote_endpoint());
+
+        if let Err(err) = handle_connection(&mut socket, state).await {
+            warn!("error while handling rcon connection {err:?}");
+        }
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+async fn handle_connection(
+    socket: &mut TcpSocket<'_>,
+    state: &ServerState,
+) -> Result<(), MinecraftError> {
+    // Parse refuses anything longer, so a packet always fits once the previous ones are gone
+    let mut buffer = [0u8; MAX_REQUEST_LENGTH];
+    let mut filled = 0;
+    let mut authenticated = false;
+
+    loop {
+        let len = socket.read(&mut buffer[filled..]).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        filled += len;
+
+        let mut start = 0;
+        while let Some((packet, used)) = RconPacket::parse(&buffer[start..filled])? {
+            start += used;
+            authenticated = answer(socket, packet, authenticated, state).await?;
+        }
+        buffer.copy_within(start..filled, 0);
+        filled -= start;
+
+        socket.flush().await?;
+    }
+}
+
+/// Answers one request, returning whether the connection is authenticated afterwards. A wrong
+/// password ends the connection instead
+async fn answer(
+    socket: &mut TcpSocket<'_>,
+    packet: RconPacket<'_>,
+    authenticated: bool,
+    state: &ServerState,
+) -> Result<bool, MinecraftError> {
+    match packet.kind {
+        TYPE_AUTH => {
+            // Read every time, so a changed password takes over straight away
+            let accepted = {
+                let config = state.config.lock().await;
+                let password = &config.config().rcon_password;
+                // Hashed first, so not even the length is given away
+                !password.is_empty()
+                    && constant_time_eq(&sha256(&[packet.body]), &sha256(&[password.as_bytes()]))
+            };
+            if !accepted {
+                warn!("rcon login with the wrong password");
+                send(socket, AUTH_FAILED, TYPE_AUTH_RESPONSE, b"").await?;
+                socket.flush().await?;
+                Timer::after(FAILED_AUTH_DELAY).await;
+                return Err(MinecraftError::Unauthorized);
+            }
+            send(socket, packet.request_id, TYPE_AUTH_RESPONSE, b"").await?;
+            Ok(true)
+        }
+        TYPE_COMMAND | TYPE_RESPONSE if !authenticated => {
+            send(socket, AUTH_FAILED, TYPE_AUTH_RESPONSE, b"").await?;
+            Ok(false)
+        }
+        TYPE_COMMAND => {
+            let command = String::from_utf8_lossy(packet.body);
+            let command = command.strip_prefix('/').unwrap_or(&command);
+            let lines = dispatch(command, CommandSource::Console, state).await;
+
+            let mut output = String::new();
+            for line in lines {
+                if !output.is_empty() {
+                    output.push('\n');
+                }
+                output.push_str(&strip_formatting(&line));
+            }
+            for fragment in fragments(&output) {
+                send(socket, packet.request_id, TYPE_RESPONSE, fragment.as_bytes()).await?;
+            }
+            Ok(true)
+        }
+        // Clients follow a command with an empty response to find where its fragments end,
+        // echoing it back marks the end
+        TYPE_RESPONSE => {
+            send(socket, packet.request_id, TYPE_RESPONSE, b"").await?;
+            Ok(true)
+        }
+        kind => {
+            warn!("unknown rcon request type {}", kind);
+            send(socket, packet.request_id, TYPE_RESPONSE, b"Unknown request").await?;
+            Ok(authenticated)
+        }
+    }
+}
+
+async fn send(
+    socket: &mut TcpSocket<'_>,
+    request_id: i32,
+    kind: i32,
+    body: &[u8],
+) -> Result<(), MinecraftError> {
+    let bytes = RconPacket { request_id, kind, body }.encode();
+
+    let mut written = 0;
+    while written < bytes.len() {
+        written += socket.write(&bytes[written..]).await?;
+    }
+    Ok(())
+}
+
+/// Drops § colour codes, consoles show plain text
+fn strip_formatting(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -1,45 +1,92 @@
-use core::ptr::addr_of_mut;
+use core::{net::SocketAddr, ptr::addr_of_mut};
 
-use aes::cipher::BlockDecryptMut;
+use alloc::{string::String, vec::Vec};
 use embassy_net::tcp::TcpSocket;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_time::{Duration, with_timeout};
 use log::{info, warn};
 use mcproto_rs::{
-    Deserialize as _,
-    protocol::{Id, PacketDirection, PacketErr, RawPacket as _},
-    types::VarInt,
+    with_allocation_budget,
+    frame::{FrameDecoder, FrameEvent},
+    legacy::LEGACY_PING,
+    protocol::{PacketDirection, PacketErr, RawPacket as _, State},
+    proxy_protocol::{self, ProxyErr, ProxyParse},
     v1_21_8::RawPacket772,
 };
 
 use crate::{
-    encryption::ServerEncryption,
-    errors::MinecraftError, packets::{process_packet, PlayerContext, VAR_INT_BUF_SIZE},
+    encryption::{CipherStream as _, PlatformBackend, ServerEncryption},
+    errors::MinecraftError,
+    forwarding::Forwarding,
+    packets::{
+        answer_legacy_ping, handle_plugin_message, process_packet, send_block_changes,
+        send_disconnect, send_replay, PlayerContext,
+    },
+    session::HttpTransport,
+    storage::{Nvs, Partition, accounts::AccountStore, config::ConfigStore},
+    transfer::TransferTickets,
+    world::{World, events::BlockChanges},
 };
 
 const RX_BUFFER_SIZE: usize = 16384;
 const TX_BUFFER_SIZE: usize = 16384;
-const READ_BUF: usize = 8128;
-const READ_BUF_MAX: usize = READ_BUF * 2;
-const MAX_PACKET_LENGTH: u32 = 1024 * 64;
+// Bytes asked of the socket per read
+const READ_CHUNK: usize = 1024;
+// Packets bigger than this are streamed past rather than buffered, none of the ones we handle are
+const MAX_BUFFERED_PACKET: usize = 8 * 1024;
+pub const MAX_PACKET_LENGTH: u32 = 1024 * 64;
+// What deserializing one packet may allocate, well under the heap so a hostile length prefix
+// becomes an error instead of an allocation failure
+const MAX_PACKET_ALLOCATION: usize = 16 * 1024;
+// Room for the PROXY header, addresses and a few TLVs. Longer ones end the connection
+const MAX_PROXY_HEADER: usize = 256;
+// How often a running replay gets to send more updates while the client is quiet
+const REPLAY_TICK: Duration = Duration::from_millis(50);
 
 static mut RX_BUFFER: [u8; RX_BUFFER_SIZE] = [0; RX_BUFFER_SIZE];
 static mut TX_BUFFER: [u8; TX_BUFFER_SIZE] = [0; TX_BUFFER_SIZE];
 
+/// Everything a connection needs that outlives it
+pub struct ServerState {
+    pub encryption: &'static ServerEncryption<PlatformBackend>,
+    pub world: &'static Mutex<NoopRawMutex, World<Partition>>,
+    /// Changes the connected player didn't make, waiting to be sent to them
+    pub block_changes: BlockChanges,
+    /// Settings changed with `/config`, kept in nvs
+    pub config: &'static Mutex<NoopRawMutex, ConfigStore<Nvs>>,
+    /// Passwords for offline mode
+    pub accounts: &'static Mutex<NoopRawMutex, AccountStore<Nvs>>,
+    /// Verifies logins with the session server, players aren't authenticated without it
+    pub session: Option<HttpTransport>,
+    /// Set when players only join through a proxy, which then vouches for who they are
+    pub forwarding: Option<Forwarding>,
+    /// Signs the cookies players carry when transferred, without it their login stays behind
+    pub transfer_secret: Option<Vec<u8>>,
+    /// Cookies handed to players we sent away, each is accepted back once
+    pub transfer_tickets: TransferTickets,
+    /// Base64 of a favicon sent with `/config favicon append`, set once it's all in
+    pub favicon_upload: Mutex<NoopRawMutex, String>,
+}
+
 #[embassy_executor::task]
 pub async fn start_tcp_server(
     stack: embassy_net::Stack<'static>,
-    encryption: &'static ServerEncryption<'static>,
+    state: &'static ServerState,
 ) {
     loop {
         let mut socket = TcpSocket::new(stack, unsafe { &mut *addr_of_mut!(RX_BUFFER) }, unsafe {
             &mut *addr_of_mut!(TX_BUFFER)
         });
 
-        socket.accept(25565).await.expect("failed to accept socket");
+        // Read every time we listen, a changed port takes over once a connection on the old one
+        // ends, or after a restart
+        let port = state.config.lock().await.config().port;
+        socket.accept(port).await.expect("failed to accept socket");
 
         let remote = socket.remote_endpoint();
         info!("recieved connection from {:?}", remote);
 
-        match handle_connection(socket, encryption).await {
+        match handle_connection(socket, state).await {
             Ok(_) => (),
             Err(err) => warn!("error while handing connection {err:?}"),
         }
@@ -49,107 +96,130 @@ pub async fn start_tcp_server(
 async fn read_socket(
     socket: &mut TcpSocket<'_>,
     context: &mut PlayerContext,
-    buf: &mut [u8],
-    written: &mut usize,
+    decoder: &mut FrameDecoder,
 ) -> Result<(), embassy_net::tcp::Error> {
-    let len = socket.read(&mut buf[*written..]).await?;
-    *written += len;
+    let len = socket.read(decoder.spare(READ_CHUNK)).await?;
     if len == 0 {
         return Err(embassy_net::tcp::Error::ConnectionReset);
     }
-    if let Some(encryption) = &mut context.encryption_context {
-        for block in &mut buf[(*written - len)..(*written)].chunks_mut(1) {
-            encryption.decrypter.decrypt_block_mut(block.into());
+    decoder.commit(len, |data| {
+        if let Some(encryption) = &mut context.encryption_context {
+            encryption.stream.decrypt(data);
         }
-    }
+    });
     Ok(())
 }
 
-async fn read_packet_length(
-    socket: &mut TcpSocket<'_>,
-    context: &mut PlayerContext,
-    read_buf: &mut [u8],
-    read_pointer: &mut usize,
-    write_pointer: &mut usize,
-) -> Result<u32, MinecraftError> {
-    let mut accumulator = 0u32;
-    let mut position = 0;
+pub async fn handle_connection<'a>(
+    mut socket: TcpSocket<'a>,
+    state: &'static ServerState,
+) -> Result<(), MinecraftError> {
+    let mut context = PlayerContext {
+        address: socket.remote_endpoint().map(|endpoint| endpoint.addr.into()),
+        ..PlayerContext::default()
+    };
+
+    state.block_changes.listen();
+    let result = serve_connection(&mut socket, &mut context, state).await;
+    state.block_changes.stop_listening();
+    // Errors end the connection, tell the player why rather than leaving them to time out
+    if let Some(reason) = result.as_ref().err().and_then(MinecraftError::disconnect_reason) {
+        if let Err(err) = send_disconnect(&mut socket, &mut context, &reason).await {
+            warn!("failed to send disconnect: {err:?}");
+        }
+    }
+    result
+}
 
+/// Reads the PROXY header a load balancer sends first, and never any of the bytes after it
+async fn read_proxy_header(socket: &mut TcpSocket<'_>) -> Result<Option<SocketAddr>, MinecraftError> {
+    let mut header = [0; MAX_PROXY_HEADER];
+    let mut read = 0;
     loop {
-        while *read_pointer >= *write_pointer {
-            read_socket(socket, context, read_buf, write_pointer).await?;
-        }
-        let current_byte = read_buf[*read_pointer];
-        accumulator |= (current_byte as u32 & 0x7f) << (position * 7);
-        position += 1;
-        *read_pointer += 1;
-        if (current_byte & 0x80) == 0 {
-            break;
-        }
-        if position >= VAR_INT_BUF_SIZE {
-            return Err(MinecraftError::InvalidPacketHeader);
+        match proxy_protocol::parse(&header[..read])? {
+            ProxyParse::Complete { source, .. } => return Ok(source),
+            ProxyParse::Incomplete(needed) => {
+                let Some(buffer) = header.get_mut(read..read + needed) else {
+                    return Err(ProxyErr::Malformed("header too long").into());
+                };
+                let len = socket.read(buffer).await?;
+                if len == 0 {
+                    return Err(embassy_net::tcp::Error::ConnectionReset.into());
+                }
+                read += len;
+            }
         }
     }
-
-    Ok(accumulator)
 }
 
-pub async fn handle_connection<'a>(
-    mut socket: TcpSocket<'a>,
-    encryption: &'static ServerEncryption<'static>,
+async fn serve_connection(
+    socket: &mut TcpSocket<'_>,
+    context: &mut PlayerContext,
+    state: &'static ServerState,
 ) -> Result<(), MinecraftError> {
-    let mut read_buf = [0u8; READ_BUF_MAX];
-    let mut write_pointer = 0;
-    let mut read_pointer = 0;
+    // Read every connection like the port, a load balancer can be put in front without a restart
+    let proxy_protocol = state.config.lock().await.config().proxy_protocol;
+    if proxy_protocol {
+        if let Some(source) = read_proxy_header(socket).await? {
+            context.address = Some(source.ip());
+        }
+        info!("proxied connection from {:?}", context.address);
+    }
+
+    // Pre-1.7 clients ping with a byte no handshake starts with, answer them and hang up
+    if socket.read_with(|data| (0, data.first() == Some(&LEGACY_PING))).await? {
+        info!("answering legacy ping");
+        return answer_legacy_ping(socket, context, state).await;
+    }
 
-    let mut context = PlayerContext::default();
+    let mut decoder = FrameDecoder::new(PacketDirection::ServerBound)
+        .with_limits(MAX_BUFFERED_PACKET, MAX_PACKET_LENGTH as usize);
 
     loop {
-        if read_pointer >= READ_BUF {
-            read_buf.copy_within(read_pointer..write_pointer, 0);
-            write_pointer -= read_pointer;
-            read_pointer = 0;
-            info!("reset ring buffer");
+        if matches!(context.state, State::Play) {
+            send_block_changes(socket, context, state).await?;
+
+            if context.replay.is_some() {
+                send_replay(socket, context, state).await?;
+                // Only wait on an empty buffer, so no half read packet is ever abandoned
+                if decoder.is_empty()
+                    && with_timeout(REPLAY_TICK, socket.wait_read_ready()).await.is_err()
+                {
+                    continue;
+                }
+            }
         }
 
-        let packet_length = read_packet_length(
-            &mut socket,
-            &mut context,
-            &mut read_buf,
-            &mut read_pointer,
-            &mut write_pointer,
-        )
-        .await?;
-
-        if packet_length > MAX_PACKET_LENGTH {
-            return Err(MinecraftError::InvalidPacketHeader);
-        }
-        if packet_length == 0 {
-            continue;
-        }
+        // Both change between packets, never part way through one
+        decoder.set_state(context.state);
+        decoder.set_compression(context.compression);
 
-        while (write_pointer - read_pointer) < packet_length.try_into().unwrap() {
-            read_socket(&mut socket, &mut context, &mut read_buf, &mut write_pointer).await?;
+        while !decoder.ready()? {
+            read_socket(socket, context, &mut decoder).await?;
         }
 
-        let end = read_pointer + packet_length as usize;
-
-        info!(
-            "reading from {} to {} for size of {}",
-            read_pointer, end, packet_length
-        );
-
-        let packet_id = VarInt::mc_deserialize(&read_buf[read_pointer..end])?;
+        let frame = match decoder.next_event()? {
+            Some(FrameEvent::Frame(frame)) => frame,
+            Some(FrameEvent::LargeStart { id, length }) => {
+                // Nothing we handle is this big, drop it as it comes in
+                warn!("skipping {} byte packet {:?}", length, id);
+                continue;
+            }
+            Some(FrameEvent::Skipped { length }) => {
+                warn!("skipping {} byte compressed packet", length);
+                continue;
+            }
+            Some(FrameEvent::LargeChunk(_) | FrameEvent::LargeEnd) | None => continue,
+        };
 
-        read_pointer += packet_length as usize;
+        info!("read packet {:?} of {} bytes", frame.id, frame.body.len());
 
-        let id = Id {
-            id: *packet_id.value,
-            state: context.state,
-            direction: PacketDirection::ServerBound,
+        // Older clients number some packets differently, everything past here speaks 1.21.8 ids
+        let Some(id) = context.version.to_latest_id(frame.id) else {
+            warn!("unknown packet recieved: {:?}", frame.id);
+            continue;
         };
-
-        let packet = match RawPacket772::create(id, packet_id.data) {
+        let packet = match RawPacket772::create(id, &frame.body) {
             Ok(v) => v,
             Err(PacketErr::UnknownId(id)) => {
                 warn!("unknown packet recieved: {:?}", id);
@@ -157,11 +227,19 @@ pub async fn handle_connection<'a>(
             }
             Err(err) => return Err(err.into()),
         };
-        let packet = packet.deserialize();
+        if let RawPacket772::ServerBoundPluginMessage(body) = &packet {
+            match with_allocation_budget(MAX_PACKET_ALLOCATION, || body.deserialize_borrowed()) {
+                Ok(message) => handle_plugin_message(&message),
+                Err(err) => warn!("failed to read packet: {:?}", err),
+            }
+            continue;
+        }
+
+        let packet = with_allocation_budget(MAX_PACKET_ALLOCATION, || context.version.deserialize(&packet));
         match packet {
             Ok(packet) => {
                 let should_continue =
-                    process_packet(packet, &mut context, &mut socket, &encryption).await?;
+                    process_packet(packet, context, socket, state).await?;
                 if !should_continue {
                     break;
                 }
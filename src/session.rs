@@ -0,0 +1,102 @@
+use core::{net::SocketAddrV4, ptr::addr_of_mut};
+
+use alloc::{format, vec::Vec};
+use embassy_net::{
+    IpAddress,
+    tcp::{ConnectError, Error, TcpSocket},
+};
+use embassy_time::Duration;
+use mcproto_rs::auth::SessionTransport;
+
+// A profile with skin textures is around 2 KiB
+const RX_BUFFER_SIZE: usize = 4096;
+const TX_BUFFER_SIZE: usize = 512;
+const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Only the connection logging in uses these, and there is one connection at a time
+static mut RX_BUFFER: [u8; RX_BUFFER_SIZE] = [0; RX_BUFFER_SIZE];
+static mut TX_BUFFER: [u8; TX_BUFFER_SIZE] = [0; TX_BUFFER_SIZE];
+
+#[derive(Debug)]
+pub enum HttpError {
+    ConnectError(ConnectError),
+    ConnectionError(Error),
+    ResponseTooLong,
+    MalformedResponse,
+}
+
+impl From<ConnectError> for HttpError {
+    fn from(value: ConnectError) -> Self {
+        HttpError::ConnectError(value)
+    }
+}
+
+impl From<Error> for HttpError {
+    fn from(value: Error) -> Self {
+        HttpError::ConnectionError(value)
+    }
+}
+
+/// HTTP/1.0 GETs over embassy-net, so responses are never chunked. The device can't speak TLS to
+/// Mojang's session server, so this talks to a proxy in front of it or a stand-in on the LAN
+pub struct HttpTransport {
+    stack: embassy_net::Stack<'static>,
+    server: SocketAddrV4,
+}
+
+impl HttpTransport {
+    /// `None` if `server` isn't an `ip:port`
+    pub fn new(stack: embassy_net::Stack<'static>, server: &str) -> Option<Self> {
+        Some(Self {
+            stack,
+            server: server.parse().ok()?,
+        })
+    }
+}
+
+impl SessionTransport for HttpTransport {
+    type Error = HttpError;
+
+    async fn get(&self, path: &str) -> Result<(u16, Vec<u8>), HttpError> {
+        let mut socket = TcpSocket::new(self.stack, unsafe { &mut *addr_of_mut!(RX_BUFFER) }, unsafe {
+            &mut *addr_of_mut!(TX_BUFFER)
+        });
+        socket.set_timeout(Some(TIMEOUT));
+        socket
+            .connect((IpAddress::Ipv4(*self.server.ip()), self.server.port()))
+            .await?;
+
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, self.server);
+        let mut written = 0;
+        while written < request.len() {
+            written += socket.write(&request.as_bytes()[written..]).await?;
+        }
+        socket.flush().await?;
+
+        // HTTP/1.0 servers close the connection once the body is sent
+        let mut response = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let len = socket.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            if response.len() + len > MAX_RESPONSE_SIZE {
+                return Err(HttpError::ResponseTooLong);
+            }
+            response.extend_from_slice(&buf[..len]);
+        }
+        socket.close();
+
+        parse_response(response).ok_or(HttpError::MalformedResponse)
+    }
+}
+
+fn parse_response(mut response: Vec<u8>) -> Option<(u16, Vec<u8>)> {
+    let head_len = response.windows(4).position(|window| window == b"\r\n\r\n")?;
+    // "HTTP/1.1 200 OK"
+    let status_line = response[..head_len].split(|b| *b == b'\r').next()?;
+    let status = core::str::from_utf8(status_line.get(9..12)?).ok()?.parse().ok()?;
+    Some((status, response.split_off(head_len + 4)))
+}
@@ -0,0 +1,36 @@
+use blockchain_core::storage::{
+    key::{load_key, store_key},
+    record::{RecordError, RecordStore},
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use rsa::{
+    RsaPrivateKey,
+    pkcs8::{DecodePrivateKey, EncodePrivateKey},
+    rand_core::{CryptoRng, RngCore},
+};
+
+pub const SERVER_KEY_BITS: usize = 1024;
+
+pub fn generate_server_key<R: RngCore + CryptoRng>(rng: &mut R) -> RsaPrivateKey {
+    RsaPrivateKey::new(rng, SERVER_KEY_BITS).expect("failed to generate private key")
+}
+
+fn encode(key: &RsaPrivateKey) -> alloc::vec::Vec<u8> {
+    key.to_pkcs8_der().expect("failed to encode private key").as_bytes().to_vec()
+}
+
+/// Saves the key as PKCS#8 DER
+pub fn store_server_key<F: NorFlash + ReadNorFlash>(
+    store: &mut RecordStore<F>,
+    key: &RsaPrivateKey,
+) -> Result<(), RecordError<F::Error>> {
+    store_key(store, &encode(key))
+}
+
+/// Loads the stored key, or generates and stores a new one if there isn't a usable one
+pub fn load_server_key<F: NorFlash + ReadNorFlash, R: RngCore + CryptoRng>(
+    store: &mut RecordStore<F>,
+    rng: &mut R,
+) -> RsaPrivateKey {
+    load_key(store, RsaPrivateKey::from_pkcs8_der, encode, || generate_server_key(rng))
+}
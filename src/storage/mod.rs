@@ -0,0 +1,123 @@
+use core::{cell::RefCell, ptr::addr_of_mut};
+
+use alloc::boxed::Box;
+use embassy_sync::blocking_mutex::{Mutex, raw::NoopRawMutex};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use esp_bootloader_esp_idf::partitions::{
+    DataPartitionSubType, FlashRegion, PartitionTable, PartitionType,
+};
+use esp_storage::FlashStorage;
+use static_cell::StaticCell;
+
+pub use blockchain_core::storage::{accounts, config, record};
+
+pub mod key;
+
+static TABLE_FLASH_STORAGE: StaticCell<FlashStorage> = StaticCell::new();
+static mut FLASH_BUFFER: [u8; esp_bootloader_esp_idf::partitions::PARTITION_TABLE_MAX_LEN] = [0; _];
+
+/// Where each record's two copies live in the `nvs` partition. Records are erased on every write,
+/// so each copy gets its own flash sector. The first copies are where the only ones used to be
+pub const SERVER_KEY_RECORD: [u32; 2] = [0x0000, 0x3000];
+pub const ACCOUNTS_RECORD: [u32; 2] = [0x1000, 0x4000];
+pub const CONFIG_RECORD: [u32; 2] = [0x2000, 0x5000];
+pub const RECORD_SIZE: u32 = 0x1000;
+
+pub type Partition = FlashRegion<'static, FlashStorage>;
+pub type PartitionRecordError = record::RecordError<<Partition as ErrorType>::Error>;
+/// The `nvs` partition, opened once and shared by every record in it
+pub type Nvs = SharedFlash<'static, Partition>;
+
+/// Several owners of one flash handle. Flash calls never await, so each one borrows it for just
+/// that call
+pub struct SharedFlash<'a, F>(&'a Mutex<NoopRawMutex, RefCell<F>>);
+
+impl<'a, F> SharedFlash<'a, F> {
+    pub fn new(flash: &'a Mutex<NoopRawMutex, RefCell<F>>) -> Self {
+        Self(flash)
+    }
+}
+
+impl<F> Clone for SharedFlash<'_, F> {
+    fn clone(&self) -> Self {
+        Self(self.0)
+    }
+}
+
+impl<F: ErrorType> ErrorType for SharedFlash<'_, F> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash> ReadNorFlash for SharedFlash<'_, F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().read(offset, bytes))
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.lock(|flash| flash.borrow().capacity())
+    }
+}
+
+impl<F: NorFlash> NorFlash for SharedFlash<'_, F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().erase(from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.lock(|flash| flash.borrow_mut().write(offset, bytes))
+    }
+}
+
+pub struct OpenPartitions {
+    pub world: Partition,
+    pub world_size: u32,
+    /// Every record lives in here, share it between them with `SharedFlash`
+    pub nvs: Partition,
+}
+
+/// The partition table, read once at boot and used to open the partitions we store things in
+pub struct Partitions {
+    table: PartitionTable<'static>,
+}
+
+impl Partitions {
+    /// Must only be called once, the table is read into a static buffer
+    pub fn read() -> Self {
+        let storage = TABLE_FLASH_STORAGE.init(FlashStorage::new());
+        let table = esp_bootloader_esp_idf::partitions::read_partition_table(storage, unsafe {
+            &mut *addr_of_mut!(FLASH_BUFFER)
+        })
+        .expect("failed to fetch partition table");
+
+        Self { table }
+    }
+
+    fn open(&self, kind: PartitionType) -> Option<(Partition, u32)> {
+        let entry = self.table.find_partition(kind).expect("failed to search pt")?;
+        let length = entry.len();
+        let entry = Box::leak(Box::new(entry));
+        let storage = Box::leak(Box::new(FlashStorage::new()));
+        Some((entry.as_embedded_storage(storage), length))
+    }
+
+    /// Opens everything we store things in. Each partition gets its own handle on the flash
+    /// that lives for the rest of the program, taking `self` makes sure that only happens once
+    pub fn open_all(self) -> OpenPartitions {
+        let (world, world_size) = self
+            .open(PartitionType::Data(DataPartitionSubType::Undefined))
+            .expect("failed to find world data");
+        let (nvs, _) = self
+            .open(PartitionType::Data(DataPartitionSubType::Nvs))
+            .expect("failed to find nvs partition");
+        OpenPartitions {
+            world,
+            world_size,
+            nvs,
+        }
+    }
+}
@@ -0,0 +1 @@
+pub use blockchain_core::transfer::{TRANSFER_COOKIE, TransferState, TransferTickets};
@@ -19,7 +19,7 @@ pub struct SliceSerializer<'a> {
 impl<'a> Serializer for SliceSerializer<'a> {
     fn serialize_bytes(&mut self, data: &[u8]) -> SerializeResult {
         let end_at = self.at + data.len();
-        if end_at >= self.target.len() {
+        if end_at > self.target.len() {
             panic!(
                 "cannot fit data in slice ({} exceeds length {} at {})",
                 data.len(),
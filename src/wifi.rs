@@ -1,13 +1,12 @@
 use embassy_net::Runner;
 use embassy_time::{Duration, Timer};
 use esp_wifi::wifi::{AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState};
+use alloc::string::String;
 use log::{info, warn};
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
-
+/// Keeps the device on the network from the config, changes to it apply after a restart
 #[embassy_executor::task]
-pub async fn maintain_wifi_connection(mut controller: WifiController<'static>) {
+pub async fn maintain_wifi_connection(mut controller: WifiController<'static>, ssid: String, password: String) {
     loop {
         match esp_wifi::wifi::wifi_state() {
             WifiState::StaDisconnected => {
@@ -19,8 +18,8 @@ pub async fn maintain_wifi_connection(mut controller: WifiController<'static>) {
         }
         if !matches!(controller.is_started(), Ok(true)) {
             let client_config = Configuration::Client(ClientConfiguration {
-                ssid: SSID.into(),
-                password: PASSWORD.into(),
+                ssid: ssid.as_str().into(),
+                password: password.as_str().into(),
                 ..Default::default()
             });
             controller